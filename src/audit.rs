@@ -0,0 +1,128 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// append only audit log of key operations. Every entry chains in the hash of the one before,
+// a modified entry breaks the chain and a truncated log no longer reaches the head kept aside
+// (or handed to the counter party) when the entry was appended.
+//
+// Key gen (set_master_key), get_child, rotation and signing take the log and append their own
+// record, a key made or used through them is always recorded. The unrecorded derive_child is
+// private to the two party module, which records the signing done with its child keys.
+
+use curv::BigInt;
+use sha2::{Digest, Sha256};
+use util::hash_length_prefixed;
+use AuditError::{self, Modified, Truncated};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Operation {
+    KeyGen,
+    GetChild,
+    Rotation,
+    Sign,
+}
+
+// produced by the master keys, see audit_record
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AuditRecord {
+    pub operation: Operation,
+    pub key_fingerprint: BigInt,
+    pub epoch: u32,
+    // the digest signed, only for Sign
+    pub message_hash: Option<BigInt>,
+    pub session_id: BigInt,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AuditEntry {
+    pub record: AuditRecord,
+    // hash of the entry before, zero for the first one
+    pub previous: BigInt,
+}
+
+// length and last hash of a log, enough to detect truncation later
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AuditHead {
+    pub length: usize,
+    pub hash: BigInt,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+}
+
+impl AuditEntry {
+    pub fn hash(&self) -> BigInt {
+        let record = &self.record;
+        let mut hasher = Sha256::new();
+        hash_length_prefixed(&mut hasher, &self.previous);
+        hasher.input(&[record.operation as u8]);
+        hash_length_prefixed(&mut hasher, &record.key_fingerprint);
+        hasher.input(&record.epoch.to_be_bytes());
+        // a zero digest differs from none
+        match record.message_hash {
+            Some(ref message_hash) => {
+                hasher.input(&[1]);
+                hash_length_prefixed(&mut hasher, message_hash);
+            }
+            None => hasher.input(&[0]),
+        }
+        hash_length_prefixed(&mut hasher, &record.session_id);
+        BigInt::from(&hasher.result()[..])
+    }
+}
+
+impl AuditLog {
+    pub fn append(&mut self, record: AuditRecord) -> AuditHead {
+        let previous = self.head().hash;
+        self.entries.push(AuditEntry { record, previous });
+        self.head()
+    }
+
+    pub fn head(&self) -> AuditHead {
+        AuditHead {
+            length: self.entries.len(),
+            hash: self
+                .entries
+                .last()
+                .map_or(BigInt::from(0), |entry| entry.hash()),
+        }
+    }
+
+    // the log must chain up from the start and contain the entry a head was taken at
+    pub fn verify(&self, head: &AuditHead) -> Result<(), AuditError> {
+        let mut previous = BigInt::from(0);
+        for entry in &self.entries {
+            if entry.previous != previous {
+                return Err(Modified);
+            }
+            previous = entry.hash();
+        }
+        if self.entries.len() < head.length {
+            return Err(Truncated);
+        }
+        let at_head = match head.length {
+            0 => BigInt::from(0),
+            length => self.entries[length - 1].hash(),
+        };
+        if at_head == head.hash {
+            Ok(())
+        } else {
+            Err(Modified)
+        }
+    }
+}
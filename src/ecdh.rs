@@ -0,0 +1,148 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// building blocks of the two party ecdh: a party multiplies a point by its share and proves
+// the result has the same dlog as its public share, so the counter party can combine it
+// without trusting it. The ecies helpers work on the combined shared point.
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use sha2::{Digest, Sha256};
+use util::compressed;
+use EcdhError;
+
+// chaum pedersen proof that point1 = x * base1 and point2 = x * base2:
+// z = r + e * x with e = H(base1, point1, base2, point2, a1, a2)
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DleqProof {
+    pub a1: GE,
+    pub a2: GE,
+    pub z: FE,
+}
+
+// share * base and the proof it was computed with the share behind a public share
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PartialEcdh {
+    pub point: GE,
+    pub proof: DleqProof,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct EciesCiphertext {
+    pub ephemeral: GE,
+    // aes-256-gcm, the tag is appended
+    pub ciphertext: Vec<u8>,
+}
+
+fn dleq_challenge(base1: &GE, point1: &GE, base2: &GE, point2: &GE, a1: &GE, a2: &GE) -> FE {
+    let e = HSha256::create_hash(&[
+        &base1.bytes_compressed_to_big_int(),
+        &point1.bytes_compressed_to_big_int(),
+        &base2.bytes_compressed_to_big_int(),
+        &point2.bytes_compressed_to_big_int(),
+        &a1.bytes_compressed_to_big_int(),
+        &a2.bytes_compressed_to_big_int(),
+    ]);
+    ECScalar::from(&e)
+}
+
+impl DleqProof {
+    pub fn prove(secret: &FE, base1: &GE, base2: &GE) -> DleqProof {
+        let r: FE = ECScalar::new_random();
+        let a1 = base1 * &r;
+        let a2 = base2 * &r;
+        let e = dleq_challenge(base1, &(base1 * secret), base2, &(base2 * secret), &a1, &a2);
+        DleqProof {
+            a1,
+            a2,
+            z: r + &(e * secret),
+        }
+    }
+
+    pub fn verify(
+        &self,
+        base1: &GE,
+        point1: &GE,
+        base2: &GE,
+        point2: &GE,
+    ) -> Result<(), EcdhError> {
+        let e = dleq_challenge(base1, point1, base2, point2, &self.a1, &self.a2);
+        if base1 * &self.z == &self.a1 + &(point1 * &e)
+            && base2 * &self.z == &self.a2 + &(point2 * &e)
+        {
+            Ok(())
+        } else {
+            Err(EcdhError::ProofInvalid)
+        }
+    }
+}
+
+impl PartialEcdh {
+    pub fn compute(secret_share: &FE, base: &GE) -> PartialEcdh {
+        let g: GE = ECPoint::generator();
+        PartialEcdh {
+            point: base * secret_share,
+            proof: DleqProof::prove(secret_share, &g, base),
+        }
+    }
+
+    pub fn verify(&self, public_share: &GE, base: &GE) -> Result<(), EcdhError> {
+        let g: GE = ECPoint::generator();
+        self.proof.verify(&g, public_share, base, &self.point)
+    }
+}
+
+// ansi x9.63 kdf with sha256 over the x coordinate of the shared point, the ephemeral point is
+// the shared info. The first block is the aes key, the second gives the nonce.
+fn ecies_key(shared: &GE, ephemeral: &GE) -> (Vec<u8>, Vec<u8>) {
+    let z = &compressed(shared)[1..33];
+    let ephemeral_bytes = compressed(ephemeral);
+    let block = |counter: u32| {
+        let mut hasher = Sha256::new();
+        hasher.input(z);
+        hasher.input(&counter.to_be_bytes());
+        hasher.input(&ephemeral_bytes[..]);
+        hasher.result().to_vec()
+    };
+    (block(1), block(2)[..12].to_vec())
+}
+
+// anyone can encrypt to the joint public key
+pub fn ecies_encrypt(q: &GE, plaintext: &[u8]) -> Result<EciesCiphertext, EcdhError> {
+    let g: GE = ECPoint::generator();
+    let k: FE = ECScalar::new_random();
+    let ephemeral = &g * &k;
+    let (key, nonce) = ecies_key(&(q * &k), &ephemeral);
+    let ciphertext = Aes256Gcm::new(Key::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .or(Err(EcdhError::Encryption))?;
+    Ok(EciesCiphertext {
+        ephemeral,
+        ciphertext,
+    })
+}
+
+// shared is the output of the two party ecdh run on the ephemeral point of the ciphertext
+pub fn ecies_decrypt(ciphertext: &EciesCiphertext, shared: &GE) -> Result<Vec<u8>, EcdhError> {
+    let (key, nonce) = ecies_key(shared, &ciphertext.ephemeral);
+    Aes256Gcm::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(&nonce), &ciphertext.ciphertext[..])
+        .or(Err(EcdhError::Decryption))
+}
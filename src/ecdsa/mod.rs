@@ -15,3 +15,4 @@
 */
 
 pub mod two_party;
+pub mod multi_party;
@@ -0,0 +1,80 @@
+/*
+    KMS-ECDSA
+    Copyright 2018 by Kzen Networks
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use curv::BigInt;
+use ecdsa::two_party::hd_key;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{
+    Keys, Parameters, SharedKeys,
+};
+use paillier::EncryptionKey;
+
+// t-of-n wrapper around gg18. Unlike the two party case all parties play the same role,
+// so there is a single master key type.
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultiPartyPublic {
+    pub q: GE,
+    // x_i * G for every party, by party index
+    pub pub_shares: Vec<GE>,
+    pub paillier_pub_vec: Vec<EncryptionKey>,
+    // only the sharing parameters are used (lagrange coefficients when signing)
+    pub vss_scheme: VerifiableSS<GE>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MasterKey {
+    pub public: MultiPartyPublic,
+    pub params: Parameters,
+    keys: Keys,
+    shared_keys: SharedKeys,
+    chain_code: BigInt,
+}
+
+pub mod party_i;
+mod test;
+
+impl MasterKey {
+    pub fn party_index(&self) -> usize {
+        self.keys.party_index
+    }
+
+    // same derivation as the two party ecdsa keys: q and every share are multiplied by f_l
+    pub fn get_child(&self, location_in_hir: Vec<BigInt>) -> MasterKey {
+        let (public_key_new_child, f_l_new, cc_new) =
+            hd_key(location_in_hir, &self.public.q, &self.chain_code);
+
+        let public = MultiPartyPublic {
+            q: public_key_new_child.clone(),
+            pub_shares: self
+                .public
+                .pub_shares
+                .iter()
+                .map(|pub_share| pub_share * &f_l_new)
+                .collect(),
+            paillier_pub_vec: self.public.paillier_pub_vec.clone(),
+            vss_scheme: self.public.vss_scheme.clone(),
+        };
+        MasterKey {
+            public,
+            params: self.params.clone(),
+            keys: self.keys.clone(),
+            shared_keys: SharedKeys {
+                y: public_key_new_child,
+                x_i: self.shared_keys.x_i.clone() * &f_l_new,
+            },
+            chain_code: cc_new.bytes_compressed_to_big_int(),
+        }
+    }
+}
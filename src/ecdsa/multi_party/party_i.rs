@@ -0,0 +1,756 @@
+/*
+    KMS-ECDSA
+    Copyright 2018 by Kzen Networks
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+use curv::arithmetic::traits::{Converter, Modulo, Samplable};
+use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
+use curv::cryptographic_primitives::commitments::traits::Commitment;
+use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::HomoELGamalProof;
+use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::BigInt;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{
+    verify, KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, LocalSignature, Parameters,
+    PartyPrivate, Phase5ADecom1, Phase5Com1, Phase5Com2, Phase5DDecom2, SharedKeys,
+    SignBroadcastPhase1, SignDecommitPhase1, SignKeys, SignatureRecid,
+};
+use multi_party_ecdsa::utilities::mta::{MessageA, MessageB};
+use sha2::{Digest, Sha256};
+use util::compressed;
+
+use super::{MasterKey, MultiPartyPublic};
+use Errors::{self, KeyGenError, RefreshError, SignError};
+
+// Messages are exchanged in rounds. Broadcast inputs are slices with the message of every
+// participant including our own, ordered by party index (key gen, refresh) or by position in
+// the signers list (sign). Point to point outputs and inputs follow the same order and skip
+// our own slot, except for the key gen and refresh shares where a party keeps a share for itself.
+
+const CHAIN_CODE_BLIND_BITS: usize = 256;
+
+pub struct KeyGen {
+    params: Parameters,
+    keys: Keys,
+    decom: KeyGenDecommitMessage1,
+    cc_share: FE,
+    cc_blind: BigInt,
+    pub first_message: KeyGenMessage1,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyGenMessage1 {
+    pub bc: KeyGenBroadcastMessage1,
+    pub cc_com: BigInt,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyGenMessage2 {
+    pub decom: KeyGenDecommitMessage1,
+    pub cc_share: FE,
+    pub cc_blind: BigInt,
+}
+
+// point to point, must be sent over a private channel
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyGenMessage3 {
+    pub vss_scheme: VerifiableSS<GE>,
+    pub secret_share: FE,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyGenMessage4 {
+    pub dlog_proof: DLogProof<GE>,
+}
+
+pub struct Sign {
+    signers: Vec<usize>,
+    sign_keys: SignKeys,
+    decom: SignDecommitPhase1,
+    beta_vec: Vec<FE>,
+    ni_vec: Vec<FE>,
+    b_proof_vec: Vec<DLogProof<GE>>,
+    sigma_i: Option<FE>,
+    r: Option<GE>,
+    local_sig: Option<LocalSignature>,
+    phase5_first_message: Option<SignMessage5>,
+    phase5_decom2: Option<Phase5DDecom2>,
+    pub first_message: SignMessage1,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessage1 {
+    pub com: SignBroadcastPhase1,
+    pub m_a: MessageA,
+}
+
+// point to point
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessage2 {
+    pub m_b_gamma: MessageB,
+    pub m_b_w: MessageB,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessage3 {
+    pub delta_i: FE,
+    pub decom: SignDecommitPhase1,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessage4 {
+    pub com: Phase5Com1,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessage5 {
+    pub decom: Phase5ADecom1,
+    pub elgamal_proof: HomoELGamalProof<GE>,
+    pub dlog_proof_rho: DLogProof<GE>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessage6 {
+    pub com: Phase5Com2,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessage7 {
+    pub decom: Phase5DDecom2,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SignMessage8 {
+    pub s_i: FE,
+}
+
+// commitments to the zero sharing of the dealer, broadcast so every party checks its share
+// against the same polynomial
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RefreshMessage1 {
+    pub commitments: Vec<GE>,
+}
+
+// point to point, must be sent over a private channel
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RefreshMessage2 {
+    pub share: FE,
+}
+
+// echo of the commitments received in the first round. A dealer that sent different
+// commitments to different parties shows up as a mismatch and the refresh is aborted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RefreshMessage3 {
+    pub commitments_hash: BigInt,
+}
+
+impl KeyGen {
+    pub fn first_message(params: &Parameters, party_index: usize) -> KeyGen {
+        let keys = Keys::create(party_index);
+        let (bc, decom) = keys.phase1_broadcast_phase3_proof_of_correct_key();
+        let cc_share: FE = ECScalar::new_random();
+        let cc_blind = BigInt::sample(CHAIN_CODE_BLIND_BITS);
+        let cc_com = HashCommitment::create_commitment_with_user_defined_randomness(
+            &cc_share.to_big_int(),
+            &cc_blind,
+        );
+        KeyGen {
+            params: params.clone(),
+            keys,
+            decom,
+            cc_share,
+            cc_blind,
+            first_message: KeyGenMessage1 { bc, cc_com },
+        }
+    }
+
+    // sent once the first message of every party was received
+    pub fn second_message(&self) -> KeyGenMessage2 {
+        KeyGenMessage2 {
+            decom: self.decom.clone(),
+            cc_share: self.cc_share.clone(),
+            cc_blind: self.cc_blind.clone(),
+        }
+    }
+
+    // verifies the decommitments and deals the shares of our secret
+    pub fn third_message(
+        &self,
+        received_message1: &[KeyGenMessage1],
+        received_message2: &[KeyGenMessage2],
+    ) -> Result<Vec<KeyGenMessage3>, Errors> {
+        let cc_verify =
+            received_message1
+                .iter()
+                .zip(received_message2.iter())
+                .all(|(message1, message2)| {
+                    HashCommitment::create_commitment_with_user_defined_randomness(
+                        &message2.cc_share.to_big_int(),
+                        &message2.cc_blind,
+                    ) == message1.cc_com
+                });
+        if !cc_verify {
+            return Err(KeyGenError);
+        }
+        let bc1_vec = received_message1
+            .iter()
+            .map(|message| message.bc.clone())
+            .collect::<Vec<KeyGenBroadcastMessage1>>();
+        let decom_vec = received_message2
+            .iter()
+            .map(|message| message.decom.clone())
+            .collect::<Vec<KeyGenDecommitMessage1>>();
+        let (vss_scheme, secret_shares, _index) = self
+            .keys
+            .phase1_verify_com_phase3_verify_correct_key_phase2_distribute(
+                &self.params,
+                &decom_vec,
+                &bc1_vec,
+            )
+            .or(Err(KeyGenError))?;
+        Ok(secret_shares
+            .into_iter()
+            .map(|secret_share| KeyGenMessage3 {
+                vss_scheme: vss_scheme.clone(),
+                secret_share,
+            })
+            .collect())
+    }
+
+    // verifies the received shares, computes our share of the joint key and proves knowledge of it
+    pub fn fourth_message(
+        &self,
+        received_message2: &[KeyGenMessage2],
+        received_message3: &[KeyGenMessage3],
+    ) -> Result<(SharedKeys, KeyGenMessage4), Errors> {
+        let y_vec = received_message2
+            .iter()
+            .map(|message| message.decom.y_i.clone())
+            .collect::<Vec<GE>>();
+        let secret_shares_vec = received_message3
+            .iter()
+            .map(|message| message.secret_share.clone())
+            .collect::<Vec<FE>>();
+        let vss_scheme_vec = received_message3
+            .iter()
+            .map(|message| message.vss_scheme.clone())
+            .collect::<Vec<VerifiableSS<GE>>>();
+        let (shared_keys, dlog_proof) = self
+            .keys
+            .phase2_verify_vss_construct_keypair_phase3_pok_dlog(
+                &self.params,
+                &y_vec,
+                &secret_shares_vec,
+                &vss_scheme_vec,
+                &(self.keys.party_index + 1),
+            )
+            .or(Err(KeyGenError))?;
+        Ok((shared_keys, KeyGenMessage4 { dlog_proof }))
+    }
+}
+
+impl MasterKey {
+    pub fn set_master_key(
+        local_key_gen: &KeyGen,
+        shared_keys: SharedKeys,
+        received_message1: &[KeyGenMessage1],
+        received_message2: &[KeyGenMessage2],
+        received_message3: &[KeyGenMessage3],
+        received_message4: &[KeyGenMessage4],
+    ) -> Result<MasterKey, Errors> {
+        let y_vec = received_message2
+            .iter()
+            .map(|message| message.decom.y_i.clone())
+            .collect::<Vec<GE>>();
+        let dlog_proofs = received_message4
+            .iter()
+            .map(|message| message.dlog_proof.clone())
+            .collect::<Vec<DLogProof<GE>>>();
+        Keys::verify_dlog_proofs(&local_key_gen.params, &dlog_proofs, &y_vec)
+            .or(Err(KeyGenError))?;
+
+        // the proven public shares must match the ones committed to in the vss schemes
+        let vss_scheme_vec = received_message3
+            .iter()
+            .map(|message| message.vss_scheme.clone())
+            .collect::<Vec<VerifiableSS<GE>>>();
+        let pub_shares = Keys::get_commitments_to_xi(&vss_scheme_vec);
+        let pub_shares_verify = pub_shares
+            .iter()
+            .zip(dlog_proofs.iter())
+            .all(|(pub_share, dlog_proof)| pub_share == &dlog_proof.pk);
+        if !pub_shares_verify {
+            return Err(KeyGenError);
+        }
+
+        let g: GE = ECPoint::generator();
+        let cc_sum = received_message2
+            .iter()
+            .skip(1)
+            .fold(received_message2[0].cc_share.clone(), |acc, message| {
+                acc + &message.cc_share
+            });
+        let chain_code = (&g * &cc_sum).bytes_compressed_to_big_int();
+
+        let party_index = local_key_gen.keys.party_index;
+        Ok(MasterKey {
+            public: MultiPartyPublic {
+                q: shared_keys.y.clone(),
+                pub_shares,
+                paillier_pub_vec: received_message1
+                    .iter()
+                    .map(|message| message.bc.e.clone())
+                    .collect(),
+                vss_scheme: vss_scheme_vec[party_index].clone(),
+            },
+            params: local_key_gen.params.clone(),
+            keys: local_key_gen.keys.clone(),
+            shared_keys,
+            chain_code,
+        })
+    }
+
+    // signers are the party indices of the t + 1 participants, in the same order for everyone
+    pub fn sign_first_message(&self, signers: &[usize]) -> Result<Sign, Errors> {
+        self.check_signers(signers)?;
+        let private = PartyPrivate::set_private(self.keys.clone(), self.shared_keys.clone());
+        let sign_keys = SignKeys::create(
+            &private,
+            &self.public.vss_scheme,
+            self.keys.party_index,
+            signers,
+        );
+        let (com, decom) = sign_keys.phase1_broadcast();
+        let (m_a, _) = MessageA::a(&sign_keys.k_i, &self.keys.ek);
+        Ok(Sign {
+            signers: signers.to_vec(),
+            sign_keys,
+            decom,
+            beta_vec: Vec::new(),
+            ni_vec: Vec::new(),
+            b_proof_vec: Vec::new(),
+            sigma_i: None,
+            r: None,
+            local_sig: None,
+            phase5_first_message: None,
+            phase5_decom2: None,
+            first_message: SignMessage1 { com, m_a },
+        })
+    }
+
+    // at least t + 1 distinct parties of the key, we among them
+    fn check_signers(&self, signers: &[usize]) -> Result<(), Errors> {
+        let share_count = self.params.share_count as usize;
+        let mut sorted = signers.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        if sorted.len() != signers.len()
+            || signers.len() <= self.params.threshold as usize
+            || signers
+                .iter()
+                .any(|party_index| *party_index >= share_count)
+            || !signers.contains(&self.party_index())
+        {
+            return Err(SignError);
+        }
+        Ok(())
+    }
+
+    // new shares of the same key: every party deals a sharing of zero and adds up what it receives.
+    // the commitments are broadcast, the shares go out one per party, our own included.
+    pub fn refresh_first_message(&self) -> (RefreshMessage1, Vec<RefreshMessage2>) {
+        let g: GE = ECPoint::generator();
+        let q = FE::q();
+        let coefficients = (0..self.params.threshold)
+            .map(|_| BigInt::sample_below(&q))
+            .collect::<Vec<BigInt>>();
+        let commitments = coefficients
+            .iter()
+            .map(|coefficient| {
+                let coefficient_fe: FE = ECScalar::from(coefficient);
+                &g * &coefficient_fe
+            })
+            .collect::<Vec<GE>>();
+        let shares = (0..self.params.share_count as usize)
+            .map(|party_index| {
+                // polynomial without constant term, evaluated at party_index + 1
+                let point = BigInt::from((party_index + 1) as i32);
+                let share = coefficients.iter().rev().fold(BigInt::from(0), |acc, a| {
+                    BigInt::mod_mul(&BigInt::mod_add(&acc, a, &q), &point, &q)
+                });
+                RefreshMessage2 {
+                    share: ECScalar::from(&share),
+                }
+            })
+            .collect();
+        (RefreshMessage1 { commitments }, shares)
+    }
+
+    // sent once the first message of every party was received
+    pub fn refresh_second_message(
+        &self,
+        received_message1: &[RefreshMessage1],
+    ) -> Result<RefreshMessage3, Errors> {
+        self.check_refresh_commitments(received_message1)?;
+        Ok(RefreshMessage3 {
+            commitments_hash: refresh_commitments_hash(received_message1),
+        })
+    }
+
+    // the current key is left as is, it can be dropped once every party refreshed
+    pub fn refresh_third_message(
+        &self,
+        received_message1: &[RefreshMessage1],
+        received_message2: &[RefreshMessage2],
+        received_message3: &[RefreshMessage3],
+    ) -> Result<MasterKey, Errors> {
+        self.check_refresh_commitments(received_message1)?;
+        let commitments_hash = refresh_commitments_hash(received_message1);
+        if received_message2.len() != received_message1.len()
+            || received_message3.len() != received_message1.len()
+            || received_message3
+                .iter()
+                .any(|message| message.commitments_hash != commitments_hash)
+        {
+            return Err(RefreshError);
+        }
+
+        let g: GE = ECPoint::generator();
+        let party_index = self.keys.party_index;
+        for (message1, message2) in received_message1.iter().zip(received_message2.iter()) {
+            let expected = zero_sharing_commitment(&message1.commitments, party_index)?;
+            if &g * &message2.share != expected {
+                return Err(RefreshError);
+            }
+        }
+
+        let x_i = received_message2
+            .iter()
+            .fold(self.shared_keys.x_i.clone(), |acc, message| {
+                acc + &message.share
+            });
+        let mut pub_shares = Vec::new();
+        for (index, pub_share) in self.public.pub_shares.iter().enumerate() {
+            let mut pub_share = pub_share.clone();
+            for message in received_message1 {
+                pub_share = pub_share + zero_sharing_commitment(&message.commitments, index)?;
+            }
+            pub_shares.push(pub_share);
+        }
+        if &g * &x_i != pub_shares[party_index] {
+            return Err(RefreshError);
+        }
+
+        Ok(MasterKey {
+            public: MultiPartyPublic {
+                q: self.public.q.clone(),
+                pub_shares,
+                paillier_pub_vec: self.public.paillier_pub_vec.clone(),
+                vss_scheme: self.public.vss_scheme.clone(),
+            },
+            params: self.params.clone(),
+            keys: self.keys.clone(),
+            shared_keys: SharedKeys {
+                y: self.shared_keys.y.clone(),
+                x_i,
+            },
+            chain_code: self.chain_code.clone(),
+        })
+    }
+
+    // one dealing per party, each of threshold commitments
+    fn check_refresh_commitments(
+        &self,
+        received_message1: &[RefreshMessage1],
+    ) -> Result<(), Errors> {
+        if received_message1.len() != self.params.share_count as usize
+            || received_message1
+                .iter()
+                .any(|message| message.commitments.len() != self.params.threshold as usize)
+        {
+            Err(RefreshError)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Sign {
+    // MtA with every other signer on k_i * gamma_j and k_i * w_j
+    pub fn second_message(
+        &mut self,
+        master_key: &MasterKey,
+        received_message1: &[SignMessage1],
+    ) -> Result<Vec<SignMessage2>, Errors> {
+        self.check_broadcast(received_message1)?;
+        let mut sign_messages = Vec::new();
+        for (position, party_index) in self.signers.clone().iter().enumerate() {
+            if *party_index == master_key.party_index() {
+                continue;
+            }
+            let ek = master_key
+                .public
+                .paillier_pub_vec
+                .get(*party_index)
+                .ok_or(SignError)?;
+            let m_a = &received_message1[position].m_a;
+            let (m_b_gamma, beta_gamma, _, _) =
+                MessageB::b(&self.sign_keys.gamma_i, ek, m_a.clone());
+            let (m_b_w, beta_w, _, _) = MessageB::b(&self.sign_keys.w_i, ek, m_a.clone());
+            self.beta_vec.push(beta_gamma);
+            self.ni_vec.push(beta_w);
+            sign_messages.push(SignMessage2 { m_b_gamma, m_b_w });
+        }
+        Ok(sign_messages)
+    }
+
+    pub fn third_message(
+        &mut self,
+        master_key: &MasterKey,
+        received_message2: &[SignMessage2],
+    ) -> Result<SignMessage3, Errors> {
+        if received_message2.len() + 1 != self.signers.len() {
+            return Err(SignError);
+        }
+        let others = self
+            .signers
+            .iter()
+            .filter(|party_index| **party_index != master_key.party_index())
+            .cloned()
+            .collect::<Vec<usize>>();
+        let mut alpha_vec = Vec::new();
+        let mut miu_vec = Vec::new();
+        for (party_index, message) in others.iter().zip(received_message2.iter()) {
+            let (alpha, _) = message
+                .m_b_gamma
+                .verify_proofs_get_alpha(&master_key.keys.dk, &self.sign_keys.k_i)
+                .or(Err(SignError))?;
+            let (miu, _) = message
+                .m_b_w
+                .verify_proofs_get_alpha(&master_key.keys.dk, &self.sign_keys.k_i)
+                .or(Err(SignError))?;
+            // w_j used in the MtA must be the lagrange weighted share of party j
+            let pub_share = master_key
+                .public
+                .pub_shares
+                .get(*party_index)
+                .ok_or(SignError)?;
+            let g_w_j = Keys::update_commitments_to_xi(
+                pub_share,
+                &master_key.public.vss_scheme,
+                *party_index,
+                &self.signers,
+            );
+            if message.m_b_w.b_proof.pk != g_w_j {
+                return Err(SignError);
+            }
+            alpha_vec.push(alpha);
+            miu_vec.push(miu);
+            self.b_proof_vec.push(message.m_b_gamma.b_proof.clone());
+        }
+        let delta_i = self.sign_keys.phase2_delta_i(&alpha_vec, &self.beta_vec);
+        self.sigma_i = Some(self.sign_keys.phase2_sigma_i(&miu_vec, &self.ni_vec));
+        Ok(SignMessage3 {
+            delta_i,
+            decom: self.decom.clone(),
+        })
+    }
+
+    pub fn fourth_message(
+        &mut self,
+        master_key: &MasterKey,
+        message: &BigInt,
+        received_message1: &[SignMessage1],
+        received_message3: &[SignMessage3],
+    ) -> Result<SignMessage4, Errors> {
+        let position = self.position(master_key)?;
+        self.check_broadcast(received_message1)?;
+        self.check_broadcast(received_message3)?;
+        let sigma_i = self.sigma_i.clone().ok_or(SignError)?;
+        let delta_vec = received_message3
+            .iter()
+            .map(|received| received.delta_i.clone())
+            .collect::<Vec<FE>>();
+        let delta_inv = SignKeys::phase3_reconstruct_delta(&delta_vec);
+
+        let decom_vec = skip_position(received_message3, position)
+            .map(|received| received.decom.clone())
+            .collect::<Vec<SignDecommitPhase1>>();
+        let bc1_vec = skip_position(received_message1, position)
+            .map(|received| received.com.clone())
+            .collect::<Vec<SignBroadcastPhase1>>();
+        let b_proof_vec = self.b_proof_vec.iter().collect::<Vec<&DLogProof<GE>>>();
+        let r =
+            SignKeys::phase4(&delta_inv, &b_proof_vec, decom_vec, &bc1_vec).or(Err(SignError))?;
+        // adding local g_gamma_i
+        let r = r + self.decom.g_gamma_i.clone() * &delta_inv;
+
+        let local_sig = LocalSignature::phase5_local_sig(
+            &self.sign_keys.k_i,
+            message,
+            &r,
+            &sigma_i,
+            &master_key.public.q,
+        );
+        let (com, decom, elgamal_proof, dlog_proof_rho) = local_sig.phase5a_broadcast_5b_zkproof();
+        self.r = Some(r);
+        self.local_sig = Some(local_sig);
+        self.phase5_first_message = Some(SignMessage5 {
+            decom,
+            elgamal_proof,
+            dlog_proof_rho,
+        });
+        Ok(SignMessage4 { com })
+    }
+
+    pub fn fifth_message(&self) -> Result<SignMessage5, Errors> {
+        self.phase5_first_message.clone().ok_or(SignError)
+    }
+
+    pub fn sixth_message(
+        &mut self,
+        master_key: &MasterKey,
+        received_message4: &[SignMessage4],
+        received_message5: &[SignMessage5],
+    ) -> Result<SignMessage6, Errors> {
+        let position = self.position(master_key)?;
+        self.check_broadcast(received_message4)?;
+        self.check_broadcast(received_message5)?;
+        let local_sig = self.local_sig.as_ref().ok_or(SignError)?;
+        let r = self.r.clone().ok_or(SignError)?;
+        let com_vec = skip_position(received_message4, position)
+            .map(|message| message.com.clone())
+            .collect::<Vec<Phase5Com1>>();
+        let decom_vec = skip_position(received_message5, position)
+            .map(|message| message.decom.clone())
+            .collect::<Vec<Phase5ADecom1>>();
+        let elgamal_proofs = skip_position(received_message5, position)
+            .map(|message| message.elgamal_proof.clone())
+            .collect::<Vec<HomoELGamalProof<GE>>>();
+        let dlog_proofs_rho = skip_position(received_message5, position)
+            .map(|message| message.dlog_proof_rho.clone())
+            .collect::<Vec<DLogProof<GE>>>();
+        let (com, decom2) = local_sig
+            .phase5c(
+                &decom_vec,
+                &com_vec,
+                &elgamal_proofs,
+                &dlog_proofs_rho,
+                &received_message5[position].decom.V_i,
+                &r,
+            )
+            .or(Err(SignError))?;
+        self.phase5_decom2 = Some(decom2);
+        Ok(SignMessage6 { com })
+    }
+
+    pub fn seventh_message(&self) -> Result<SignMessage7, Errors> {
+        Ok(SignMessage7 {
+            decom: self.phase5_decom2.clone().ok_or(SignError)?,
+        })
+    }
+
+    pub fn eighth_message(
+        &self,
+        received_message5: &[SignMessage5],
+        received_message6: &[SignMessage6],
+        received_message7: &[SignMessage7],
+    ) -> Result<SignMessage8, Errors> {
+        self.check_broadcast(received_message5)?;
+        self.check_broadcast(received_message6)?;
+        self.check_broadcast(received_message7)?;
+        let local_sig = self.local_sig.as_ref().ok_or(SignError)?;
+        let decom_vec1 = received_message5
+            .iter()
+            .map(|message| message.decom.clone())
+            .collect::<Vec<Phase5ADecom1>>();
+        let com_vec2 = received_message6
+            .iter()
+            .map(|message| message.com.clone())
+            .collect::<Vec<Phase5Com2>>();
+        let decom_vec2 = received_message7
+            .iter()
+            .map(|message| message.decom.clone())
+            .collect::<Vec<Phase5DDecom2>>();
+        let s_i = local_sig
+            .phase5d(&decom_vec2, &com_vec2, &decom_vec1)
+            .or(Err(SignError))?;
+        Ok(SignMessage8 { s_i })
+    }
+
+    pub fn signature(
+        &self,
+        master_key: &MasterKey,
+        message: &BigInt,
+        received_message8: &[SignMessage8],
+    ) -> Result<SignatureRecid, Errors> {
+        let position = self.position(master_key)?;
+        self.check_broadcast(received_message8)?;
+        let local_sig = self.local_sig.as_ref().ok_or(SignError)?;
+        let s_vec = skip_position(received_message8, position)
+            .map(|message| message.s_i.clone())
+            .collect::<Vec<FE>>();
+        let sig = local_sig.output_signature(&s_vec).or(Err(SignError))?;
+        verify(&sig, &master_key.public.q, message).or(Err(SignError))?;
+        Ok(sig)
+    }
+
+    // one message per signer, the signers were checked against the key in sign_first_message
+    fn check_broadcast<T>(&self, messages: &[T]) -> Result<(), Errors> {
+        if messages.len() == self.signers.len() {
+            Ok(())
+        } else {
+            Err(SignError)
+        }
+    }
+
+    fn position(&self, master_key: &MasterKey) -> Result<usize, Errors> {
+        self.signers
+            .iter()
+            .position(|party_index| *party_index == master_key.party_index())
+            .ok_or(SignError)
+    }
+}
+
+// sum_k C_k * (index + 1)^k, the public image of a share of a zero sharing
+fn zero_sharing_commitment(commitments: &[GE], index: usize) -> Result<GE, Errors> {
+    let q = FE::q();
+    let point = BigInt::from((index + 1) as i32);
+    let mut point_pow = BigInt::from(1);
+    let mut terms = commitments.iter().map(|commitment| {
+        point_pow = BigInt::mod_mul(&point_pow, &point, &q);
+        let point_pow_fe: FE = ECScalar::from(&point_pow);
+        commitment * &point_pow_fe
+    });
+    let head = terms.next().ok_or(RefreshError)?;
+    Ok(terms.fold(head, |acc, term| acc + term))
+}
+
+// commitments of every dealer in party order, each point in its 33 byte encoding
+fn refresh_commitments_hash(received_message1: &[RefreshMessage1]) -> BigInt {
+    let mut hasher = Sha256::new();
+    for message in received_message1 {
+        hasher.input(&(message.commitments.len() as u32).to_be_bytes());
+        for commitment in message.commitments.iter() {
+            hasher.input(&compressed(commitment)[..]);
+        }
+    }
+    BigInt::from(&hasher.result()[..])
+}
+
+fn skip_position<T>(messages: &[T], position: usize) -> impl Iterator<Item = &T> {
+    messages
+        .iter()
+        .enumerate()
+        .filter(move |(index, _)| *index != position)
+        .map(|(_, message)| message)
+}
@@ -0,0 +1,263 @@
+/*
+    KMS
+    Copyright 2018 by Kzen Networks
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::super::party_i::{KeyGen, RefreshMessage1, RefreshMessage2, RefreshMessage3, Sign};
+    use super::super::MasterKey;
+    use curv::BigInt;
+    use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::Parameters;
+    use Errors::{RefreshError, SignError};
+
+    #[test]
+    fn test_two_of_three() {
+        let master_keys = test_key_gen(1, 3);
+        for master_key in master_keys.iter() {
+            assert_eq!(master_key.public.q, master_keys[0].public.q);
+        }
+
+        // owner with the provider, and owner with the offline backup
+        test_sign(&[&master_keys[0], &master_keys[1]], &[0, 1]);
+        test_sign(&[&master_keys[0], &master_keys[2]], &[0, 2]);
+    }
+
+    #[test]
+    fn test_sign_malformed_input() {
+        let master_keys = test_key_gen(1, 3);
+        // too few signers, unknown party, duplicate, or without us
+        for signers in [vec![0], vec![0, 3], vec![0, 0], vec![1, 2]].iter() {
+            assert_eq!(
+                master_keys[0].sign_first_message(signers).err(),
+                Some(SignError)
+            );
+        }
+
+        let mut sign = master_keys[0]
+            .sign_first_message(&[0, 1])
+            .expect("bad signers");
+        // rounds out of order
+        assert_eq!(sign.fifth_message().err(), Some(SignError));
+        assert_eq!(sign.seventh_message().err(), Some(SignError));
+        assert_eq!(sign.eighth_message(&[], &[], &[]).err(), Some(SignError));
+        // one first message per signer
+        let messages1 = vec![sign.first_message.clone()];
+        assert_eq!(
+            sign.second_message(&master_keys[0], &messages1).err(),
+            Some(SignError)
+        );
+        assert_eq!(
+            sign.third_message(&master_keys[0], &[]).err(),
+            Some(SignError)
+        );
+    }
+
+    #[test]
+    fn test_get_child() {
+        let master_keys = test_key_gen(1, 3);
+        let child_keys = master_keys
+            .iter()
+            .map(|master_key| master_key.get_child(vec![BigInt::from(10), BigInt::from(5)]))
+            .collect::<Vec<MasterKey>>();
+        assert_eq!(child_keys[0].public.q, child_keys[2].public.q);
+        assert_ne!(child_keys[0].public.q, master_keys[0].public.q);
+        test_sign(&[&child_keys[1], &child_keys[2]], &[1, 2]);
+    }
+
+    #[test]
+    fn test_refresh() {
+        let master_keys = test_key_gen(1, 3);
+        let q = master_keys[0].public.q.clone();
+
+        let refresh_messages = master_keys
+            .iter()
+            .map(|master_key| master_key.refresh_first_message())
+            .collect::<Vec<(RefreshMessage1, Vec<RefreshMessage2>)>>();
+        let messages1 = refresh_messages
+            .iter()
+            .map(|(message1, _)| message1.clone())
+            .collect::<Vec<RefreshMessage1>>();
+        let messages3 = master_keys
+            .iter()
+            .map(|master_key| {
+                master_key
+                    .refresh_second_message(&messages1)
+                    .expect("bad commitments")
+            })
+            .collect::<Vec<RefreshMessage3>>();
+
+        // a dealer that shows party two other commitments than the rest is caught by the echo
+        let mut equivocated = messages1.clone();
+        equivocated[0] = master_keys[0].refresh_first_message().0;
+        let mut echo = messages3.clone();
+        echo[2] = master_keys[2]
+            .refresh_second_message(&equivocated)
+            .expect("bad commitments");
+
+        let refreshed_keys = master_keys
+            .iter()
+            .enumerate()
+            .map(|(index, master_key)| {
+                let received2 = refresh_messages
+                    .iter()
+                    .map(|(_, messages2)| messages2[index].clone())
+                    .collect::<Vec<RefreshMessage2>>();
+                assert_eq!(
+                    master_key
+                        .refresh_third_message(&messages1, &received2, &echo)
+                        .err(),
+                    Some(RefreshError)
+                );
+                master_key
+                    .refresh_third_message(&messages1, &received2, &messages3)
+                    .expect("bad refresh")
+            })
+            .collect::<Vec<MasterKey>>();
+        assert_eq!(refreshed_keys[1].public.q, q);
+        test_sign(&[&refreshed_keys[0], &refreshed_keys[2]], &[0, 2]);
+    }
+
+    pub fn test_key_gen(threshold: u16, share_count: u16) -> Vec<MasterKey> {
+        let params = Parameters {
+            threshold,
+            share_count,
+        };
+        let key_gens = (0..share_count as usize)
+            .map(|party_index| KeyGen::first_message(&params, party_index))
+            .collect::<Vec<KeyGen>>();
+        let messages1 = key_gens
+            .iter()
+            .map(|key_gen| key_gen.first_message.clone())
+            .collect::<Vec<_>>();
+        let messages2 = key_gens
+            .iter()
+            .map(|key_gen| key_gen.second_message())
+            .collect::<Vec<_>>();
+        // messages3[i][j] is sent by party i to party j
+        let messages3 = key_gens
+            .iter()
+            .map(|key_gen| {
+                key_gen
+                    .third_message(&messages1, &messages2)
+                    .expect("bad key gen commitments")
+            })
+            .collect::<Vec<_>>();
+        let mut shared_keys_vec = Vec::new();
+        let mut received_messages3 = Vec::new();
+        let mut messages4 = Vec::new();
+        for (index, key_gen) in key_gens.iter().enumerate() {
+            let received = messages3
+                .iter()
+                .map(|messages| messages[index].clone())
+                .collect::<Vec<_>>();
+            let (shared_keys, message4) = key_gen
+                .fourth_message(&messages2, &received)
+                .expect("bad key gen shares");
+            shared_keys_vec.push(shared_keys);
+            received_messages3.push(received);
+            messages4.push(message4);
+        }
+        key_gens
+            .iter()
+            .zip(shared_keys_vec.into_iter())
+            .zip(received_messages3.iter())
+            .map(|((key_gen, shared_keys), received)| {
+                MasterKey::set_master_key(
+                    key_gen,
+                    shared_keys,
+                    &messages1,
+                    &messages2,
+                    received,
+                    &messages4,
+                )
+                .expect("bad key gen proofs")
+            })
+            .collect()
+    }
+
+    pub fn test_sign(master_keys: &[&MasterKey], signers: &[usize]) {
+        let message = BigInt::from(1234);
+        let mut signs = master_keys
+            .iter()
+            .map(|master_key| master_key.sign_first_message(signers).expect("bad signers"))
+            .collect::<Vec<Sign>>();
+        let messages1 = signs
+            .iter()
+            .map(|sign| sign.first_message.clone())
+            .collect::<Vec<_>>();
+
+        // messages2[i] holds the messages of party i to every other signer, in signers order
+        let messages2 = signs
+            .iter_mut()
+            .zip(master_keys.iter())
+            .map(|(sign, master_key)| {
+                sign.second_message(master_key, &messages1)
+                    .expect("bad first messages")
+            })
+            .collect::<Vec<_>>();
+        let messages3 = signs
+            .iter_mut()
+            .zip(master_keys.iter())
+            .enumerate()
+            .map(|(position, (sign, master_key))| {
+                let received = messages2
+                    .iter()
+                    .enumerate()
+                    .filter(|(sender, _)| *sender != position)
+                    .map(|(sender, messages)| {
+                        let slot = if position < sender {
+                            position
+                        } else {
+                            position - 1
+                        };
+                        messages[slot].clone()
+                    })
+                    .collect::<Vec<_>>();
+                sign.third_message(master_key, &received).expect("bad mta")
+            })
+            .collect::<Vec<_>>();
+        let messages4 = signs
+            .iter_mut()
+            .zip(master_keys.iter())
+            .map(|(sign, master_key)| {
+                sign.fourth_message(master_key, &message, &messages1, &messages3)
+                    .expect("bad gamma decommitment")
+            })
+            .collect::<Vec<_>>();
+        let messages5 = signs
+            .iter()
+            .map(|sign| sign.fifth_message().expect("fourth message missing"))
+            .collect::<Vec<_>>();
+        let messages6 = signs
+            .iter_mut()
+            .zip(master_keys.iter())
+            .map(|(sign, master_key)| {
+                sign.sixth_message(master_key, &messages4, &messages5)
+                    .expect("bad phase 5 proofs")
+            })
+            .collect::<Vec<_>>();
+        let messages7 = signs
+            .iter()
+            .map(|sign| sign.seventh_message().expect("sixth message missing"))
+            .collect::<Vec<_>>();
+        let messages8 = signs
+            .iter()
+            .map(|sign| {
+                sign.eighth_message(&messages5, &messages6, &messages7)
+                    .expect("bad phase 5 decommitment")
+            })
+            .collect::<Vec<_>>();
+        for (sign, master_key) in signs.iter().zip(master_keys.iter()) {
+            sign.signature(master_key, &message, &messages8)
+                .expect("bad signature");
+        }
+    }
+}
@@ -0,0 +1,118 @@
+/*
+    KMS-ECDSA
+    Copyright 2018 by Kzen Networks
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// ecdsa adaptor signatures for the two party key. With k = k1 * k2 the pre signature is
+// s' = k^-1 * (m + r * x) where r is taken from the adapted nonce k * Y instead of k * G, and
+// s = s' * t^-1 verifies as a plain ecdsa signature. Nobody knows k, so the dleq between the
+// nonce and the adapted nonce is proven in two steps: k2 by party two and k1 by party one.
+
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::BigInt;
+use ecdh::DleqProof;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one;
+use AdaptorError::{self, PreSignatureInvalid, ProofInvalid, WitnessMismatch};
+
+// r2 = k2 * G, y2 = k2 * Y and the nonce, adapted nonce are k1 times those
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AdaptorProof {
+    pub r2: GE,
+    pub y2: GE,
+    pub proof2: DleqProof,
+    pub proof1: DleqProof,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PreSignature {
+    // k * G
+    pub nonce: GE,
+    // k * Y, its x coordinate is the r of the signature
+    pub adapted_nonce: GE,
+    pub s: BigInt,
+    pub proof: AdaptorProof,
+}
+
+pub fn nonce_r(adapted_nonce: &GE) -> FE {
+    ECScalar::from(&adapted_nonce.x_coor().unwrap())
+}
+
+// low s, as the signing code does
+fn normalize(s: &FE) -> BigInt {
+    let s = s.to_big_int();
+    let s_neg = FE::q() - &s;
+    if s < s_neg {
+        s
+    } else {
+        s_neg
+    }
+}
+
+impl PreSignature {
+    // run by the counter party of a swap before it commits to anything locked to Y
+    pub fn verify(&self, q: &GE, message: &BigInt, adaptor_point: &GE) -> Result<(), AdaptorError> {
+        let g: GE = ECPoint::generator();
+        let proof = &self.proof;
+        proof
+            .proof2
+            .verify(&g, &proof.r2, adaptor_point, &proof.y2)
+            .or(Err(ProofInvalid))?;
+        proof
+            .proof1
+            .verify(&proof.r2, &self.nonce, &proof.y2, &self.adapted_nonce)
+            .or(Err(ProofInvalid))?;
+
+        // s'^-1 * (m * G + r * Q) is the nonce up to the sign of s'
+        if self.s == BigInt::from(0) || self.s >= FE::q() {
+            return Err(PreSignatureInvalid);
+        }
+        let s: FE = ECScalar::from(&self.s);
+        let s_inv = s.invert();
+        let m: FE = ECScalar::from(message);
+        let nonce = &(&g * &(m * &s_inv)) + &(q * &(nonce_r(&self.adapted_nonce) * &s_inv));
+        if nonce.x_coor() == self.nonce.x_coor() {
+            Ok(())
+        } else {
+            Err(PreSignatureInvalid)
+        }
+    }
+
+    pub fn adapt(&self, t: &FE) -> party_one::Signature {
+        let s: FE = ECScalar::from(&self.s);
+        party_one::Signature {
+            r: nonce_r(&self.adapted_nonce).to_big_int(),
+            s: normalize(&(s * &t.invert())),
+        }
+    }
+
+    // t from the published signature, up to the sign lost by the low s normalization
+    pub fn extract(
+        &self,
+        signature: &party_one::Signature,
+        adaptor_point: &GE,
+    ) -> Result<FE, AdaptorError> {
+        let g: GE = ECPoint::generator();
+        if signature.r != nonce_r(&self.adapted_nonce).to_big_int() {
+            return Err(WitnessMismatch);
+        }
+        let s_pre: FE = ECScalar::from(&self.s);
+        let s: FE = ECScalar::from(&signature.s);
+        let t = s_pre * &s.invert();
+        let t_neg: FE = ECScalar::from(&(FE::q() - t.to_big_int()));
+        if &g * &t == *adaptor_point {
+            Ok(t)
+        } else if &g * &t_neg == *adaptor_point {
+            Ok(t_neg)
+        } else {
+            Err(WitnessMismatch)
+        }
+    }
+}
@@ -10,15 +10,27 @@
     @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
 */
 
-use curv::arithmetic::traits::Converter;
+use centipede::juggling::proof_system::{Helgamalsegmented, Proof, Witness};
+use centipede::juggling::segmentation::Msegmentation;
+use curv::arithmetic::traits::{Converter, Samplable};
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
 use curv::cryptographic_primitives::hashing::hmac_sha512;
-use curv::cryptographic_primitives::hashing::traits::KeyedHash;
+use curv::cryptographic_primitives::hashing::traits::{Hash, KeyedHash};
 use curv::elliptic::curves::traits::{ECPoint, ECScalar};
 use curv::BigInt;
 use curv::arithmetic::{BasicOps, One};
 use curv::elliptic::curves::secp256_k1::{FE, GE};
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
 use paillier::*;
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use util::hash_length_prefixed;
+use ConsistencyError::{
+    self, CKeyMismatch, ChainCodeMismatch, EpochMismatch, P1Mismatch, P2Mismatch,
+    PaillierKeyMismatch, PublicKeyMismatch,
+};
+use Errors::{self, EpochError};
+use ValidationError;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Party1Public {
@@ -30,11 +42,13 @@ pub struct Party1Public {
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(try_from = "UncheckedMasterKey1")]
 pub struct MasterKey1 {
     pub public: Party1Public,
     // Why is the field below public? See: https://github.com/KZen-networks/kms-secp256k1/issues/20
     pub private: party_one::Party1Private,
     chain_code: BigInt,
+    pub epoch: KeyEpoch,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -47,16 +61,327 @@ pub struct Party2Public {
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(try_from = "UncheckedMasterKey2")]
 pub struct MasterKey2 {
     pub public: Party2Public,
     pub private: party_two::Party2Private,
     pub chain_code: BigInt,
+    pub epoch: KeyEpoch,
 }
 
+// master keys as loaded, before the checks of validate run
+#[derive(Deserialize)]
+struct UncheckedMasterKey1 {
+    public: Party1Public,
+    private: party_one::Party1Private,
+    chain_code: BigInt,
+    // keys stored before epochs were introduced load at the genesis epoch
+    #[serde(default = "KeyEpoch::genesis")]
+    epoch: KeyEpoch,
+}
+
+#[derive(Deserialize)]
+struct UncheckedMasterKey2 {
+    public: Party2Public,
+    private: party_two::Party2Private,
+    chain_code: BigInt,
+    // keys stored before epochs were introduced load at the genesis epoch
+    #[serde(default = "KeyEpoch::genesis")]
+    epoch: KeyEpoch,
+}
+
+// position of a master key in its rotation history. Every rotation moves both parties to the
+// next epoch and chains in the hash of the public data left behind, so a master key restored
+// from a backup taken before a rotation is behind and the counter party refuses to use it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct KeyEpoch {
+    pub epoch: u32,
+    // hash of the entry of the previous epoch, zero at key gen
+    pub previous: BigInt,
+}
+
+// public data of a master key at one epoch, the same for both parties
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct EpochEntry {
+    pub epoch: KeyEpoch,
+    pub q: GE,
+    pub p1: GE,
+    pub p2: GE,
+    pub paillier_pub: EncryptionKey,
+    pub c_key: BigInt,
+    pub chain_code: BigInt,
+}
+
+// sent by both parties before a rotation, derivation or signing to check they hold the same key
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ConsistencyMessage {
+    pub entry: EpochEntry,
+}
+
+// entries of past epochs, oldest first. Every operation that replaces the key material adds the
+// entry of the key it replaces when it switches: the one shot rotations take the ledger as an
+// argument, the two phase rotation (also used by provider replacement and device migration) and
+// the chain code refresh on commit.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct EpochLedger {
+    pub entries: Vec<EpochEntry>,
+}
+
+// parameters of the verifiable encryption used to move secret shares between parties
+pub const SEGMENT_SIZE: usize = 8;
+pub const NUM_OF_SEGMENTS: usize = 32;
+
+// full private key reconstructed by party two at the end of a cooperative export
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ExportedKey {
+    pub q: GE,
+    pub secret: FE,
+    pub chain_code: BigInt,
+}
+
+// what is left of a master key after export. Enough to know which key was released,
+// nothing that can take part in signing.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RetiredMasterKey {
+    pub q: GE,
+    pub chain_code: BigInt,
+}
+
+// chain code of a master key replaced by a chain code refresh. A child path keeps its meaning
+// but not its key: the same path under the new chain code is a different key. Children of the
+// retired chain code can still be found with public derivation, funds on them have to be moved
+// before the refresh.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RetiredChainCode {
+    pub q: GE,
+    pub chain_code: BigInt,
+}
+
+// public key of a child before and after a chain code refresh
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ChildMapping {
+    pub location_in_hir: Vec<BigInt>,
+    pub retired_q: GE,
+    pub q: GE,
+}
+
+pub mod adaptor;
 pub mod party1;
 pub mod party2;
 mod test;
 
+// identifies a master key across rotations: only q and the chain code go in
+pub fn key_fingerprint(q: &GE, chain_code: &BigInt) -> BigInt {
+    HSha256::create_hash(&[&q.bytes_compressed_to_big_int(), chain_code])
+}
+
+// loading runs every check of validate but the decryption of c_key, which costs two paillier
+// decryptions and is left to an explicit validate
+impl TryFrom<UncheckedMasterKey1> for MasterKey1 {
+    type Error = ValidationError;
+
+    fn try_from(unchecked: UncheckedMasterKey1) -> Result<MasterKey1, ValidationError> {
+        let master_key = MasterKey1 {
+            public: unchecked.public,
+            private: unchecked.private,
+            chain_code: unchecked.chain_code,
+            epoch: unchecked.epoch,
+        };
+        master_key.check()?;
+        Ok(master_key)
+    }
+}
+
+impl TryFrom<UncheckedMasterKey2> for MasterKey2 {
+    type Error = ValidationError;
+
+    fn try_from(unchecked: UncheckedMasterKey2) -> Result<MasterKey2, ValidationError> {
+        let master_key = MasterKey2 {
+            public: unchecked.public,
+            private: unchecked.private,
+            chain_code: unchecked.chain_code,
+            epoch: unchecked.epoch,
+        };
+        master_key.validate()?;
+        Ok(master_key)
+    }
+}
+
+impl KeyEpoch {
+    pub fn genesis() -> KeyEpoch {
+        KeyEpoch {
+            epoch: 0,
+            previous: BigInt::from(0),
+        }
+    }
+
+    pub fn next(entry: &EpochEntry) -> KeyEpoch {
+        KeyEpoch {
+            epoch: entry.epoch.epoch + 1,
+            previous: entry.hash(),
+        }
+    }
+}
+
+impl EpochEntry {
+    pub fn hash(&self) -> BigInt {
+        let mut hasher = Sha256::new();
+        hasher.input(&self.epoch.epoch.to_be_bytes());
+        for value in &[
+            &self.epoch.previous,
+            &self.q.bytes_compressed_to_big_int(),
+            &self.p1.bytes_compressed_to_big_int(),
+            &self.p2.bytes_compressed_to_big_int(),
+            &self.paillier_pub.n,
+            &self.c_key,
+            &self.chain_code,
+        ] {
+            hash_length_prefixed(&mut hasher, value);
+        }
+        BigInt::from(&hasher.result()[..])
+    }
+}
+
+// the fingerprints are compared first, the fields only to report where the keys diverged
+pub fn check_consistency(
+    local: &EpochEntry,
+    received: &EpochEntry,
+) -> Result<(), ConsistencyError> {
+    if local.hash() == received.hash() {
+        return Ok(());
+    }
+    if local.epoch != received.epoch {
+        Err(EpochMismatch)
+    } else if local.q != received.q {
+        Err(PublicKeyMismatch)
+    } else if local.p1 != received.p1 {
+        Err(P1Mismatch)
+    } else if local.p2 != received.p2 {
+        Err(P2Mismatch)
+    } else if local.paillier_pub.n != received.paillier_pub.n {
+        Err(PaillierKeyMismatch)
+    } else if local.c_key != received.c_key {
+        Err(CKeyMismatch)
+    } else {
+        Err(ChainCodeMismatch)
+    }
+}
+
+impl EpochLedger {
+    pub fn new() -> EpochLedger {
+        EpochLedger {
+            entries: Vec::new(),
+        }
+    }
+
+    // an entry is accepted only if it is the epoch right after the last one
+    pub fn push(&mut self, entry: EpochEntry) -> Result<(), Errors> {
+        if let Some(last) = self.entries.last() {
+            if KeyEpoch::next(last) != entry.epoch {
+                return Err(EpochError);
+            }
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    // the current epoch of a master key must follow the last entry
+    pub fn verify_epoch(&self, epoch: &KeyEpoch) -> Result<(), Errors> {
+        match self.entries.last() {
+            Some(last) if &KeyEpoch::next(last) != epoch => Err(EpochError),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl RetiredChainCode {
+    pub fn get_child_public_key(&self, location_in_hir: Vec<BigInt>) -> GE {
+        let (public_key_new_child, _, _) = hd_key(location_in_hir, &self.q, &self.chain_code);
+        public_key_new_child
+    }
+
+    // old and new public key of every child the caller has in use
+    pub fn map_children(
+        &self,
+        chain_code: &BigInt,
+        locations_in_hir: &[Vec<BigInt>],
+    ) -> Vec<ChildMapping> {
+        locations_in_hir
+            .iter()
+            .map(|location_in_hir| {
+                let (public_key_new_child, _, _) =
+                    hd_key(location_in_hir.clone(), &self.q, chain_code);
+                ChildMapping {
+                    location_in_hir: location_in_hir.clone(),
+                    retired_q: self.get_child_public_key(location_in_hir.clone()),
+                    q: public_key_new_child,
+                }
+            })
+            .collect()
+    }
+}
+
+impl ExportedKey {
+    pub fn get_child(&self, location_in_hir: Vec<BigInt>) -> ExportedKey {
+        let (public_key_new_child, f_l_new, cc_new) =
+            hd_key(location_in_hir, &self.q, &self.chain_code);
+        ExportedKey {
+            q: public_key_new_child,
+            secret: self.secret.clone() * &f_l_new,
+            chain_code: cc_new.bytes_compressed_to_big_int(),
+        }
+    }
+
+    // 32 bytes big endian
+    pub fn to_raw(&self) -> Vec<u8> {
+        let secret_bytes = BigInt::to_bytes(&self.secret.to_big_int());
+        let mut raw = vec![0u8; 32 - secret_bytes.len()];
+        raw.extend_from_slice(&secret_bytes);
+        raw
+    }
+
+    // wallet import format for a compressed public key
+    pub fn to_wif(&self, testnet: bool) -> String {
+        let version = if testnet { 0xef } else { 0x80 };
+        let mut payload = vec![version];
+        payload.extend_from_slice(&self.to_raw());
+        payload.push(0x01);
+        bs58::encode(payload).with_check().into_string()
+    }
+}
+
+// Party1Private and Party2Private do not expose their share, only its segments through the
+// verifiable encryption of the share. The encryption is thrown away, with its one time key.
+fn party_one_share(private: &party_one::Party1Private) -> FE {
+    let g: GE = ECPoint::generator();
+    let (y_pub, _) = one_time_key();
+    let (segments, _) = private.to_encrypted_segment(SEGMENT_SIZE, NUM_OF_SEGMENTS, &y_pub, &g);
+    Msegmentation::assemble_fe(&segments.x_vec, &SEGMENT_SIZE)
+}
+
+fn party_two_share(private: &party_two::Party2Private) -> FE {
+    let g: GE = ECPoint::generator();
+    let (y_pub, _) = one_time_key();
+    let (segments, _) = private.to_encrypted_segment(SEGMENT_SIZE, NUM_OF_SEGMENTS, &y_pub, &g);
+    Msegmentation::assemble_fe(&segments.x_vec, &SEGMENT_SIZE)
+}
+
+// Dec(c) mod q up to the sign, without the paillier private key leaving Party1Private: the
+// signing code decrypts c and an encryption of one under the same ephemeral key, which gives
+// k^-1 * Dec(c) and k^-1, each up to the sign of the low s normalization
+fn party_one_decrypt(private: &party_one::Party1Private, ek: &EncryptionKey, c: &BigInt) -> FE {
+    let g: GE = ECPoint::generator();
+    let (_, eph_ec_key_pair) = party_one::EphKeyGenFirstMsg::create();
+    let c_one = Paillier::encrypt(ek, RawPlaintext::from(BigInt::one()))
+        .0
+        .into_owned();
+    let s = party_one::Signature::compute(private, c, &eph_ec_key_pair, &g).s;
+    let s_one = party_one::Signature::compute(private, &c_one, &eph_ec_key_pair, &g).s;
+    let s: FE = ECScalar::from(&s);
+    let s_one: FE = ECScalar::from(&s_one);
+    s * &s_one.invert()
+}
+
 pub fn hd_key(
     mut location_in_hir: Vec<BigInt>,
     pubkey: &GE,
@@ -96,3 +421,121 @@ pub fn hd_key(
             });
     (public_key_new_child, f_l_new, cc_new)
 }
+
+// Enc(rho) + sigma * c_key, decrypts to rho + sigma * x1 as long as c_key still encrypts x1.
+// rho and sigma are below q so the plaintext can't wrap around n.
+fn health_check_ciphertext(
+    ek: &EncryptionKey,
+    c_key: &BigInt,
+    rho: &FE,
+    sigma: &FE,
+    randomness: &BigInt,
+) -> BigInt {
+    let c_rho = Paillier::encrypt_with_chosen_randomness(
+        ek,
+        RawPlaintext::from(rho.to_big_int()),
+        &Randomness(randomness.clone()),
+    );
+    let c_sigma = Paillier::mul(
+        ek,
+        RawCiphertext::from(c_key.clone()),
+        RawPlaintext::from(sigma.to_big_int()),
+    );
+    Paillier::add(ek, c_rho, c_sigma).0.into_owned()
+}
+
+// c3 of party_two::PartialSig::compute for a nonce k2 party two picked itself, the ephemeral
+// key of the signing code does not expose its share. r is taken from k2 times the nonce share
+// of party one: c3 = Enc(k2^-1 * m + rho * q) + c_key * (k2^-1 * r * x2)
+fn adaptor_c3(
+    ek: &EncryptionKey,
+    c_key: &BigInt,
+    x2: &FE,
+    k2: &FE,
+    nonce_share: &GE,
+    message: &BigInt,
+) -> BigInt {
+    let q = FE::q();
+    let k2_inv = k2.invert();
+    let r: FE = ECScalar::from(&(nonce_share * k2).x_coor().unwrap());
+    let m: FE = ECScalar::from(message);
+    let rho = BigInt::sample_below(&(&q * &q));
+    let c1 = Paillier::encrypt(
+        ek,
+        RawPlaintext::from(rho * &q + (k2_inv.clone() * &m).to_big_int()),
+    );
+    let c2 = Paillier::mul(
+        ek,
+        RawCiphertext::from(c_key.clone()),
+        RawPlaintext::from((k2_inv * &r * x2).to_big_int()),
+    );
+    Paillier::add(ek, c2, c1).0.into_owned()
+}
+
+fn health_check_response_hash(r1: &GE, s: &BigInt) -> BigInt {
+    HSha256::create_hash(&[&r1.bytes_compressed_to_big_int(), s])
+}
+
+fn adaptor_nonce_hash(r2: &GE, y2: &GE) -> BigInt {
+    HSha256::create_hash(&[
+        &r2.bytes_compressed_to_big_int(),
+        &y2.bytes_compressed_to_big_int(),
+    ])
+}
+
+// receiving end of a share transfer (export, provider replacement, device migration): one time
+// key the share is encrypted under
+fn one_time_key() -> (GE, FE) {
+    let g: GE = ECPoint::generator();
+    let y: FE = ECScalar::new_random();
+    (&g * &y, y)
+}
+
+// sending end: the share encrypted under y_pub with a proof that it is the dlog of its public
+// share. The segments come from the private key type, the share itself is never read.
+fn prove_encrypted_share(
+    (segments, encryptions): (Witness, Helgamalsegmented),
+    y_pub: &GE,
+) -> (Helgamalsegmented, Proof) {
+    let g: GE = ECPoint::generator();
+    let proof = Proof::prove(&segments, &encryptions, &g, y_pub, &SEGMENT_SIZE);
+    (encryptions, proof)
+}
+
+fn encrypt_party_one_share(
+    private: &party_one::Party1Private,
+    y_pub: &GE,
+) -> (Helgamalsegmented, Proof) {
+    let g: GE = ECPoint::generator();
+    prove_encrypted_share(
+        private.to_encrypted_segment(SEGMENT_SIZE, NUM_OF_SEGMENTS, y_pub, &g),
+        y_pub,
+    )
+}
+
+fn encrypt_party_two_share(
+    private: &party_two::Party2Private,
+    y_pub: &GE,
+) -> (Helgamalsegmented, Proof) {
+    let g: GE = ECPoint::generator();
+    prove_encrypted_share(
+        private.to_encrypted_segment(SEGMENT_SIZE, NUM_OF_SEGMENTS, y_pub, &g),
+        y_pub,
+    )
+}
+
+// receiving end of a share transfer: checks that the encrypted share is the dlog of
+// public_share and decrypts it with the one time key y
+fn decrypt_share(
+    y: &FE,
+    encryptions: &Helgamalsegmented,
+    proof: &Proof,
+    public_share: &GE,
+) -> Result<FE, ()> {
+    let g: GE = ECPoint::generator();
+    let y_pub = &g * y;
+    proof
+        .verify(encryptions, &g, &y_pub, public_share, &SEGMENT_SIZE)
+        .or(Err(()))?;
+    Msegmentation::decrypt(encryptions, &g, y, &SEGMENT_SIZE).or(Err(()))
+}
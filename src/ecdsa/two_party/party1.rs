@@ -9,25 +9,65 @@
     version 3 of the License, or (at your option) any later version.
     @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
 */
+use centipede::juggling::proof_system::{Helgamalsegmented, Proof};
+use curv::arithmetic::traits::Samplable;
+use curv::arithmetic::One;
+use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
+use curv::cryptographic_primitives::commitments::traits::Commitment;
 use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
+use curv::cryptographic_primitives::twoparty::dh_key_exchange_variant_with_pok_comm::{
+    CommWitness, EcKeyPair, Party1FirstMessage, Party1SecondMessage,
+};
 use curv::elliptic::curves::traits::ECPoint;
 use curv::elliptic::curves::traits::ECScalar;
 use curv::BigInt;
 use curv::elliptic::curves::secp256_k1::{FE, GE};
 
-use super::hd_key;
-use super::{MasterKey1, MasterKey2, Party1Public};
-use ecdsa::two_party::party2::SignMessage;
+use super::adaptor::{AdaptorProof, PreSignature};
+use super::adaptor_nonce_hash;
+use super::{check_consistency, decrypt_share, encrypt_party_one_share, hd_key, key_fingerprint};
+use super::{health_check_ciphertext, health_check_response_hash};
+use super::{one_time_key, party_one_decrypt, party_one_share};
+use super::{
+    ConsistencyMessage, EpochEntry, EpochLedger, KeyEpoch, MasterKey1, MasterKey2, Party1Public,
+    Party2Public, RetiredChainCode, RetiredMasterKey,
+};
+use audit::{AuditLog, AuditRecord, Operation};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::Network;
+use chain_code::two_party::party1::ChainCode1;
+use ecdh::{DleqProof, PartialEcdh};
+use ecdsa::two_party::party2::{
+    AdaptorSignParty2Message1, AdaptorSignParty2Message2, ChainCodeParty2Message1,
+    EcdhParty2Message1, ExportParty2Message1, HealthCheckParty2Message1, HealthCheckParty2Message2,
+    RecoveryParty2Message1, RotationParty2Message1, SignMessage,
+};
+use health_check::{new_challenge, ShareProof};
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_two::EphKeyGenFirstMsg;
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
 use multi_party_ecdsa::utilities::zk_pdl_with_slack::PDLwSlackProof;
 use multi_party_ecdsa::utilities::zk_pdl_with_slack::PDLwSlackStatement;
 use zk_paillier::zkproofs::CompositeDLogProof;
 
-use paillier::EncryptionKey;
+use paillier::{Add, Encrypt, EncryptionKey, Paillier, RawCiphertext, RawPlaintext};
+use policy::{PolicyApproval, PolicyEngine, PolicyRequest};
+use psbt::{add_ecdsa_signature, external_outputs, sign_jobs, SignJob};
 use rotation::two_party::Rotation;
+use sign_request::SignRequest;
+use validation::{check_chain_code, check_paillier, check_point};
 use zk_paillier::zkproofs::NICorrectKeyProof;
-use Errors::{self, SignError};
+use ConsistencyError::{self, EpochMismatch};
+use EcdhError;
+use Errors::{
+    self, ChainCodeError, EpochError, HealthCheckError, ProviderReplaceError, RotationError,
+    SignError,
+};
+use PolicyError::{self, TransactionInvalid};
+use PsbtError::{self, SighashMismatch, SignatureInvalid};
+use RecoveryError::{self, FingerprintMismatch, PublicKeyMismatch, PublicShareMismatch};
+use ValidationError;
+
+const BLIND_BITS: usize = 256;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyGenParty1Message2 {
@@ -40,8 +80,22 @@ pub struct KeyGenParty1Message2 {
     pub composite_dlog_proof: CompositeDLogProof,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignParty1Message1 {
+    pub eph_key_gen_first_message: party_one::EphKeyGenFirstMsg,
+    pub epoch: KeyEpoch,
+}
+
+// the final signature, delivered to party two
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignParty1Message2 {
+    pub signature: party_one::SignatureRecid,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RotationParty1Message1 {
+    // epoch of party one before the rotation
+    pub epoch: KeyEpoch,
     pub ek: EncryptionKey,
     pub c_key_new: BigInt,
     pub correct_key_proof: NICorrectKeyProof,
@@ -50,17 +104,119 @@ pub struct RotationParty1Message1 {
     pub composite_dlog_proof: CompositeDLogProof,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ExportParty1Message1 {
+    pub encryptions: Helgamalsegmented,
+    pub proof: Proof,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecoveryParty1Message1 {
+    pub public: Party2Public,
+    pub chain_code: BigInt,
+    pub rotation_first_message: RotationParty1Message1,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplaceProviderMessage1 {
+    pub y_pub: GE,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReplaceProviderMessage2 {
+    pub encryptions: Helgamalsegmented,
+    pub proof: Proof,
+    pub public: Party1Public,
+    pub chain_code: BigInt,
+    pub epoch: KeyEpoch,
+}
+
+// the rotated key of a two phase rotation. Should be stored next to the current key until the
+// rotation is either committed or aborted.
+#[derive(Serialize, Deserialize)]
+pub struct PendingRotation1 {
+    pub rotated: MasterKey1,
+    // public data of the current key, recorded in the epoch ledger on the switch
+    pub previous: EpochEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotationParty1Message2 {
+    // epoch of party one after the commit
+    pub epoch: KeyEpoch,
+}
+
+// chain code refresh: commitment of party one to its share of the new chain code
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChainCodeParty1Message1 {
+    pub first_message: Party1FirstMessage,
+    pub epoch: KeyEpoch,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChainCodeParty1Message2 {
+    pub second_message: Party1SecondMessage<GE>,
+}
+
+// the key with the new chain code, kept next to the current key until party two confirmed it
+// derived the same chain code, see PendingChainCode1::commit
+#[derive(Serialize, Deserialize)]
+pub struct PendingChainCode1 {
+    pub refreshed: MasterKey1,
+    pub retired: RetiredChainCode,
+    pub previous: EpochEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthCheckParty1Message1 {
+    pub challenge: BigInt,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthCheckParty1Message2 {
+    pub share_proof: ShareProof,
+    // commitment to the answer to c_check
+    pub com: BigInt,
+}
+
+// answer to c_check, held back by party one until party two showed how c_check was built
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthCheckParty1Message3 {
+    pub r1: GE,
+    pub s: BigInt,
+    pub blind: BigInt,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdaptorSignParty1Message1 {
+    pub r1: GE,
+    // k1 * Y
+    pub partial_y1: PartialEcdh,
+    pub epoch: KeyEpoch,
+}
+
+// nonce of party one in adaptor mode, kept until the pre signature
+pub struct AdaptorEphKeyPair {
+    k1: FE,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EcdhParty1Message1 {
+    pub partial_ecdh: PartialEcdh,
+}
+
 impl MasterKey1 {
-    // before rotation make sure both parties have the same key
-    pub fn rotate(
-        self,
+    // before rotation make sure both parties have the same key, see check_consistency
+    fn rotated(
+        &self,
         cf: &Rotation,
         party_one_private: party_one::Party1Private,
         ek_new: &EncryptionKey,
         c_key_new: &BigInt,
     ) -> MasterKey1 {
+        let epoch = KeyEpoch::next(&self.epoch_entry());
         let public = Party1Public {
-            q: self.public.q,
+            q: self.public.q.clone(),
             p1: &self.public.p1 * &cf.rotation,
             p2: &self.public.p2 * &cf.rotation.invert(),
             paillier_pub: ek_new.clone(),
@@ -69,11 +225,25 @@ impl MasterKey1 {
         MasterKey1 {
             public,
             private: party_one_private,
-            chain_code: self.chain_code,
+            chain_code: self.chain_code.clone(),
+            epoch,
         }
     }
 
-    pub fn get_child(&self, location_in_hir: Vec<BigInt>) -> MasterKey1 {
+    // the derivation is recorded in the log. derive_child is the same without a record, for the
+    // public keys of children only.
+    pub fn get_child(
+        &self,
+        location_in_hir: Vec<BigInt>,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> MasterKey1 {
+        let child = self.derive_child(location_in_hir);
+        log.append(child.audit_record(Operation::GetChild, None, session_id));
+        child
+    }
+
+    pub(super) fn derive_child(&self, location_in_hir: Vec<BigInt>) -> MasterKey1 {
         let (public_key_new_child, f_l_new, cc_new) =
             hd_key(location_in_hir, &self.public.q, &self.chain_code);
 
@@ -88,15 +258,38 @@ impl MasterKey1 {
             public,
             private: self.private.clone(),
             chain_code: cc_new.bytes_compressed_to_big_int(),
+            epoch: self.epoch.clone(),
         }
     }
 
+    // last step of key gen, recorded in the log
     pub fn set_master_key(
         chain_code: &BigInt,
         party_one_private: party_one::Party1Private,
         party_one_public_ec_key: &GE,
         party2_first_message_public_share: &GE,
         paillier_key_pair: party_one::PaillierKeyPair,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> MasterKey1 {
+        let master_key = MasterKey1::assemble(
+            chain_code,
+            party_one_private,
+            party_one_public_ec_key,
+            party2_first_message_public_share,
+            paillier_key_pair,
+        );
+        log.append(master_key.audit_record(Operation::KeyGen, None, session_id));
+        master_key
+    }
+
+    // the master key from key gen data, also used to rebuild keys in recovery
+    pub(super) fn assemble(
+        chain_code: &BigInt,
+        party_one_private: party_one::Party1Private,
+        party_one_public_ec_key: &GE,
+        party2_first_message_public_share: &GE,
+        paillier_key_pair: party_one::PaillierKeyPair,
     ) -> MasterKey1 {
         let party1_public = Party1Public {
             q: party_one::compute_pubkey(&party_one_private, party2_first_message_public_share),
@@ -110,6 +303,7 @@ impl MasterKey1 {
             public: party1_public,
             private: party_one_private,
             chain_code: chain_code.clone(),
+            epoch: KeyEpoch::genesis(),
         }
     }
 
@@ -122,18 +316,21 @@ impl MasterKey1 {
             encrypted_secret_share: self.public.c_key.clone(),
         };
         // set master keys:
-        MasterKey2::set_master_key(
+        let mut master_key = MasterKey2::assemble(
             &self.chain_code,
             &ec_key_pair_party2,
             &ec_key_pair_party2.public_share,
             &party_two_paillier,
-        )
+        );
+        master_key.epoch = self.epoch.clone();
+        master_key
     }
 
     pub fn recover_master_key(
         recovered_secret: FE,
         party_one_public: Party1Public,
         chain_code: BigInt,
+        epoch: KeyEpoch,
     ) -> MasterKey1 {
         //  master key of party one from party one secret recovery:
         // q2 (public key of party two), chain code, and paillier data are needed for
@@ -143,8 +340,9 @@ impl MasterKey1 {
         // (2) party 1 lost the public data as well. in this case only party 2 can help with the public data.
         //     if party 2 becomes malicious it means two failures at the same time from which the system will not be able to recover.
         //     Therefore no point of running any secure protocol with party 2 and just accept the public data as is.
+        //     recover_master_key_verified checks the public data against a stored fingerprint of the key.
         // paillier data can be refreshed. Because it is likely that paillier private key was lost, therefore a new paillier scheme must be created
-        // to make sure that party2 updates to the new paillier - a key rotation scheme must be performed. see test
+        // to make sure that party2 updates to the new paillier - a key rotation scheme must be performed. see recover_master_key_and_rotate
 
         let (_, _, ec_key_pair_party1) =
             party_one::KeyGenFirstMsg::create_commitments_with_fixed_secret_share(recovered_secret);
@@ -157,6 +355,187 @@ impl MasterKey1 {
             public: party_one_public,
             private: party_one_private,
             chain_code: chain_code,
+            epoch,
+        }
+    }
+
+    // same as recover_master_key but the public data received from party two is checked against
+    // the recovered secret and the fingerprint party one kept of the key
+    pub fn recover_master_key_verified(
+        recovered_secret: FE,
+        party_two_recovery_first_message: &RecoveryParty2Message1,
+        fingerprint: &BigInt,
+    ) -> Result<MasterKey1, RecoveryError> {
+        let public = &party_two_recovery_first_message.public;
+        let chain_code = &party_two_recovery_first_message.chain_code;
+        let g: GE = ECPoint::generator();
+        if &g * &recovered_secret != public.p1 {
+            return Err(PublicShareMismatch);
+        }
+        if &public.p2 * &recovered_secret != public.q {
+            return Err(PublicKeyMismatch);
+        }
+        if &key_fingerprint(&public.q, chain_code) != fingerprint {
+            return Err(FingerprintMismatch);
+        }
+        Ok(MasterKey1::recover_master_key(
+            recovered_secret,
+            public.clone(),
+            chain_code.clone(),
+            party_two_recovery_first_message.epoch.clone(),
+        ))
+    }
+
+    // full self recovery of party one: the public data from party two is verified, the master key
+    // is rebuilt from the backup secret with a new paillier key and rotated right away so party two
+    // receives the new c_key with PDL and correct key proofs. party two finishes with rotate_first_message
+    // and both parties can sign again. The recovered epoch must follow the ledger, if party one kept it.
+    pub fn recover_master_key_and_rotate(
+        recovered_secret: FE,
+        party_two_recovery_first_message: &RecoveryParty2Message1,
+        fingerprint: &BigInt,
+        cf: &Rotation,
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<(RotationParty1Message1, MasterKey1), RecoveryError> {
+        let master_key_half_recovered = MasterKey1::recover_master_key_verified(
+            recovered_secret,
+            party_two_recovery_first_message,
+            fingerprint,
+        )?;
+        master_key_half_recovered
+            .rotation_first_message(cf, ledger, log, session_id)
+            .or(Err(RecoveryError::EpochMismatch))
+    }
+
+    // helps party two to recover: sends the public data of party two together with a rotation
+    // by one, so party two gets a c_key with a fresh PDL proof instead of taking it as is
+    pub fn recovery_first_message(
+        self,
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<(RecoveryParty1Message1, MasterKey1), Errors> {
+        let public = Party2Public {
+            q: self.public.q.clone(),
+            p2: self.public.p2.clone(),
+            p1: self.public.p1.clone(),
+            paillier_pub: self.public.paillier_pub.clone(),
+            c_key: self.public.c_key.clone(),
+        };
+        let chain_code = self.chain_code.clone();
+        let one: FE = ECScalar::from(&BigInt::one());
+        let (rotation_first_message, master_key) =
+            self.rotation_first_message(&Rotation { rotation: one }, ledger, log, session_id)?;
+        Ok((
+            RecoveryParty1Message1 {
+                public,
+                chain_code,
+                rotation_first_message,
+            },
+            master_key,
+        ))
+    }
+
+    pub fn fingerprint(&self) -> BigInt {
+        key_fingerprint(&self.public.q, &self.chain_code)
+    }
+
+    // every invariant that can be checked without the counter party. Loading a key runs all of
+    // them but the decryption of c_key, see check.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.check()?;
+        let x1 = party_one_share(&self.private);
+        // c_key + Enc(t - x1) decrypts to the random t only if c_key decrypts to x1, so the sign
+        // party_one_decrypt leaves open does not matter
+        let t: FE = ECScalar::new_random();
+        let ek = &self.public.paillier_pub;
+        let c_shift = Paillier::add(
+            ek,
+            RawCiphertext::from(self.public.c_key.clone()),
+            Paillier::encrypt(
+                ek,
+                RawPlaintext::from(t.sub(&x1.get_element()).to_big_int()),
+            ),
+        );
+        let shift = party_one_decrypt(&self.private, ek, &c_shift.0.into_owned());
+        if shift != t && shift.to_big_int() != FE::q() - t.to_big_int() {
+            return Err(ValidationError::CKeyMismatch);
+        }
+        Ok(())
+    }
+
+    // the checks of validate that need no paillier decryption, run on load
+    pub(super) fn check(&self) -> Result<(), ValidationError> {
+        check_point(&self.public.q)?;
+        check_point(&self.public.p1)?;
+        check_point(&self.public.p2)?;
+        check_chain_code(&self.chain_code)?;
+        check_paillier(&self.public.paillier_pub, &self.public.c_key)?;
+        let x1 = party_one_share(&self.private);
+        let g: GE = ECPoint::generator();
+        if &g * &x1 != self.public.p1 {
+            return Err(ValidationError::PublicShareMismatch);
+        }
+        if &self.public.p2 * &x1 != self.public.q {
+            return Err(ValidationError::PublicKeyMismatch);
+        }
+        Ok(())
+    }
+
+    // over all public data and the epoch: unlike fingerprint it changes with every rotation
+    pub fn state_fingerprint(&self) -> BigInt {
+        self.epoch_entry().hash()
+    }
+
+    // entry for the audit log of an operation run with this key, message only for signing
+    pub fn audit_record(
+        &self,
+        operation: Operation,
+        message: Option<&BigInt>,
+        session_id: &BigInt,
+    ) -> AuditRecord {
+        AuditRecord {
+            operation,
+            key_fingerprint: self.fingerprint(),
+            epoch: self.epoch.epoch,
+            message_hash: message.cloned(),
+            session_id: session_id.clone(),
+        }
+    }
+
+    pub fn consistency_first_message(&self) -> ConsistencyMessage {
+        ConsistencyMessage {
+            entry: self.epoch_entry(),
+        }
+    }
+
+    pub fn check_consistency(
+        &self,
+        party_two_consistency_message: &ConsistencyMessage,
+    ) -> Result<(), ConsistencyError> {
+        check_consistency(&self.epoch_entry(), &party_two_consistency_message.entry)
+    }
+
+    pub fn epoch_entry(&self) -> EpochEntry {
+        EpochEntry {
+            epoch: self.epoch.clone(),
+            q: self.public.q.clone(),
+            p1: self.public.p1.clone(),
+            p2: self.public.p2.clone(),
+            paillier_pub: self.public.paillier_pub.clone(),
+            c_key: self.public.c_key.clone(),
+            chain_code: self.chain_code.clone(),
+        }
+    }
+
+    // to be run on the epoch party two announces before a rotation
+    pub fn check_epoch(&self, party_two_epoch: &KeyEpoch) -> Result<(), Errors> {
+        if &self.epoch == party_two_epoch {
+            Ok(())
+        } else {
+            Err(EpochError)
         }
     }
 
@@ -206,17 +585,28 @@ impl MasterKey1 {
         )
     }
 
-    pub fn sign_first_message() -> (party_one::EphKeyGenFirstMsg, party_one::EphEcKeyPair) {
-        party_one::EphKeyGenFirstMsg::create()
+    pub fn sign_first_message(&self) -> (SignParty1Message1, party_one::EphEcKeyPair) {
+        let (eph_key_gen_first_message, eph_ec_key_pair) = party_one::EphKeyGenFirstMsg::create();
+        (
+            SignParty1Message1 {
+                eph_key_gen_first_message,
+                epoch: self.epoch.clone(),
+            },
+            eph_ec_key_pair,
+        )
     }
 
+    // the signature is recorded in the log once it verifies
     pub fn sign_second_message(
         &self,
         party_two_sign_message: &SignMessage,
         eph_key_gen_first_message_party_two: &EphKeyGenFirstMsg,
         eph_ec_key_pair_party1: &party_one::EphEcKeyPair,
         message: &BigInt,
+        log: &mut AuditLog,
+        session_id: &BigInt,
     ) -> Result<party_one::SignatureRecid, Errors> {
+        self.check_epoch(&party_two_sign_message.epoch)?;
         let verify_party_two_second_message =
             party_one::EphKeyGenSecondMsg::verify_commitments_and_dlog_proof(
                 &eph_key_gen_first_message_party_two,
@@ -244,6 +634,7 @@ impl MasterKey1 {
         let verify = party_one::verify(&signature, &self.public.q, message).is_ok();
         if verify {
             if verify_party_two_second_message {
+                log.append(self.audit_record(Operation::Sign, Some(message), session_id));
                 Ok(signature_with_recid)
             } else {
                 Err(SignError)
@@ -253,7 +644,232 @@ impl MasterKey1 {
         }
     }
 
-    pub fn rotation_first_message(self, cf: &Rotation) -> (RotationParty1Message1, MasterKey1) {
+    // the message is computed from the request, the same way as party two does
+    pub fn sign_second_message_request(
+        &self,
+        party_two_sign_message: &SignMessage,
+        eph_key_gen_first_message_party_two: &EphKeyGenFirstMsg,
+        eph_ec_key_pair_party1: &party_one::EphEcKeyPair,
+        request: &SignRequest,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<party_one::SignatureRecid, Errors> {
+        let message = request.message().or(Err(SignError))?;
+        self.sign_second_message(
+            party_two_sign_message,
+            eph_key_gen_first_message_party_two,
+            eph_ec_key_pair_party1,
+            &message,
+            log,
+            session_id,
+        )
+    }
+
+    // the provider decodes what it is asked to sign from the psbt itself: the sighash it computes
+    // for every input, the path of the child key signing it and the payments. Run on the master key.
+    pub fn policy_request(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        network: Network,
+        time: u64,
+    ) -> Result<PolicyRequest, PolicyError> {
+        let jobs = self.psbt_sign_jobs(psbt).or(Err(TransactionInvalid))?;
+        let external = external_outputs(psbt, &self.public.q, |path| {
+            self.derive_child(path).public.q
+        });
+        let txid = psbt.unsigned_tx.txid().to_string();
+        PolicyRequest::from_psbt(txid, &jobs, &external, network, time)
+    }
+
+    // the provider evaluates the request from policy_request before its ephemeral key for the
+    // input is handed out, a denied request never reaches signing and the reason goes back to
+    // party two. The approval is bound to the ephemeral key.
+    pub fn sign_first_message_with_policy(
+        &self,
+        engine: &mut PolicyEngine,
+        request: &PolicyRequest,
+        input: usize,
+    ) -> Result<(SignParty1Message1, party_one::EphEcKeyPair, PolicyApproval), PolicyError> {
+        let approved = request.input(input)?.clone();
+        engine.evaluate(request)?;
+        let (sign_first_message, eph_ec_key_pair) = self.sign_first_message();
+        let approval = PolicyApproval {
+            input: approved,
+            eph_public: sign_first_message
+                .eph_key_gen_first_message
+                .public_share
+                .clone(),
+        };
+        Ok((sign_first_message, eph_ec_key_pair, approval))
+    }
+
+    // run on the master key with the approval of the ephemeral key: the signature must verify on
+    // the message of the approved input under the child key at its path, so party two can't get
+    // anything else co-signed
+    pub fn sign_second_message_with_policy(
+        &self,
+        approval: PolicyApproval,
+        party_two_sign_message: &SignMessage,
+        eph_key_gen_first_message_party_two: &EphKeyGenFirstMsg,
+        eph_ec_key_pair_party1: &party_one::EphEcKeyPair,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<party_one::SignatureRecid, Errors> {
+        if approval.eph_public != eph_ec_key_pair_party1.public_share {
+            return Err(SignError);
+        }
+        self.derive_child(approval.input.path.clone())
+            .sign_second_message(
+                party_two_sign_message,
+                eph_key_gen_first_message_party_two,
+                eph_ec_key_pair_party1,
+                &approval.input.message,
+                log,
+                session_id,
+            )
+    }
+
+    // inputs of the psbt to sign with children of this master key, the same for both parties
+    pub fn psbt_sign_jobs(
+        &self,
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<SignJob>, PsbtError> {
+        sign_jobs(psbt, &self.public.q, |path| {
+            self.derive_child(path).public.q
+        })
+    }
+
+    // party one's side of signing one input of the psbt: the job must be one of its own, the
+    // signature of the child key at its path goes into the psbt
+    pub fn psbt_sign_second_message(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        job: &SignJob,
+        party_two_sign_message: &SignMessage,
+        eph_key_gen_first_message_party_two: &EphKeyGenFirstMsg,
+        eph_ec_key_pair_party1: &party_one::EphEcKeyPair,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<party_one::SignatureRecid, PsbtError> {
+        if !self.psbt_sign_jobs(psbt)?.contains(job) {
+            return Err(SighashMismatch);
+        }
+        let signature = self
+            .derive_child(job.path.clone())
+            .sign_second_message(
+                party_two_sign_message,
+                eph_key_gen_first_message_party_two,
+                eph_ec_key_pair_party1,
+                &job.message,
+                log,
+                session_id,
+            )
+            .or(Err(SignatureInvalid))?;
+        add_ecdsa_signature(psbt, job, &signature)?;
+        Ok(signature)
+    }
+
+    // a copy of the signature goes to party two, which verifies it before use (see
+    // MasterKey2::sign_third_message). Party one computed the signature and keeps it as well.
+    pub fn sign_third_message(signature: &party_one::SignatureRecid) -> SignParty1Message2 {
+        SignParty1Message2 {
+            signature: party_one::SignatureRecid {
+                r: signature.r.clone(),
+                s: signature.s.clone(),
+                recid: signature.recid,
+            },
+        }
+    }
+
+    // adaptor mode: r is taken from the nonce times the adaptor point Y, so the result is a pre
+    // signature that the dlog t of Y completes
+    pub fn adaptor_sign_first_message(
+        &self,
+        adaptor_point: &GE,
+    ) -> (AdaptorSignParty1Message1, AdaptorEphKeyPair) {
+        let g: GE = ECPoint::generator();
+        let k1: FE = ECScalar::new_random();
+        (
+            AdaptorSignParty1Message1 {
+                r1: &g * &k1,
+                partial_y1: PartialEcdh::compute(&k1, adaptor_point),
+                epoch: self.epoch.clone(),
+            },
+            AdaptorEphKeyPair { k1 },
+        )
+    }
+
+    // as with a plain signature, the pre signature is only released if it verifies, and recorded
+    pub fn adaptor_pre_signature(
+        &self,
+        party_two_adaptor_first_message: &AdaptorSignParty2Message1,
+        party_two_adaptor_second_message: &AdaptorSignParty2Message2,
+        eph_key_pair: &AdaptorEphKeyPair,
+        message: &BigInt,
+        adaptor_point: &GE,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<PreSignature, Errors> {
+        self.check_epoch(&party_two_adaptor_second_message.epoch)?;
+        let r2 = &party_two_adaptor_second_message.r2;
+        let partial_y2 = &party_two_adaptor_second_message.partial_y2;
+        let com = HashCommitment::create_commitment_with_user_defined_randomness(
+            &adaptor_nonce_hash(r2, &partial_y2.point),
+            &party_two_adaptor_second_message.blind,
+        );
+        if com != party_two_adaptor_first_message.com {
+            return Err(SignError);
+        }
+        partial_y2.verify(r2, adaptor_point).or(Err(SignError))?;
+
+        // s' = k1^-1 * Dec(c3), the sign is fixed when the signature is adapted
+        let k1 = &eph_key_pair.k1;
+        let s_tag = party_one_decrypt(
+            &self.private,
+            &self.public.paillier_pub,
+            &party_two_adaptor_second_message.c3,
+        );
+        let pre_signature = PreSignature {
+            nonce: r2 * k1,
+            adapted_nonce: &partial_y2.point * k1,
+            s: (s_tag * &k1.invert()).to_big_int(),
+            proof: AdaptorProof {
+                r2: r2.clone(),
+                y2: partial_y2.point.clone(),
+                proof2: partial_y2.proof.clone(),
+                proof1: DleqProof::prove(k1, r2, &partial_y2.point),
+            },
+        };
+        pre_signature
+            .verify(&self.public.q, message, adaptor_point)
+            .or(Err(SignError))?;
+        log.append(self.audit_record(Operation::Sign, Some(message), session_id));
+        Ok(pre_signature)
+    }
+
+    // one shot rotation: party one switches right away, records the current key in the ledger
+    // and the rotation in the log. rotation_prepare_first_message keeps the current key until
+    // party two confirms.
+    pub fn rotation_first_message(
+        self,
+        cf: &Rotation,
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<(RotationParty1Message1, MasterKey1), Errors> {
+        let (rotation_first_message, pending_rotation) = self.rotation_prepare_first_message(cf);
+        ledger.push(pending_rotation.previous)?;
+        let rotated = pending_rotation.rotated;
+        log.append(rotated.audit_record(Operation::Rotation, None, session_id));
+        Ok((rotation_first_message, rotated))
+    }
+
+    // two phase rotation: party one keeps using self until party two confirmed it holds the
+    // matching rotated share, see PendingRotation1::commit
+    pub fn rotation_prepare_first_message(
+        &self,
+        cf: &Rotation,
+    ) -> (RotationParty1Message1, PendingRotation1) {
         let (
             ek_new,
             c_key_new,
@@ -263,9 +879,10 @@ impl MasterKey1 {
             pdl_proof,
             composite_dlog_proof,
         ) = party_one::Party1Private::refresh_private_key(&self.private, &cf.rotation.to_big_int());
-        let master_key_new = self.rotate(cf, new_private, &ek_new, &c_key_new);
+        let rotated = self.rotated(cf, new_private, &ek_new, &c_key_new);
         (
             RotationParty1Message1 {
+                epoch: self.epoch.clone(),
                 ek: ek_new,
                 c_key_new,
                 correct_key_proof,
@@ -273,7 +890,303 @@ impl MasterKey1 {
                 pdl_proof,
                 composite_dlog_proof,
             },
-            master_key_new,
+            PendingRotation1 {
+                rotated,
+                previous: self.epoch_entry(),
+            },
         )
     }
+
+    // share health check: both parties prove their share against a challenge of the other and
+    // party one shows c_key still decrypts to x1. No signature is produced.
+    pub fn health_check_first_message() -> HealthCheckParty1Message1 {
+        HealthCheckParty1Message1 {
+            challenge: new_challenge(),
+        }
+    }
+
+    // c_check is decrypted through the signing code with G as the ephemeral share of party two,
+    // so s = k1^-1 * Dec(c_check). The answer is only committed to at this point.
+    pub fn health_check_second_message(
+        &self,
+        party_one_health_check_first_message: &HealthCheckParty1Message1,
+        party_two_health_check_first_message: &HealthCheckParty2Message1,
+    ) -> Result<(HealthCheckParty1Message2, HealthCheckParty1Message3), Errors> {
+        party_two_health_check_first_message.share_proof.verify(
+            &self.public.p2,
+            &party_one_health_check_first_message.challenge,
+        )?;
+        let x1 = party_one_share(&self.private);
+        let share_proof = ShareProof::prove(&x1, &party_two_health_check_first_message.challenge);
+
+        let g: GE = ECPoint::generator();
+        let (eph_key_gen_first_message, eph_ec_key_pair) = party_one::EphKeyGenFirstMsg::create();
+        let signature = party_one::Signature::compute(
+            &self.private,
+            &party_two_health_check_first_message.c_check,
+            &eph_ec_key_pair,
+            &g,
+        );
+        let r1 = eph_key_gen_first_message.public_share;
+        let blind = BigInt::sample(BLIND_BITS);
+        let com = HashCommitment::create_commitment_with_user_defined_randomness(
+            &health_check_response_hash(&r1, &signature.s),
+            &blind,
+        );
+        Ok((
+            HealthCheckParty1Message2 { share_proof, com },
+            HealthCheckParty1Message3 {
+                r1,
+                s: signature.s,
+                blind,
+            },
+        ))
+    }
+
+    // the answer is released only if c_check was built from c_key as claimed, otherwise it could
+    // leak x1 to a party two that picked c_check freely
+    pub fn health_check_third_message(
+        &self,
+        party_two_health_check_first_message: &HealthCheckParty2Message1,
+        party_two_health_check_second_message: &HealthCheckParty2Message2,
+        party_one_health_check_third_message: HealthCheckParty1Message3,
+    ) -> Result<HealthCheckParty1Message3, Errors> {
+        let c_check = health_check_ciphertext(
+            &self.public.paillier_pub,
+            &self.public.c_key,
+            &party_two_health_check_second_message.rho,
+            &party_two_health_check_second_message.sigma,
+            &party_two_health_check_second_message.randomness,
+        );
+        if c_check != party_two_health_check_first_message.c_check {
+            return Err(HealthCheckError);
+        }
+        Ok(party_one_health_check_third_message)
+    }
+
+    // two party ecdh with an external point R, e.g. the ephemeral point of an ecies ciphertext:
+    // party one sends x1 * R, party two multiplies it by x2 to get x1 * x2 * R
+    pub fn ecdh_first_message(&self, point: &GE) -> EcdhParty1Message1 {
+        let x1 = party_one_share(&self.private);
+        EcdhParty1Message1 {
+            partial_ecdh: PartialEcdh::compute(&x1, point),
+        }
+    }
+
+    // only if party one needs the shared point as well
+    pub fn ecdh_second_message(
+        &self,
+        party_one_ecdh_first_message: &EcdhParty1Message1,
+        party_two_ecdh_first_message: &EcdhParty2Message1,
+    ) -> Result<GE, EcdhError> {
+        party_two_ecdh_first_message.partial_ecdh.verify(
+            &self.public.p2,
+            &party_one_ecdh_first_message.partial_ecdh.point,
+        )?;
+        Ok(party_two_ecdh_first_message.partial_ecdh.point.clone())
+    }
+
+    // cooperative export: party one releases x1 to party two, encrypted under the
+    // one time key of party two, with a proof that the plaintext is the dlog of p1.
+    // party one is done with this key after the message is sent.
+    pub fn export_first_message(
+        self,
+        party_two_export_first_message: &ExportParty2Message1,
+    ) -> (ExportParty1Message1, RetiredMasterKey) {
+        let (encryptions, proof) =
+            encrypt_party_one_share(&self.private, &party_two_export_first_message.y_pub);
+        (ExportParty1Message1 { encryptions, proof }, self.retire())
+    }
+
+    // provider replacement, run by the new provider: one time key for the share of the old provider
+    pub fn replace_provider_first_message() -> (ReplaceProviderMessage1, FE) {
+        let (y_pub, y) = one_time_key();
+        (ReplaceProviderMessage1 { y_pub }, y)
+    }
+
+    // run by the old provider: hands over x1 together with the public data of the key
+    pub fn replace_provider_second_message(
+        self,
+        new_provider_first_message: &ReplaceProviderMessage1,
+    ) -> (ReplaceProviderMessage2, RetiredMasterKey) {
+        let (encryptions, proof) =
+            encrypt_party_one_share(&self.private, &new_provider_first_message.y_pub);
+        let message = ReplaceProviderMessage2 {
+            encryptions,
+            proof,
+            public: self.public.clone(),
+            chain_code: self.chain_code.clone(),
+            epoch: self.epoch.clone(),
+        };
+        (message, self.retire())
+    }
+
+    // run by the new provider after a coin flip with party two. x1 is recovered and then rotated
+    // with a fresh paillier key, so x1 of the old provider no longer matches the share of party two.
+    // The rotation is two phase: party two answers with rotation_prepare_first_message and the new
+    // provider switches with PendingRotation1::commit, which records the key it took over.
+    pub fn replace_provider_third_message(
+        y: &FE,
+        old_provider_second_message: &ReplaceProviderMessage2,
+        cf: &Rotation,
+    ) -> Result<(RotationParty1Message1, PendingRotation1), Errors> {
+        let public = &old_provider_second_message.public;
+        let x1 = decrypt_share(
+            y,
+            &old_provider_second_message.encryptions,
+            &old_provider_second_message.proof,
+            &public.p1,
+        )
+        .or(Err(ProviderReplaceError))?;
+        if &public.p2 * &x1 != public.q {
+            return Err(ProviderReplaceError);
+        }
+        let master_key_recovered = MasterKey1::recover_master_key(
+            x1,
+            public.clone(),
+            old_provider_second_message.chain_code.clone(),
+            old_provider_second_message.epoch.clone(),
+        );
+        Ok(master_key_recovered.rotation_prepare_first_message(cf))
+    }
+
+    // optional last step of a rotation: the chain code is replaced by the output of a new run of
+    // the chain_code::two_party exchange, bound to the current epoch. Children are not carried
+    // over, see RetiredChainCode.
+    pub fn chain_code_first_message(
+        &self,
+    ) -> (ChainCodeParty1Message1, CommWitness<GE>, EcKeyPair<GE>) {
+        let (first_message, comm_witness, ec_key_pair) = ChainCode1::chain_code_first_message();
+        (
+            ChainCodeParty1Message1 {
+                first_message,
+                epoch: self.epoch.clone(),
+            },
+            comm_witness,
+            ec_key_pair,
+        )
+    }
+
+    pub fn chain_code_second_message(
+        &self,
+        comm_witness: CommWitness<GE>,
+        ec_key_pair: &EcKeyPair<GE>,
+        party_two_chain_code_first_message: &ChainCodeParty2Message1,
+    ) -> Result<(ChainCodeParty1Message2, PendingChainCode1), Errors> {
+        let first_message = &party_two_chain_code_first_message.first_message;
+        let second_message =
+            Party1SecondMessage::verify_and_decommit(comm_witness, &first_message.d_log_proof)
+                .or(Err(ChainCodeError))?;
+        let chain_code =
+            ChainCode1::compute_chain_code(ec_key_pair, &first_message.public_share).chain_code;
+        check_chain_code(&chain_code).or(Err(ChainCodeError))?;
+        let (refreshed, retired) = self.with_chain_code(chain_code);
+        Ok((
+            ChainCodeParty1Message2 { second_message },
+            PendingChainCode1 {
+                refreshed,
+                retired,
+                previous: self.epoch_entry(),
+            },
+        ))
+    }
+
+    fn with_chain_code(&self, chain_code: BigInt) -> (MasterKey1, RetiredChainCode) {
+        let retired = RetiredChainCode {
+            q: self.public.q.clone(),
+            chain_code: self.chain_code.clone(),
+        };
+        (
+            MasterKey1 {
+                public: self.public.clone(),
+                private: self.private.clone(),
+                chain_code,
+                epoch: KeyEpoch::next(&self.epoch_entry()),
+            },
+            retired,
+        )
+    }
+
+    pub fn retire(self) -> RetiredMasterKey {
+        RetiredMasterKey {
+            q: self.public.q,
+            chain_code: self.chain_code,
+        }
+    }
+}
+
+impl PendingChainCode1 {
+    // party two sent the consistency message of its refreshed key: party one switches if both
+    // hold the same chain code and answers with its own, for party two to commit. The entry of
+    // the current key goes to the ledger.
+    pub fn commit(
+        self,
+        party_two_consistency_message: &ConsistencyMessage,
+        ledger: &mut EpochLedger,
+    ) -> Result<(ConsistencyMessage, MasterKey1, RetiredChainCode), ConsistencyError> {
+        self.refreshed
+            .check_consistency(party_two_consistency_message)?;
+        ledger.push(self.previous).or(Err(EpochMismatch))?;
+        Ok((
+            self.refreshed.consistency_first_message(),
+            self.refreshed,
+            self.retired,
+        ))
+    }
+}
+
+impl PendingRotation1 {
+    // party two verified the rotation and announced the epoch of its rotated key: party one
+    // records the current key in the ledger, switches to the rotated key and confirms. The
+    // current key should be dropped only after this returns Ok. The rotation goes to the log.
+    pub fn commit(
+        self,
+        party_two_rotation_first_message: &RotationParty2Message1,
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<(RotationParty1Message2, MasterKey1), Errors> {
+        if party_two_rotation_first_message.epoch != self.rotated.epoch {
+            return Err(RotationError);
+        }
+        ledger.push(self.previous)?;
+        log.append(
+            self.rotated
+                .audit_record(Operation::Rotation, None, session_id),
+        );
+        Ok((
+            RotationParty1Message2 {
+                epoch: self.rotated.epoch.clone(),
+            },
+            self.rotated,
+        ))
+    }
+
+    // party two rejected the rotation or did not answer: the rotated key is dropped and party one
+    // stays on the current epoch, returned here. Party two drops its pending rotation through
+    // resolve when it sees this epoch in the next message.
+    pub fn abort(self) -> KeyEpoch {
+        self.previous.epoch
+    }
+
+    // recovery path for an interrupted rotation: the epoch party two presents in its next
+    // message decides which key is kept
+    pub fn resolve(
+        self,
+        current: MasterKey1,
+        party_two_epoch: &KeyEpoch,
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<MasterKey1, Errors> {
+        if party_two_epoch != &self.rotated.epoch {
+            return Ok(current);
+        }
+        ledger.push(self.previous)?;
+        log.append(
+            self.rotated
+                .audit_record(Operation::Rotation, None, session_id),
+        );
+        Ok(self.rotated)
+    }
 }
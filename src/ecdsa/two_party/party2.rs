@@ -10,24 +10,65 @@
     @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
 */
 
+use centipede::juggling::proof_system::{Helgamalsegmented, Proof};
+use curv::arithmetic::traits::Samplable;
+use curv::arithmetic::One;
+use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
+use curv::cryptographic_primitives::commitments::traits::Commitment;
+use curv::cryptographic_primitives::twoparty::dh_key_exchange_variant_with_pok_comm::{
+    EcKeyPair, Party2FirstMessage,
+};
 use curv::BigInt;
 use curv::elliptic::curves::secp256_k1::{FE, GE};
-use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one::EphKeyGenFirstMsg as Party1EphKeyGenFirstMsg;
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one::KeyGenFirstMsg as Party1KeyGenFirstMsg;
 
-use super::party1::{KeyGenParty1Message2, RotationParty1Message1};
+use super::party1::{
+    AdaptorSignParty1Message1, ChainCodeParty1Message1, ChainCodeParty1Message2,
+    EcdhParty1Message1, ExportParty1Message1, HealthCheckParty1Message1, HealthCheckParty1Message2,
+    HealthCheckParty1Message3, KeyGenParty1Message2, RecoveryParty1Message1,
+    RotationParty1Message1, RotationParty1Message2, SignParty1Message1, SignParty1Message2,
+};
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
 
-use super::hd_key;
-use super::{MasterKey1, MasterKey2, Party2Public};
+use super::{adaptor_c3, adaptor_nonce_hash};
+use super::{check_consistency, decrypt_share, encrypt_party_two_share, hd_key, key_fingerprint};
+use super::{health_check_ciphertext, health_check_response_hash};
+use super::{one_time_key, party_two_share};
+use super::{
+    ConsistencyMessage, EpochEntry, EpochLedger, ExportedKey, KeyEpoch, MasterKey1, MasterKey2,
+    Party1Public, Party2Public, RetiredChainCode, RetiredMasterKey,
+};
+use audit::{AuditLog, AuditRecord, Operation};
+use bitcoin::psbt::PartiallySignedTransaction;
+use chain_code::two_party::party2::ChainCode2;
 use curv::elliptic::curves::traits::ECPoint;
 use curv::elliptic::curves::traits::ECScalar;
+use ecdh::PartialEcdh;
+use health_check::{new_challenge, ShareProof};
+use message_signing::verify_recid;
+use psbt::{sign_jobs, SignJob};
 use rotation::two_party::Rotation;
+use sign_request::SignRequest;
+use validation::{check_chain_code, check_paillier, check_point};
+use ConsistencyError::{self, EpochMismatch};
+use EcdhError;
+use Errors::{
+    self, ChainCodeError, DeviceMoveError, EpochError, ExportError, HealthCheckError,
+    RotationError, SignError,
+};
+use PsbtError::{self, SighashMismatch, SignatureInvalid};
+use RecoveryError::{
+    self, FingerprintMismatch, PaillierProofError, PublicKeyMismatch, PublicShareMismatch,
+};
+use ValidationError;
+
+const BLIND_BITS: usize = 256;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SignMessage {
     pub partial_sig: party_two::PartialSig,
     pub second_message: party_two::EphKeyGenSecondMsg,
+    pub epoch: KeyEpoch,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,14 +76,118 @@ pub struct Party2SecondMessage {
     pub key_gen_second_message: party_two::KeyGenSecondMsg,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportParty2Message1 {
+    pub y_pub: GE,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryParty2Message1 {
+    pub public: Party1Public,
+    pub chain_code: BigInt,
+    pub epoch: KeyEpoch,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotationParty2Message1 {
+    // epoch of the rotated key of party two
+    pub epoch: KeyEpoch,
+}
+
+// the rotated key of a two phase rotation. Should be stored next to the current key until the
+// rotation is either committed or aborted.
+#[derive(Serialize, Deserialize)]
+pub struct PendingRotation2 {
+    pub rotated: MasterKey2,
+    // public data of the current key, recorded in the epoch ledger on the switch
+    pub previous: EpochEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChainCodeParty2Message1 {
+    pub first_message: Party2FirstMessage<GE>,
+}
+
+// the key with the new chain code, see PendingChainCode2::commit
+#[derive(Serialize, Deserialize)]
+pub struct PendingChainCode2 {
+    pub refreshed: MasterKey2,
+    pub retired: RetiredChainCode,
+    pub previous: EpochEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthCheckParty2Message1 {
+    pub challenge: BigInt,
+    pub share_proof: ShareProof,
+    // Enc(rho) + sigma * c_key
+    pub c_check: BigInt,
+}
+
+// how c_check was built, held back by party two until party one committed to its answer
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthCheckParty2Message2 {
+    pub rho: FE,
+    pub sigma: FE,
+    pub randomness: BigInt,
+}
+
+// commitment to the nonce of party two in adaptor mode
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdaptorSignParty2Message1 {
+    pub com: BigInt,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdaptorSignParty2Message2 {
+    pub r2: GE,
+    // k2 * Y
+    pub partial_y2: PartialEcdh,
+    pub blind: BigInt,
+    pub c3: BigInt,
+    pub epoch: KeyEpoch,
+}
+
+// nonce of party two in adaptor mode with the opening of its commitment
+pub struct AdaptorEphKeyPair {
+    k2: FE,
+    r2: GE,
+    partial_y2: PartialEcdh,
+    blind: BigInt,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EcdhParty2Message1 {
+    pub partial_ecdh: PartialEcdh,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MoveDeviceMessage1 {
+    pub y_pub: GE,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MoveDeviceMessage2 {
+    pub encryptions: Helgamalsegmented,
+    pub proof: Proof,
+    pub public: Party2Public,
+    pub chain_code: BigInt,
+    pub epoch: KeyEpoch,
+}
+
 impl MasterKey2 {
-    pub fn rotate(self, cf: &Rotation, new_paillier: &party_two::PaillierPublic) -> MasterKey2 {
+    fn rotate(self, cf: &Rotation, new_paillier: &party_two::PaillierPublic) -> MasterKey2 {
+        self.rotated(cf, new_paillier)
+    }
+
+    fn rotated(&self, cf: &Rotation, new_paillier: &party_two::PaillierPublic) -> MasterKey2 {
+        let epoch = KeyEpoch::next(&self.epoch_entry());
         let rand_str_invert_fe = cf.rotation.invert();
         let c_key_new = new_paillier.encrypted_secret_share.clone();
 
         //TODO: use proper set functions
         let public = Party2Public {
-            q: self.public.q,
+            q: self.public.q.clone(),
             p1: self.public.p1.clone() * &cf.rotation,
             p2: &self.public.p2 * &cf.rotation.invert(),
             paillier_pub: new_paillier.ek.clone(),
@@ -54,11 +199,25 @@ impl MasterKey2 {
                 &self.private,
                 &rand_str_invert_fe.to_big_int(),
             ),
-            chain_code: self.chain_code,
+            chain_code: self.chain_code.clone(),
+            epoch,
         }
     }
 
-    pub fn get_child(&self, location_in_hir: Vec<BigInt>) -> MasterKey2 {
+    // the derivation is recorded in the log. derive_child is the same without a record, for the
+    // public keys of children only.
+    pub fn get_child(
+        &self,
+        location_in_hir: Vec<BigInt>,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> MasterKey2 {
+        let child = self.derive_child(location_in_hir);
+        log.append(child.audit_record(Operation::GetChild, None, session_id));
+        child
+    }
+
+    pub(super) fn derive_child(&self, location_in_hir: Vec<BigInt>) -> MasterKey2 {
         let (public_key_new_child, f_l_new, cc_new) =
             hd_key(location_in_hir, &self.public.q, &self.chain_code);
 
@@ -76,14 +235,35 @@ impl MasterKey2 {
                 &f_l_new.to_big_int(),
             ),
             chain_code: cc_new.bytes_compressed_to_big_int(),
+            epoch: self.epoch.clone(),
         }
     }
 
+    // last step of key gen, recorded in the log
     pub fn set_master_key(
         chain_code: &BigInt,
         ec_key_pair_party2: &party_two::EcKeyPair,
         party1_second_message_public_share: &GE,
         paillier_public: &party_two::PaillierPublic,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> MasterKey2 {
+        let master_key = MasterKey2::assemble(
+            chain_code,
+            ec_key_pair_party2,
+            party1_second_message_public_share,
+            paillier_public,
+        );
+        log.append(master_key.audit_record(Operation::KeyGen, None, session_id));
+        master_key
+    }
+
+    // the master key from key gen data, also used to rebuild keys in recovery
+    pub(super) fn assemble(
+        chain_code: &BigInt,
+        ec_key_pair_party2: &party_two::EcKeyPair,
+        party1_second_message_public_share: &GE,
+        paillier_public: &party_two::PaillierPublic,
     ) -> MasterKey2 {
         let party2_public = Party2Public {
             q: party_two::compute_pubkey(ec_key_pair_party2, party1_second_message_public_share),
@@ -97,6 +277,7 @@ impl MasterKey2 {
             public: party2_public,
             private: party2_private,
             chain_code: chain_code.clone(),
+            epoch: KeyEpoch::genesis(),
         }
     }
 
@@ -111,19 +292,22 @@ impl MasterKey2 {
             party_one::Party1Private::set_private_key(&ec_key_pair_party1, &paillier_key_pair);
 
         // set master keys:
-        MasterKey1::set_master_key(
+        let mut master_key = MasterKey1::assemble(
             &self.chain_code,
             party_one_private,
             &ec_key_pair_party1.public_share,
             &self.public.p2,
             paillier_key_pair,
-        )
+        );
+        master_key.epoch = self.epoch.clone();
+        master_key
     }
 
     pub fn recover_master_key(
         recovered_secret: FE,
         party_two_public: Party2Public,
         chain_code: BigInt,
+        epoch: KeyEpoch,
     ) -> MasterKey2 {
         //  master key of party two from party two secret recovery:
         // q1 (public key of party one), chain code, and public paillier data (c_key, ek) are needed for
@@ -133,6 +317,7 @@ impl MasterKey2 {
         // (2) party 2 lost the public data as well. in this case only party 1 can help with the public data.
         //     if party 1 becomes malicious it means two failures at the same time from which the system will not be able to recover.
         //     Therefore no point of running any secure protocol with party 1 and just accept the public data as is.
+        //     recover_master_key_verified checks the public data against a stored fingerprint of the key.
 
         let (_, ec_key_pair_party2) =
             party_two::KeyGenFirstMsg::create_with_fixed_secret_share(recovered_secret);
@@ -141,6 +326,143 @@ impl MasterKey2 {
             public: party_two_public,
             private: party2_private,
             chain_code,
+            epoch,
+        }
+    }
+
+    // same as recover_master_key but the public data received from party one is checked against
+    // the recovered secret and the fingerprint party two kept of the key. c_key is not taken as is:
+    // party one rotates by one and party two verifies the PDL and correct key proofs of the result
+    pub fn recover_master_key_verified(
+        recovered_secret: FE,
+        party_one_recovery_first_message: &RecoveryParty1Message1,
+        fingerprint: &BigInt,
+        party_one_rotation_first_message_salt: &[u8],
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<MasterKey2, RecoveryError> {
+        let public = &party_one_recovery_first_message.public;
+        let chain_code = &party_one_recovery_first_message.chain_code;
+        let g: GE = ECPoint::generator();
+        if &g * &recovered_secret != public.p2 {
+            return Err(PublicShareMismatch);
+        }
+        if &public.p1 * &recovered_secret != public.q {
+            return Err(PublicKeyMismatch);
+        }
+        if &key_fingerprint(&public.q, chain_code) != fingerprint {
+            return Err(FingerprintMismatch);
+        }
+        let one: FE = ECScalar::from(&BigInt::one());
+        MasterKey2::recover_master_key(
+            recovered_secret,
+            public.clone(),
+            chain_code.clone(),
+            party_one_recovery_first_message
+                .rotation_first_message
+                .epoch
+                .clone(),
+        )
+        .rotate_first_message(
+            &Rotation { rotation: one },
+            &party_one_recovery_first_message.rotation_first_message,
+            party_one_rotation_first_message_salt,
+            ledger,
+            log,
+            session_id,
+        )
+        .or(Err(PaillierProofError))
+    }
+
+    // helps party one to recover: the public data of party one as seen by party two
+    pub fn recovery_first_message(&self) -> RecoveryParty2Message1 {
+        RecoveryParty2Message1 {
+            public: Party1Public {
+                q: self.public.q.clone(),
+                p1: self.public.p1.clone(),
+                p2: self.public.p2.clone(),
+                paillier_pub: self.public.paillier_pub.clone(),
+                c_key: self.public.c_key.clone(),
+            },
+            chain_code: self.chain_code.clone(),
+            epoch: self.epoch.clone(),
+        }
+    }
+
+    pub fn fingerprint(&self) -> BigInt {
+        key_fingerprint(&self.public.q, &self.chain_code)
+    }
+
+    // every invariant that can be checked without the counter party. Runs on load as well.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        check_point(&self.public.q)?;
+        check_point(&self.public.p1)?;
+        check_point(&self.public.p2)?;
+        check_chain_code(&self.chain_code)?;
+        check_paillier(&self.public.paillier_pub, &self.public.c_key)?;
+        let x2 = party_two_share(&self.private);
+        let g: GE = ECPoint::generator();
+        if &g * &x2 != self.public.p2 {
+            return Err(ValidationError::PublicShareMismatch);
+        }
+        if &self.public.p1 * &x2 != self.public.q {
+            return Err(ValidationError::PublicKeyMismatch);
+        }
+        Ok(())
+    }
+
+    // over all public data and the epoch: unlike fingerprint it changes with every rotation
+    pub fn state_fingerprint(&self) -> BigInt {
+        self.epoch_entry().hash()
+    }
+
+    // entry for the audit log of an operation run with this key, message only for signing
+    pub fn audit_record(
+        &self,
+        operation: Operation,
+        message: Option<&BigInt>,
+        session_id: &BigInt,
+    ) -> AuditRecord {
+        AuditRecord {
+            operation,
+            key_fingerprint: self.fingerprint(),
+            epoch: self.epoch.epoch,
+            message_hash: message.cloned(),
+            session_id: session_id.clone(),
+        }
+    }
+
+    pub fn consistency_first_message(&self) -> ConsistencyMessage {
+        ConsistencyMessage {
+            entry: self.epoch_entry(),
+        }
+    }
+
+    pub fn check_consistency(
+        &self,
+        party_one_consistency_message: &ConsistencyMessage,
+    ) -> Result<(), ConsistencyError> {
+        check_consistency(&self.epoch_entry(), &party_one_consistency_message.entry)
+    }
+
+    pub fn epoch_entry(&self) -> EpochEntry {
+        EpochEntry {
+            epoch: self.epoch.clone(),
+            q: self.public.q.clone(),
+            p1: self.public.p1.clone(),
+            p2: self.public.p2.clone(),
+            paillier_pub: self.public.paillier_pub.clone(),
+            c_key: self.public.c_key.clone(),
+            chain_code: self.chain_code.clone(),
+        }
+    }
+
+    pub fn check_epoch(&self, party_one_epoch: &KeyEpoch) -> Result<(), Errors> {
+        if &self.epoch == party_one_epoch {
+            Ok(())
+        } else {
+            Err(EpochError)
         }
     }
 
@@ -213,13 +535,18 @@ impl MasterKey2 {
     ) {
         party_two::EphKeyGenFirstMsg::create_commitments()
     }
+    // party two co-signs here, the message is recorded in the log
     pub fn sign_second_message(
         &self,
         ec_key_pair_party2: &party_two::EphEcKeyPair,
         eph_comm_witness: party_two::EphCommWitness,
-        eph_party1_first_message: &Party1EphKeyGenFirstMsg,
+        party_one_sign_first_message: &SignParty1Message1,
         message: &BigInt,
-    ) -> SignMessage {
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<SignMessage, Errors> {
+        self.check_epoch(&party_one_sign_first_message.epoch)?;
+        let eph_party1_first_message = &party_one_sign_first_message.eph_key_gen_first_message;
         let eph_key_gen_second_message = party_two::EphKeyGenSecondMsg::verify_and_decommit(
             eph_comm_witness,
             eph_party1_first_message,
@@ -234,22 +561,212 @@ impl MasterKey2 {
             &eph_party1_first_message.public_share,
             message,
         );
-        SignMessage {
+        log.append(self.audit_record(Operation::Sign, Some(message), session_id));
+        Ok(SignMessage {
             partial_sig,
             second_message: eph_key_gen_second_message,
+            epoch: self.epoch.clone(),
+        })
+    }
+
+    // the message is computed from the request, the same way as party one does
+    pub fn sign_second_message_request(
+        &self,
+        ec_key_pair_party2: &party_two::EphEcKeyPair,
+        eph_comm_witness: party_two::EphCommWitness,
+        party_one_sign_first_message: &SignParty1Message1,
+        request: &SignRequest,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<SignMessage, Errors> {
+        let message = request.message().or(Err(SignError))?;
+        self.sign_second_message(
+            ec_key_pair_party2,
+            eph_comm_witness,
+            party_one_sign_first_message,
+            &message,
+            log,
+            session_id,
+        )
+    }
+
+    // inputs of the psbt to sign with children of this master key, the same for both parties
+    pub fn psbt_sign_jobs(
+        &self,
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<SignJob>, PsbtError> {
+        sign_jobs(psbt, &self.public.q, |path| {
+            self.derive_child(path).public.q
+        })
+    }
+
+    // party two's side of signing one input of the psbt: the job must be one of its own, it is
+    // signed with the child key at its path
+    pub fn psbt_sign_second_message(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        job: &SignJob,
+        ec_key_pair_party2: &party_two::EphEcKeyPair,
+        eph_comm_witness: party_two::EphCommWitness,
+        party_one_sign_first_message: &SignParty1Message1,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<SignMessage, PsbtError> {
+        if !self.psbt_sign_jobs(psbt)?.contains(job) {
+            return Err(SighashMismatch);
         }
+        self.derive_child(job.path.clone())
+            .sign_second_message(
+                ec_key_pair_party2,
+                eph_comm_witness,
+                party_one_sign_first_message,
+                &job.message,
+                log,
+                session_id,
+            )
+            .or(Err(SignatureInvalid))
+    }
+
+    // party two checks the signature against the message it meant to sign, a provider that
+    // signed anything else is caught here, and that the recovery id recovers q
+    pub fn sign_third_message(
+        &self,
+        party_one_sign_second_message: &SignParty1Message2,
+        message: &BigInt,
+    ) -> Result<party_one::SignatureRecid, Errors> {
+        let signature_recid = &party_one_sign_second_message.signature;
+        let signature = party_one::Signature {
+            r: signature_recid.r.clone(),
+            s: signature_recid.s.clone(),
+        };
+        party_one::verify(&signature, &self.public.q, message).or(Err(SignError))?;
+        verify_recid(message, signature_recid, &self.public.q).or(Err(SignError))?;
+        Ok(party_one::SignatureRecid {
+            r: signature.r,
+            s: signature.s,
+            recid: signature_recid.recid,
+        })
+    }
+
+    pub fn adaptor_sign_first_message(
+        adaptor_point: &GE,
+    ) -> (AdaptorSignParty2Message1, AdaptorEphKeyPair) {
+        let g: GE = ECPoint::generator();
+        let k2: FE = ECScalar::new_random();
+        let r2 = &g * &k2;
+        let partial_y2 = PartialEcdh::compute(&k2, adaptor_point);
+        let blind = BigInt::sample(BLIND_BITS);
+        let com = HashCommitment::create_commitment_with_user_defined_randomness(
+            &adaptor_nonce_hash(&r2, &partial_y2.point),
+            &blind,
+        );
+        (
+            AdaptorSignParty2Message1 { com },
+            AdaptorEphKeyPair {
+                k2,
+                r2,
+                partial_y2,
+                blind,
+            },
+        )
+    }
+
+    // c3 as the signing code builds it, with k1 * Y as the nonce share of party one, so
+    // r is taken from k1 * k2 * Y. The message is recorded in the log.
+    pub fn adaptor_sign_second_message(
+        &self,
+        eph_key_pair: AdaptorEphKeyPair,
+        party_one_adaptor_first_message: &AdaptorSignParty1Message1,
+        message: &BigInt,
+        adaptor_point: &GE,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<AdaptorSignParty2Message2, Errors> {
+        self.check_epoch(&party_one_adaptor_first_message.epoch)?;
+        party_one_adaptor_first_message
+            .partial_y1
+            .verify(&party_one_adaptor_first_message.r1, adaptor_point)
+            .or(Err(SignError))?;
+        let c3 = adaptor_c3(
+            &self.public.paillier_pub,
+            &self.public.c_key,
+            &party_two_share(&self.private),
+            &eph_key_pair.k2,
+            &party_one_adaptor_first_message.partial_y1.point,
+            message,
+        );
+        log.append(self.audit_record(Operation::Sign, Some(message), session_id));
+        Ok(AdaptorSignParty2Message2 {
+            r2: eph_key_pair.r2,
+            partial_y2: eph_key_pair.partial_y2,
+            blind: eph_key_pair.blind,
+            c3,
+            epoch: self.epoch.clone(),
+        })
     }
 
     // party2 receives new paillier key and new c_key = Enc(x1_new) = Enc(r*x_1).
     // party2 can compute locally the updated Q1. This is why this set of messages
     // is rotation and not new key gen.
     // party2 needs to verify range proof on c_key_new and correct key proof on the new paillier keys
+    // one shot: party two switches right away, records the current key in the ledger and the
+    // rotation in the log
     pub fn rotate_first_message(
         self,
         cf: &Rotation,
         party_one_rotation_first_message: &RotationParty1Message1,
-        party_one_rotation_first_message_salt: &[u8]
+        party_one_rotation_first_message_salt: &[u8],
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
     ) -> Result<MasterKey2, ()> {
+        let party_two_paillier = self.verify_rotation_first_message(
+            cf,
+            party_one_rotation_first_message,
+            party_one_rotation_first_message_salt,
+        )?;
+        ledger.push(self.epoch_entry()).or(Err(()))?;
+        let rotated = self.rotate(cf, &party_two_paillier);
+        log.append(rotated.audit_record(Operation::Rotation, None, session_id));
+        Ok(rotated)
+    }
+
+    // two phase rotation: same checks as rotate_first_message but party two keeps using self
+    // until party one confirms, see PendingRotation2::commit
+    pub fn rotation_prepare_first_message(
+        &self,
+        cf: &Rotation,
+        party_one_rotation_first_message: &RotationParty1Message1,
+        party_one_rotation_first_message_salt: &[u8],
+    ) -> Result<(RotationParty2Message1, PendingRotation2), Errors> {
+        let party_two_paillier = self
+            .verify_rotation_first_message(
+                cf,
+                party_one_rotation_first_message,
+                party_one_rotation_first_message_salt,
+            )
+            .or(Err(RotationError))?;
+        let rotated = self.rotated(cf, &party_two_paillier);
+        Ok((
+            RotationParty2Message1 {
+                epoch: rotated.epoch.clone(),
+            },
+            PendingRotation2 {
+                rotated,
+                previous: self.epoch_entry(),
+            },
+        ))
+    }
+
+    fn verify_rotation_first_message(
+        &self,
+        cf: &Rotation,
+        party_one_rotation_first_message: &RotationParty1Message1,
+        party_one_rotation_first_message_salt: &[u8],
+    ) -> Result<party_two::PaillierPublic, ()> {
+        if party_one_rotation_first_message.epoch != self.epoch {
+            return Err(());
+        }
         let party_two_paillier = party_two::PaillierPublic {
             ek: party_one_rotation_first_message.ek.clone(),
             encrypted_secret_share: party_one_rotation_first_message.c_key_new.clone(),
@@ -260,21 +777,330 @@ impl MasterKey2 {
             &party_one_rotation_first_message.pdl_statement,
             &party_one_rotation_first_message.pdl_proof,
             &party_two_paillier,
-            &(self.public.p1 * &cf.rotation),
+            &(&self.public.p1 * &cf.rotation),
         );
 
         let correct_key_verify = party_one_rotation_first_message
             .correct_key_proof
             .verify(&party_two_paillier.ek, party_one_rotation_first_message_salt);
 
-        let master_key = self.rotate(cf, &party_two_paillier);
-
         match pdl_verify {
             Ok(_proof) => match correct_key_verify {
-                Ok(_proof) => Ok(master_key),
+                Ok(_proof) => Ok(party_two_paillier),
                 Err(_correct_key_error) => Err(()),
             },
             Err(_range_proof_error) => Err(()),
         }
     }
+
+    pub fn health_check_first_message(
+        &self,
+        party_one_health_check_first_message: &HealthCheckParty1Message1,
+    ) -> (HealthCheckParty2Message1, HealthCheckParty2Message2) {
+        let x2 = party_two_share(&self.private);
+        let share_proof = ShareProof::prove(&x2, &party_one_health_check_first_message.challenge);
+        let rho: FE = ECScalar::new_random();
+        let sigma: FE = ECScalar::new_random();
+        let randomness = BigInt::sample_below(&self.public.paillier_pub.n);
+        let c_check = health_check_ciphertext(
+            &self.public.paillier_pub,
+            &self.public.c_key,
+            &rho,
+            &sigma,
+            &randomness,
+        );
+        (
+            HealthCheckParty2Message1 {
+                challenge: new_challenge(),
+                share_proof,
+                c_check,
+            },
+            HealthCheckParty2Message2 {
+                rho,
+                sigma,
+                randomness,
+            },
+        )
+    }
+
+    pub fn health_check_second_message(
+        &self,
+        party_two_health_check_first_message: &HealthCheckParty2Message1,
+        party_one_health_check_second_message: &HealthCheckParty1Message2,
+        party_two_health_check_second_message: HealthCheckParty2Message2,
+    ) -> Result<HealthCheckParty2Message2, Errors> {
+        party_one_health_check_second_message.share_proof.verify(
+            &self.public.p1,
+            &party_two_health_check_first_message.challenge,
+        )?;
+        Ok(party_two_health_check_second_message)
+    }
+
+    // s * R1 = rho * G + sigma * p1, up to the sign of s which the signing code normalizes
+    pub fn health_check_third_message(
+        &self,
+        party_two_health_check_second_message: &HealthCheckParty2Message2,
+        party_one_health_check_second_message: &HealthCheckParty1Message2,
+        party_one_health_check_third_message: &HealthCheckParty1Message3,
+    ) -> Result<(), Errors> {
+        let r1 = &party_one_health_check_third_message.r1;
+        let s = &party_one_health_check_third_message.s;
+        let com = HashCommitment::create_commitment_with_user_defined_randomness(
+            &health_check_response_hash(r1, s),
+            &party_one_health_check_third_message.blind,
+        );
+        if com != party_one_health_check_second_message.com {
+            return Err(HealthCheckError);
+        }
+        let g: GE = ECPoint::generator();
+        let expected = &(&g * &party_two_health_check_second_message.rho)
+            + &(&self.public.p1 * &party_two_health_check_second_message.sigma);
+        let s_fe: FE = ECScalar::from(s);
+        let s_neg_fe: FE = ECScalar::from(&(FE::q() - s));
+        if r1 * &s_fe == expected || r1 * &s_neg_fe == expected {
+            Ok(())
+        } else {
+            Err(HealthCheckError)
+        }
+    }
+
+    // returns x1 * x2 * R once party one proved its partial point, and the message that lets
+    // party one check the result
+    pub fn ecdh_first_message(
+        &self,
+        point: &GE,
+        party_one_ecdh_first_message: &EcdhParty1Message1,
+    ) -> Result<(EcdhParty2Message1, GE), EcdhError> {
+        let partial_point = &party_one_ecdh_first_message.partial_ecdh.point;
+        party_one_ecdh_first_message
+            .partial_ecdh
+            .verify(&self.public.p1, point)?;
+        let x2 = party_two_share(&self.private);
+        let partial_ecdh = PartialEcdh::compute(&x2, partial_point);
+        let shared = partial_ecdh.point.clone();
+        Ok((EcdhParty2Message1 { partial_ecdh }, shared))
+    }
+
+    // cooperative export: party two picks a one time encryption key for the share of party one
+    pub fn export_first_message() -> (ExportParty2Message1, FE) {
+        let (y_pub, y) = one_time_key();
+        (ExportParty2Message1 { y_pub }, y)
+    }
+
+    // verifies that the released share is the dlog of p1, combines it with the local share
+    // and checks x*G == q before handing out the full key. Party two is done with this key
+    // as well, like party one after export_first_message.
+    pub fn export_second_message(
+        self,
+        y: &FE,
+        party_one_export_first_message: &ExportParty1Message1,
+    ) -> Result<(ExportedKey, RetiredMasterKey), Errors> {
+        let x1 = decrypt_share(
+            y,
+            &party_one_export_first_message.encryptions,
+            &party_one_export_first_message.proof,
+            &self.public.p1,
+        )
+        .or(Err(ExportError))?;
+        let x2 = party_two_share(&self.private);
+        let secret = x1 * &x2;
+        let g: GE = ECPoint::generator();
+        if &g * &secret != self.public.q {
+            return Err(ExportError);
+        }
+        let exported_key = ExportedKey {
+            q: self.public.q.clone(),
+            secret,
+            chain_code: self.chain_code.clone(),
+        };
+        Ok((exported_key, self.retire()))
+    }
+
+    // device migration, run by the new device: one time key for the share of the old device
+    pub fn move_device_first_message() -> (MoveDeviceMessage1, FE) {
+        let (y_pub, y) = one_time_key();
+        (MoveDeviceMessage1 { y_pub }, y)
+    }
+
+    // run by the old device: hands over x2 together with the public data of the key
+    pub fn move_device_second_message(
+        self,
+        new_device_first_message: &MoveDeviceMessage1,
+    ) -> (MoveDeviceMessage2, RetiredMasterKey) {
+        let (encryptions, proof) =
+            encrypt_party_two_share(&self.private, &new_device_first_message.y_pub);
+        let message = MoveDeviceMessage2 {
+            encryptions,
+            proof,
+            public: self.public.clone(),
+            chain_code: self.chain_code.clone(),
+            epoch: self.epoch.clone(),
+        };
+        (message, self.retire())
+    }
+
+    // run by the new device after a coin flip with party one and party one
+    // rotation_prepare_first_message. x2 is recovered and rotated, so x2 left on the old device no
+    // longer matches the share of party one. The rotation is two phase: party one commits on the
+    // returned message and the new device switches with PendingRotation2::commit.
+    pub fn move_device_third_message(
+        y: &FE,
+        old_device_second_message: &MoveDeviceMessage2,
+        cf: &Rotation,
+        party_one_rotation_first_message: &RotationParty1Message1,
+        party_one_rotation_first_message_salt: &[u8],
+    ) -> Result<(RotationParty2Message1, PendingRotation2), Errors> {
+        let public = &old_device_second_message.public;
+        let x2 = decrypt_share(
+            y,
+            &old_device_second_message.encryptions,
+            &old_device_second_message.proof,
+            &public.p2,
+        )
+        .or(Err(DeviceMoveError))?;
+        if &public.p1 * &x2 != public.q {
+            return Err(DeviceMoveError);
+        }
+        let master_key_recovered = MasterKey2::recover_master_key(
+            x2,
+            public.clone(),
+            old_device_second_message.chain_code.clone(),
+            old_device_second_message.epoch.clone(),
+        );
+        master_key_recovered
+            .rotation_prepare_first_message(
+                cf,
+                party_one_rotation_first_message,
+                party_one_rotation_first_message_salt,
+            )
+            .or(Err(DeviceMoveError))
+    }
+
+    // optional last step of a rotation: the chain code is replaced by the output of a new run of
+    // the chain_code::two_party exchange, bound to the current epoch. Children are not carried
+    // over, see RetiredChainCode.
+    pub fn chain_code_first_message(
+        &self,
+        party_one_chain_code_first_message: &ChainCodeParty1Message1,
+    ) -> Result<(ChainCodeParty2Message1, EcKeyPair<GE>), Errors> {
+        self.check_epoch(&party_one_chain_code_first_message.epoch)?;
+        let (first_message, ec_key_pair) = ChainCode2::chain_code_first_message();
+        Ok((ChainCodeParty2Message1 { first_message }, ec_key_pair))
+    }
+
+    // the consistency message of the refreshed key goes to party one, see PendingChainCode1
+    pub fn chain_code_second_message(
+        &self,
+        party_one_chain_code_first_message: &ChainCodeParty1Message1,
+        party_one_chain_code_second_message: &ChainCodeParty1Message2,
+        ec_key_pair: &EcKeyPair<GE>,
+    ) -> Result<(ConsistencyMessage, PendingChainCode2), Errors> {
+        let second_message = &party_one_chain_code_second_message.second_message;
+        ChainCode2::chain_code_second_message(
+            &party_one_chain_code_first_message.first_message,
+            second_message,
+        )
+        .or(Err(ChainCodeError))?;
+        let chain_code =
+            ChainCode2::compute_chain_code(ec_key_pair, &second_message.comm_witness.public_share)
+                .chain_code;
+        check_chain_code(&chain_code).or(Err(ChainCodeError))?;
+        let (refreshed, retired) = self.with_chain_code(chain_code);
+        Ok((
+            refreshed.consistency_first_message(),
+            PendingChainCode2 {
+                refreshed,
+                retired,
+                previous: self.epoch_entry(),
+            },
+        ))
+    }
+
+    fn with_chain_code(&self, chain_code: BigInt) -> (MasterKey2, RetiredChainCode) {
+        let retired = RetiredChainCode {
+            q: self.public.q.clone(),
+            chain_code: self.chain_code.clone(),
+        };
+        (
+            MasterKey2 {
+                public: self.public.clone(),
+                private: self.private.clone(),
+                chain_code,
+                epoch: KeyEpoch::next(&self.epoch_entry()),
+            },
+            retired,
+        )
+    }
+
+    pub fn retire(self) -> RetiredMasterKey {
+        RetiredMasterKey {
+            q: self.public.q,
+            chain_code: self.chain_code,
+        }
+    }
+}
+
+impl PendingChainCode2 {
+    // party one switched and answered with the consistency message of its refreshed key. The
+    // entry of the current key goes to the ledger.
+    pub fn commit(
+        self,
+        party_one_consistency_message: &ConsistencyMessage,
+        ledger: &mut EpochLedger,
+    ) -> Result<(MasterKey2, RetiredChainCode), ConsistencyError> {
+        self.refreshed
+            .check_consistency(party_one_consistency_message)?;
+        ledger.push(self.previous).or(Err(EpochMismatch))?;
+        Ok((self.refreshed, self.retired))
+    }
+}
+
+impl PendingRotation2 {
+    // party one committed to the rotated key: party two records the current key in the ledger
+    // and switches as well. The current key should be dropped only after this returns Ok. The
+    // rotation goes to the log.
+    pub fn commit(
+        self,
+        party_one_rotation_second_message: &RotationParty1Message2,
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<MasterKey2, Errors> {
+        if party_one_rotation_second_message.epoch != self.rotated.epoch {
+            return Err(RotationError);
+        }
+        ledger.push(self.previous)?;
+        log.append(
+            self.rotated
+                .audit_record(Operation::Rotation, None, session_id),
+        );
+        Ok(self.rotated)
+    }
+
+    // party one reported that it aborted. Only safe before party one committed: when the
+    // confirmation is missing instead, resolve decides from the epoch party one presents.
+    pub fn abort(self) -> KeyEpoch {
+        self.previous.epoch
+    }
+
+    // recovery path for an interrupted rotation (e.g. the confirmation of party one was lost):
+    // the epoch party one presents in its next message decides which key is kept
+    pub fn resolve(
+        self,
+        current: MasterKey2,
+        party_one_epoch: &KeyEpoch,
+        ledger: &mut EpochLedger,
+        log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> Result<MasterKey2, Errors> {
+        if party_one_epoch != &self.rotated.epoch {
+            return Ok(current);
+        }
+        ledger.push(self.previous)?;
+        log.append(
+            self.rotated
+                .audit_record(Operation::Rotation, None, session_id),
+        );
+        Ok(self.rotated)
+    }
 }
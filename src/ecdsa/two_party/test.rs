@@ -13,7 +13,20 @@
 
 #[cfg(test)]
 mod tests {
+    use super::super::adaptor;
+    use super::super::{EpochLedger, KeyEpoch};
     use super::super::{MasterKey1, MasterKey2};
+    use audit::{AuditEntry, AuditLog, Operation};
+    use base64;
+    use bitcoin::consensus::encode::deserialize;
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+    use bitcoin::psbt::PartiallySignedTransaction;
+    use bitcoin::secp256k1::{Message, Secp256k1};
+    use bitcoin::util::bip32::{ChildNumber, DerivationPath};
+    use bitcoin::{
+        Address, Network, OutPoint, PackedLockTime, PublicKey, Script, Sequence, Transaction, TxIn,
+        TxOut, Witness,
+    };
     use centipede::juggling::proof_system::Proof;
     use centipede::juggling::segmentation::Msegmentation;
     use chain_code::two_party::party1;
@@ -21,12 +34,40 @@ mod tests {
     use curv::arithmetic::traits::Converter;
     use curv::elliptic::curves::traits::{ECPoint, ECScalar};
     use curv::BigInt;
-    use curv::arithmetic::One;
     use curv::elliptic::curves::secp256_k1::{FE, GE};
+    use ecdh::{ecies_decrypt, ecies_encrypt};
+    use message_signing::{bip137_digest, bip137_signature, bip137_verify, Bip137AddressType};
+    use message_signing::{bip322_simple_digest, bip322_simple_signature, bip322_simple_verify};
+    use message_signing::{bip322_to_sign, bip322_to_spend, eip191_digest, eip712_digest};
+    use message_signing::{
+        eip712_encode_type, eth_signature, eth_verify, Eip712Struct, Eip712Value,
+    };
+    use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one;
+    use policy::{Allowlist, DailyLimit, PolicyEngine, PolicyRequest, Velocity};
+    use psbt::{
+        add_ecdsa_signature, master_fingerprint, sighash, to_32_bytes, SighashKind, SignJob,
+    };
     use rotation::two_party::party1::Rotation1;
     use rotation::two_party::party2::Rotation2;
     use rotation::two_party::Rotation;
+    use serde_json;
+    use sign_request::{HashType, SignRequest};
+    use std::str::FromStr;
     use zk_paillier::zkproofs::SALT_STRING;
+    use AdaptorError;
+    use AuditError;
+    use ConsistencyError;
+    use EcdhError;
+    use Errors::{self, EpochError, HealthCheckError, RotationError, SignError};
+    use MessageSignError;
+    use PolicyError::{
+        DailyLimitExceeded, DestinationNotAllowed, DestinationUnknown, TransactionInvalid,
+        VelocityExceeded,
+    };
+    use PsbtError;
+    use RecoveryError;
+    use SignRequestError;
+    use ValidationError;
 
     #[test]
     fn test_recovery_from_openssl() {
@@ -98,6 +139,8 @@ mod tests {
 
     #[test]
     fn test_recovery_scenarios() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
         // key gen
         let (party_one_master_key, party_two_master_key) = test_key_gen();
         // backup by party one of his private secret share: (we skip the verifiable part of proof and later verify)
@@ -120,83 +163,92 @@ mod tests {
         // In practice party two will recover party_one_master_key and from that point will run both logic parties locally
         let secret_decrypted_party_one =
             Msegmentation::decrypt(&encryptions_secret_party1, &G, &y, &segment_size);
-        let _party_one_master_key_recovered = party_two_master_key
+        let party_one_master_key_recovered = party_two_master_key
             .counter_master_key_from_recovered_secret(secret_decrypted_party_one.unwrap().clone());
+        assert_eq!(
+            party_one_master_key_recovered.public.q,
+            party_two_master_key.public.q
+        );
+        // the recovered party one has a new paillier key, party two pairs its own share with it
+        let secret_decrypted_party_two =
+            Msegmentation::decrypt(&encryptions_secret_party2, &G, &y, &segment_size);
+        let party_two_master_key_local = party_one_master_key_recovered
+            .counter_master_key_from_recovered_secret(secret_decrypted_party_two.unwrap().clone());
+        test_sign(&party_one_master_key_recovered, &party_two_master_key_local);
 
         // second case: party two wants to self-recover. public data and chain code of party two are assumed to exist locally or sent from party one
         let secret_decrypted_party_two =
             Msegmentation::decrypt(&encryptions_secret_party2, &G, &y, &segment_size);
-        let _party_two_master_key_recovered = MasterKey2::recover_master_key(
+        let party_two_master_key_recovered = MasterKey2::recover_master_key(
             secret_decrypted_party_two.unwrap().clone(),
             party_two_master_key.public.clone(),
             party_two_master_key.chain_code.clone(),
+            party_two_master_key.epoch.clone(),
         );
+        test_sign(&party_one_master_key, &party_two_master_key_recovered);
 
-        // third case: party two is dead, party two wants to recover the full key.
+        // third case: party two is dead, party one wants to recover the full key.
         // In practice party one will recover party_two_master_key and from that point will run both logic parties locally
         let secret_decrypted_party_two =
             Msegmentation::decrypt(&encryptions_secret_party2, &G, &y, &segment_size);
-        let _party_two_master_key_recovered = party_one_master_key
+        let party_two_master_key_recovered = party_one_master_key
             .counter_master_key_from_recovered_secret(secret_decrypted_party_two.unwrap().clone());
-        /*
-                assert_eq!(
-                    party_two_master_key_recovered.private,
-                    party_two_master_key.private
-                );
-        */
-        // fourth case: party one wants ro self-recover. to do so we first generate "half" party one master key from the recovered secret share
-        // then we run rotation but with coin flip = 1. because our specific rotation includes generating new paillier key with all the zk - proofs.
+        test_sign(&party_one_master_key, &party_two_master_key_recovered);
+
+        // fourth case: party one wants to self-recover. the public data sent by party two is verified against the
+        // fingerprint party one kept, "half" party one master key is generated from the recovered secret share
+        // and rotated with a fresh coin flip. because our specific rotation includes generating new paillier key with all the zk - proofs,
         // the result is that both parties will go through rotation and have a new paillier data in the master keys. we show that signing works the same
+        let fingerprint = party_one_master_key.fingerprint();
         let secret_decrypted_party_one =
             Msegmentation::decrypt(&encryptions_secret_party1, &G, &y, &segment_size);
-        let party_one_master_key_half_recovered = MasterKey1::recover_master_key(
-            secret_decrypted_party_one.unwrap().clone(),
-            party_one_master_key.public.clone(),
-            party_one_master_key.chain_code.clone(),
-        );
+        let recovery_party_two_first_message = party_two_master_key.recovery_first_message();
 
-        let one: FE = ECScalar::from(&BigInt::one());
-        let random1 = Rotation {
-            rotation: one.clone(),
-        };
-        let random2 = Rotation {
-            rotation: one.clone(),
-        };
+        //coin flip:
+        let (party1_first_message, m1, r1) = Rotation1::key_rotate_first_message();
+        let party2_first_message = Rotation2::key_rotate_first_message(&party1_first_message);
+        let (party1_second_message, random1) =
+            Rotation1::key_rotate_second_message(&party2_first_message, &m1, &r1);
+        let random2 = Rotation2::key_rotate_second_message(
+            &party1_second_message,
+            &party2_first_message,
+            &party1_first_message,
+        );
 
-        //rotation:
         let (rotation_party_one_first_message, party_one_master_key_rotated) =
-            party_one_master_key_half_recovered.rotation_first_message(&random1);
+            MasterKey1::recover_master_key_and_rotate(
+                secret_decrypted_party_one.unwrap().clone(),
+                &recovery_party_two_first_message,
+                &fingerprint,
+                &random1,
+                &mut EpochLedger::new(),
+                &mut log,
+                &session_id,
+            )
+            .expect("bad recovery");
 
-        let result_rotate_party_one_first_message =
-            party_two_master_key.rotate_first_message(&random2, &rotation_party_one_first_message, SALT_STRING);
+        let result_rotate_party_one_first_message = party_two_master_key.rotate_first_message(
+            &random2,
+            &rotation_party_one_first_message,
+            SALT_STRING,
+            &mut EpochLedger::new(),
+            &mut log,
+            &session_id,
+        );
         assert!(result_rotate_party_one_first_message.is_ok());
 
         let party_two_master_key_rotated = result_rotate_party_one_first_message.unwrap();
+        assert_eq!(party_one_master_key_rotated.fingerprint(), fingerprint);
+        assert_eq!(party_two_master_key_rotated.fingerprint(), fingerprint);
 
         //test by signing:
-        let message = BigInt::from(1234);
-        let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
-            MasterKey2::sign_first_message();
-        let (sign_party_one_first_message, eph_ec_key_pair_party1) =
-            MasterKey1::sign_first_message();
-        let sign_party_two_second_message = party_two_master_key_rotated.sign_second_message(
-            &eph_ec_key_pair_party2,
-            eph_comm_witness.clone(),
-            &sign_party_one_first_message,
-            &message,
-        );
-        let sign_party_one_second_message = party_one_master_key_rotated.sign_second_message(
-            &sign_party_two_second_message,
-            &sign_party_two_first_message,
-            &eph_ec_key_pair_party1,
-            &message,
-        );
-
-        sign_party_one_second_message.expect("bad signature");
+        test_sign(&party_one_master_key_rotated, &party_two_master_key_rotated);
     }
 
     #[test]
     fn test_commutativity_rotate_get_child() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
         // key gen
         let (party_one_master_key, party_two_master_key) = test_key_gen();
 
@@ -206,54 +258,76 @@ mod tests {
         let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
             MasterKey2::sign_first_message();
         let (sign_party_one_first_message, eph_ec_key_pair_party1) =
-            MasterKey1::sign_first_message();
-        let sign_party_two_second_message = party_two_master_key.sign_second_message(
-            &eph_ec_key_pair_party2,
-            eph_comm_witness.clone(),
-            &sign_party_one_first_message,
-            &message,
-        );
+            party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness.clone(),
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
         let sign_party_one_second_message = party_one_master_key.sign_second_message(
             &sign_party_two_second_message,
             &sign_party_two_first_message,
             &eph_ec_key_pair_party1,
             &message,
+            &mut log,
+            &session_id,
         );
         sign_party_one_second_message.expect("bad signature");
 
-        let new_party_one_master_key = party_one_master_key.get_child(vec![BigInt::from(10)]);
-        let new_party_two_master_key = party_two_master_key.get_child(vec![BigInt::from(10)]);
+        let new_party_one_master_key =
+            party_one_master_key.get_child(vec![BigInt::from(10)], &mut log, &session_id);
+        let new_party_two_master_key =
+            party_two_master_key.get_child(vec![BigInt::from(10)], &mut log, &session_id);
 
         // sign with child keys
-        let sign_party_two_second_message = new_party_two_master_key.sign_second_message(
-            &eph_ec_key_pair_party2,
-            eph_comm_witness.clone(),
-            &sign_party_one_first_message,
-            &message,
-        );
+        let sign_party_two_second_message = new_party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness.clone(),
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
         let sign_party_one_second_message = new_party_one_master_key.sign_second_message(
             &sign_party_two_second_message,
             &sign_party_two_first_message,
             &eph_ec_key_pair_party1,
             &message,
+            &mut log,
+            &session_id,
         );
         sign_party_one_second_message.expect("bad signature");
 
         let (cr_party_one_master_key, cr_party_two_master_key) =
             test_rotation(new_party_one_master_key, new_party_two_master_key);
 
-        // sign with child and rotated keys
-        let sign_party_two_second_message = cr_party_two_master_key.sign_second_message(
-            &eph_ec_key_pair_party2,
-            eph_comm_witness,
-            &sign_party_one_first_message,
-            &message,
-        );
+        // sign with child and rotated keys, party one announces the new epoch
+        let (sign_party_one_first_message, eph_ec_key_pair_party1) =
+            cr_party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = cr_party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
         let sign_party_one_second_message = cr_party_one_master_key.sign_second_message(
             &sign_party_two_second_message,
             &sign_party_two_first_message,
             &eph_ec_key_pair_party1,
             &message,
+            &mut log,
+            &session_id,
         );
         sign_party_one_second_message.expect("bad signature");
 
@@ -263,27 +337,35 @@ mod tests {
             test_rotation(party_one_master_key, party_two_master_key);
 
         //get child:
-        let rc_party_one_master_key = rotate_party_one_master_key.get_child(vec![BigInt::from(10)]);
-        let rc_party_two_master_key = rotate_party_two_master_key.get_child(vec![BigInt::from(10)]);
+        let rc_party_one_master_key =
+            rotate_party_one_master_key.get_child(vec![BigInt::from(10)], &mut log, &session_id);
+        let rc_party_two_master_key =
+            rotate_party_two_master_key.get_child(vec![BigInt::from(10)], &mut log, &session_id);
 
         // sign with rotated and child keys
         let message = BigInt::from(1234);
         let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
             MasterKey2::sign_first_message();
         let (sign_party_one_first_message, eph_ec_key_pair_party1) =
-            MasterKey1::sign_first_message();
+            rc_party_one_master_key.sign_first_message();
 
-        let sign_party_two_second_message = rc_party_two_master_key.sign_second_message(
-            &eph_ec_key_pair_party2,
-            eph_comm_witness,
-            &sign_party_one_first_message,
-            &message,
-        );
+        let sign_party_two_second_message = rc_party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
         let sign_party_one_second_message = rc_party_one_master_key.sign_second_message(
             &sign_party_two_second_message,
             &sign_party_two_first_message,
             &eph_ec_key_pair_party1,
             &message,
+            &mut log,
+            &session_id,
         );
         sign_party_one_second_message.expect("bad signature");
         assert_eq!(
@@ -294,13 +376,21 @@ mod tests {
 
     #[test]
     fn test_get_child() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
         // compute master keys:
         let (party_one_master_key, party_two_master_key) = test_key_gen();
 
-        let new_party_two_master_key =
-            party_two_master_key.get_child(vec![BigInt::from(10), BigInt::from(5)]);
-        let new_party_one_master_key =
-            party_one_master_key.get_child(vec![BigInt::from(10), BigInt::from(5)]);
+        let new_party_two_master_key = party_two_master_key.get_child(
+            vec![BigInt::from(10), BigInt::from(5)],
+            &mut log,
+            &session_id,
+        );
+        let new_party_one_master_key = party_one_master_key.get_child(
+            vec![BigInt::from(10), BigInt::from(5)],
+            &mut log,
+            &session_id,
+        );
         assert_eq!(
             new_party_one_master_key.public.q,
             new_party_two_master_key.public.q
@@ -311,18 +401,24 @@ mod tests {
         let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
             MasterKey2::sign_first_message();
         let (sign_party_one_first_message, eph_ec_key_pair_party1) =
-            MasterKey1::sign_first_message();
-        let sign_party_two_second_message = party_two_master_key.sign_second_message(
-            &eph_ec_key_pair_party2,
-            eph_comm_witness,
-            &sign_party_one_first_message,
-            &message,
-        );
+            party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
         let sign_party_one_second_message = party_one_master_key.sign_second_message(
             &sign_party_two_second_message,
             &sign_party_two_first_message,
             &eph_ec_key_pair_party1,
             &message,
+            &mut log,
+            &session_id,
         );
         sign_party_one_second_message.expect("bad signature");
 
@@ -331,24 +427,32 @@ mod tests {
         let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
             MasterKey2::sign_first_message();
         let (sign_party_one_first_message, eph_ec_key_pair_party1) =
-            MasterKey1::sign_first_message();
-        let sign_party_two_second_message = new_party_two_master_key.sign_second_message(
-            &eph_ec_key_pair_party2,
-            eph_comm_witness,
-            &sign_party_one_first_message,
-            &message,
-        );
+            new_party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = new_party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
         let sign_party_one_second_message = new_party_one_master_key.sign_second_message(
             &sign_party_two_second_message,
             &sign_party_two_first_message,
             &eph_ec_key_pair_party1,
             &message,
+            &mut log,
+            &session_id,
         );
         sign_party_one_second_message.expect("bad signature");
     }
 
     #[test]
     fn test_flip_masters() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
         // for this test to work party2 MasterKey private need to be changed to pub
         // key gen
         let (party_one_master_key, party_two_master_key) = test_key_gen();
@@ -359,18 +463,24 @@ mod tests {
         let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
             MasterKey2::sign_first_message();
         let (sign_party_one_first_message, eph_ec_key_pair_party1) =
-            MasterKey1::sign_first_message();
-        let sign_party_two_second_message = party_two_master_key.sign_second_message(
-            &eph_ec_key_pair_party2,
-            eph_comm_witness,
-            &sign_party_one_first_message,
-            &message,
-        );
+            party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
         let sign_party_one_second_message = party_one_master_key.sign_second_message(
             &sign_party_two_second_message,
             &sign_party_two_first_message,
             &eph_ec_key_pair_party1,
             &message,
+            &mut log,
+            &session_id,
         );
         sign_party_one_second_message.expect("bad signature");
 
@@ -384,23 +494,1770 @@ mod tests {
         let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
             MasterKey2::sign_first_message();
         let (sign_party_one_first_message, eph_ec_key_pair_party1) =
-            MasterKey1::sign_first_message();
-        let sign_party_two_second_message = party_two_master_key_rotated.sign_second_message(
+            party_one_master_key_rotated.sign_first_message();
+        let sign_party_two_second_message = party_two_master_key_rotated
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
+        let sign_party_one_second_message = party_one_master_key_rotated.sign_second_message(
+            &sign_party_two_second_message,
+            &sign_party_two_first_message,
+            &eph_ec_key_pair_party1,
+            &message,
+            &mut log,
+            &session_id,
+        );
+        sign_party_one_second_message.expect("bad signature");
+    }
+
+    #[test]
+    fn test_export() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let q = party_two_master_key.public.q.clone();
+        let child_q = party_two_master_key
+            .derive_child(vec![BigInt::from(10), BigInt::from(5)])
+            .public
+            .q;
+
+        let (export_party_two_first_message, y) = MasterKey2::export_first_message();
+        let (export_party_one_first_message, party_one_retired) =
+            party_one_master_key.export_first_message(&export_party_two_first_message);
+        let (exported_key, party_two_retired) = party_two_master_key
+            .export_second_message(&y, &export_party_one_first_message)
+            .expect("bad export");
+        assert_eq!(party_one_retired, party_two_retired);
+
+        let G: GE = GE::generator();
+        assert_eq!(G.clone() * &exported_key.secret, q);
+        assert_eq!(exported_key.to_raw().len(), 32);
+        let wif = exported_key.to_wif(false);
+        assert!(wif.starts_with('K') || wif.starts_with('L'));
+
+        let child_key = exported_key.get_child(vec![BigInt::from(10), BigInt::from(5)]);
+        assert_eq!(child_key.q, child_q);
+        assert_eq!(G * &child_key.secret, child_q);
+    }
+
+    #[test]
+    fn test_replace_provider() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (old_provider_master_key, party_two_master_key) = test_key_gen();
+        let q = party_two_master_key.public.q.clone();
+
+        // share transfer from the old provider to the new one
+        let (replace_first_message, y) = MasterKey1::replace_provider_first_message();
+        let (replace_second_message, _old_provider_retired) =
+            old_provider_master_key.replace_provider_second_message(&replace_first_message);
+
+        // coin flip between the new provider and party two
+        let (party1_first_message, m1, r1) = Rotation1::key_rotate_first_message();
+        let party2_first_message = Rotation2::key_rotate_first_message(&party1_first_message);
+        let (party1_second_message, random1) =
+            Rotation1::key_rotate_second_message(&party2_first_message, &m1, &r1);
+        let random2 = Rotation2::key_rotate_second_message(
+            &party1_second_message,
+            &party2_first_message,
+            &party1_first_message,
+        );
+
+        let (rotation_party_one_first_message, new_provider_pending) =
+            MasterKey1::replace_provider_third_message(&y, &replace_second_message, &random1)
+                .expect("bad share transfer");
+        let (rotation_party_two_first_message, party_two_pending) = party_two_master_key
+            .rotation_prepare_first_message(
+                &random2,
+                &rotation_party_one_first_message,
+                SALT_STRING,
+            )
+            .expect("bad rotation");
+
+        // both sides record the epoch they leave
+        let mut new_provider_ledger = EpochLedger::new();
+        let mut party_two_ledger = EpochLedger::new();
+        let (rotation_party_one_second_message, new_provider_master_key) = new_provider_pending
+            .commit(
+                &rotation_party_two_first_message,
+                &mut new_provider_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad commit");
+        let party_two_master_key = party_two_pending
+            .commit(
+                &rotation_party_one_second_message,
+                &mut party_two_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad commit");
+        assert_eq!(new_provider_master_key.public.q, q);
+        assert_eq!(party_two_master_key.public.q, q);
+        assert_eq!(new_provider_ledger.entries.len(), 1);
+        assert_eq!(new_provider_ledger, party_two_ledger);
+
+        test_sign(&new_provider_master_key, &party_two_master_key);
+    }
+
+    #[test]
+    fn test_move_device() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, old_device_master_key) = test_key_gen();
+        let q = old_device_master_key.public.q.clone();
+
+        // share transfer from the old device to the new one
+        let (move_first_message, y) = MasterKey2::move_device_first_message();
+        let (move_second_message, _old_device_retired) =
+            old_device_master_key.move_device_second_message(&move_first_message);
+
+        // coin flip between party one and the new device
+        let (party1_first_message, m1, r1) = Rotation1::key_rotate_first_message();
+        let party2_first_message = Rotation2::key_rotate_first_message(&party1_first_message);
+        let (party1_second_message, random1) =
+            Rotation1::key_rotate_second_message(&party2_first_message, &m1, &r1);
+        let random2 = Rotation2::key_rotate_second_message(
+            &party1_second_message,
+            &party2_first_message,
+            &party1_first_message,
+        );
+
+        let (rotation_party_one_first_message, party_one_pending) =
+            party_one_master_key.rotation_prepare_first_message(&random1);
+        let (rotation_party_two_first_message, new_device_pending) =
+            MasterKey2::move_device_third_message(
+                &y,
+                &move_second_message,
+                &random2,
+                &rotation_party_one_first_message,
+                SALT_STRING,
+            )
+            .expect("bad device move");
+
+        // both sides record the epoch they leave
+        let mut party_one_ledger = EpochLedger::new();
+        let mut new_device_ledger = EpochLedger::new();
+        let (rotation_party_one_second_message, party_one_master_key) = party_one_pending
+            .commit(
+                &rotation_party_two_first_message,
+                &mut party_one_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad commit");
+        let new_device_master_key = new_device_pending
+            .commit(
+                &rotation_party_one_second_message,
+                &mut new_device_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad commit");
+        assert_eq!(new_device_master_key.public.q, q);
+        assert_eq!(party_one_ledger.entries.len(), 1);
+        assert_eq!(party_one_ledger, new_device_ledger);
+
+        test_sign(&party_one_master_key, &new_device_master_key);
+    }
+
+    #[test]
+    fn test_verified_recovery() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let fingerprint = party_two_master_key.fingerprint();
+        assert_eq!(party_one_master_key.fingerprint(), fingerprint);
+
+        // backup of both secret shares
+        let segment_size = 8;
+        let y: FE = FE::new_random();
+        let G: GE = GE::generator();
+        let Y = G.clone() * &y;
+        let (_, encryptions_secret_party1) =
+            party_one_master_key
+                .private
+                .to_encrypted_segment(segment_size, 32, &Y, &G);
+        let (_, encryptions_secret_party2) =
+            party_two_master_key
+                .private
+                .to_encrypted_segment(segment_size, 32, &Y, &G);
+        let secret_party_one =
+            Msegmentation::decrypt(&encryptions_secret_party1, &G, &y, &segment_size).unwrap();
+        let secret_party_two =
+            Msegmentation::decrypt(&encryptions_secret_party2, &G, &y, &segment_size).unwrap();
+
+        // party one recovers with the public data held by party two
+        let recovery_party_two_first_message = party_two_master_key.recovery_first_message();
+        let wrong_fingerprint = BigInt::from(1234);
+        assert_eq!(
+            MasterKey1::recover_master_key_verified(
+                secret_party_one.clone(),
+                &recovery_party_two_first_message,
+                &wrong_fingerprint,
+            )
+            .err(),
+            Some(RecoveryError::FingerprintMismatch)
+        );
+        assert_eq!(
+            MasterKey1::recover_master_key_verified(
+                secret_party_two.clone(),
+                &recovery_party_two_first_message,
+                &fingerprint,
+            )
+            .err(),
+            Some(RecoveryError::PublicShareMismatch)
+        );
+        assert!(MasterKey1::recover_master_key_verified(
+            secret_party_one,
+            &recovery_party_two_first_message,
+            &fingerprint,
+        )
+        .is_ok());
+
+        // party two recovers with the public data held by party one
+        let mut party_one_ledger = EpochLedger::new();
+        let mut party_two_ledger = EpochLedger::new();
+        let (recovery_party_one_first_message, party_one_master_key) = party_one_master_key
+            .recovery_first_message(&mut party_one_ledger, &mut log, &session_id)
+            .expect("bad rotation");
+        let party_two_master_key_recovered = MasterKey2::recover_master_key_verified(
+            secret_party_two,
+            &recovery_party_one_first_message,
+            &fingerprint,
+            SALT_STRING,
+            &mut party_two_ledger,
+            &mut log,
+            &session_id,
+        )
+        .expect("bad recovery");
+        assert_eq!(party_two_master_key_recovered.fingerprint(), fingerprint);
+        assert_eq!(party_one_ledger.entries.len(), 1);
+        assert_eq!(party_one_ledger, party_two_ledger);
+
+        test_sign(&party_one_master_key, &party_two_master_key_recovered);
+    }
+
+    #[test]
+    fn test_chain_code_refresh() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let fingerprint = party_one_master_key.fingerprint();
+        let location_in_hir = vec![BigInt::from(10), BigInt::from(5)];
+        let child_q = party_two_master_key
+            .derive_child(location_in_hir.clone())
+            .public
+            .q;
+
+        // rotation followed by a new chain code exchange
+        let (party_one_master_key, party_two_master_key) =
+            test_rotation(party_one_master_key, party_two_master_key);
+        let (party_one_first_message, comm_witness, party_one_ec_key_pair) =
+            party_one_master_key.chain_code_first_message();
+        let (party_two_first_message, party_two_ec_key_pair) = party_two_master_key
+            .chain_code_first_message(&party_one_first_message)
+            .expect("epoch mismatch");
+        let (party_one_second_message, party_one_pending) = party_one_master_key
+            .chain_code_second_message(
+                comm_witness,
+                &party_one_ec_key_pair,
+                &party_two_first_message,
+            )
+            .expect("bad chain code exchange");
+        let (party_two_consistency_message, party_two_pending) = party_two_master_key
+            .chain_code_second_message(
+                &party_one_first_message,
+                &party_one_second_message,
+                &party_two_ec_key_pair,
+            )
+            .expect("bad chain code exchange");
+
+        // party two must not switch to a chain code party one did not confirm
+        let party_one_consistency_message = party_one_master_key.consistency_first_message();
+        assert!(party_one_pending
+            .refreshed
+            .check_consistency(&party_one_consistency_message)
+            .is_err());
+
+        let (party_one_consistency_message, party_one_master_key, party_one_retired) =
+            party_one_pending
+                .commit(&party_two_consistency_message, &mut EpochLedger::new())
+                .expect("chain codes differ");
+        let (party_two_master_key, party_two_retired) = party_two_pending
+            .commit(&party_one_consistency_message, &mut EpochLedger::new())
+            .expect("chain codes differ");
+
+        assert_eq!(party_one_retired, party_two_retired);
+        assert_eq!(
+            party_one_master_key.chain_code,
+            party_two_master_key.chain_code
+        );
+        assert_eq!(
+            party_one_master_key.fingerprint(),
+            party_two_master_key.fingerprint()
+        );
+        assert_ne!(party_one_master_key.fingerprint(), fingerprint);
+
+        // same path, different key
+        let child_mapping = party_two_retired
+            .map_children(&party_two_master_key.chain_code, &[location_in_hir.clone()]);
+        let party_one_child =
+            party_one_master_key.get_child(location_in_hir.clone(), &mut log, &session_id);
+        let party_two_child =
+            party_two_master_key.get_child(location_in_hir, &mut log, &session_id);
+        assert_eq!(child_mapping[0].retired_q, child_q);
+        assert_eq!(child_mapping[0].q, party_two_child.public.q);
+        assert_ne!(child_mapping[0].q, child_q);
+
+        test_sign(&party_one_child, &party_two_child);
+    }
+
+    #[test]
+    fn test_epochs() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        assert_eq!(party_one_master_key.epoch, KeyEpoch::genesis());
+        assert_eq!(
+            party_one_master_key.epoch_entry(),
+            party_two_master_key.epoch_entry()
+        );
+
+        // a key stored before epochs loads at the genesis epoch
+        let mut stored = serde_json::to_value(&party_two_master_key).expect("serialize");
+        stored
+            .as_object_mut()
+            .expect("master key is an object")
+            .remove("epoch");
+        let loaded: MasterKey2 = serde_json::from_value(stored).expect("deserialize");
+        assert_eq!(loaded.epoch, KeyEpoch::genesis());
+
+        // messages prepared before the rotation
+        let message = BigInt::from(1234);
+        let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
+            MasterKey2::sign_first_message();
+        let (stale_sign_party_one_first_message, eph_ec_key_pair_party1) =
+            party_one_master_key.sign_first_message();
+        let stale_sign_party_two_second_message = party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &stale_sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
+
+        let mut ledger = EpochLedger::new();
+        ledger
+            .push(party_one_master_key.epoch_entry())
+            .expect("bad ledger entry");
+        let (party_one_master_key, party_two_master_key) =
+            test_rotation(party_one_master_key, party_two_master_key);
+        assert_eq!(party_one_master_key.epoch.epoch, 1);
+        assert_eq!(party_one_master_key.epoch, party_two_master_key.epoch);
+        ledger
+            .verify_epoch(&party_one_master_key.epoch)
+            .expect("bad epoch");
+        ledger
+            .push(party_two_master_key.epoch_entry())
+            .expect("bad ledger entry");
+        assert_eq!(
+            ledger.push(party_two_master_key.epoch_entry()),
+            Err(EpochError)
+        );
+
+        // both parties refuse to sign with the other side behind
+        let (_, eph_comm_witness, eph_ec_key_pair_party2) = MasterKey2::sign_first_message();
+        let result = party_two_master_key.sign_second_message(
             &eph_ec_key_pair_party2,
             eph_comm_witness,
-            &sign_party_one_first_message,
+            &stale_sign_party_one_first_message,
             &message,
+            &mut log,
+            &session_id,
         );
-        let sign_party_one_second_message = party_one_master_key_rotated.sign_second_message(
+        assert_eq!(result.err(), Some(EpochError));
+        let result = party_one_master_key.sign_second_message(
+            &stale_sign_party_two_second_message,
+            &sign_party_two_first_message,
+            &eph_ec_key_pair_party1,
+            &message,
+            &mut log,
+            &session_id,
+        );
+        assert_eq!(result.err(), Some(EpochError));
+
+        test_sign(&party_one_master_key, &party_two_master_key);
+    }
+
+    #[test]
+    fn test_two_phase_rotation() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let (random1, random2) = test_coin_flip();
+        let mut party_one_ledger = EpochLedger::new();
+        let mut party_two_ledger = EpochLedger::new();
+
+        // abort: party two rejects the rotation, both parties keep their current key
+        let (rotation_party_one_first_message, party_one_pending_rotation) =
+            party_one_master_key.rotation_prepare_first_message(&random1);
+        let bad_cf = Rotation {
+            rotation: ECScalar::new_random(),
+        };
+        let result = party_two_master_key.rotation_prepare_first_message(
+            &bad_cf,
+            &rotation_party_one_first_message,
+            SALT_STRING,
+        );
+        assert_eq!(result.err(), Some(RotationError));
+        assert_eq!(
+            party_one_pending_rotation.abort(),
+            party_one_master_key.epoch
+        );
+        test_sign(&party_one_master_key, &party_two_master_key);
+
+        // abort: the answer of party two is lost, party one drops its pending rotation and
+        // party two learns from the epoch of party one that it should too
+        let (rotation_party_one_first_message, party_one_pending_rotation) =
+            party_one_master_key.rotation_prepare_first_message(&random1);
+        let (_, party_two_pending_rotation) = party_two_master_key
+            .rotation_prepare_first_message(
+                &random2,
+                &rotation_party_one_first_message,
+                SALT_STRING,
+            )
+            .expect("bad rotation");
+        party_one_pending_rotation.abort();
+        let (sign_party_one_first_message, _) = party_one_master_key.sign_first_message();
+        let party_two_master_key = party_two_pending_rotation
+            .resolve(
+                party_two_master_key,
+                &sign_party_one_first_message.epoch,
+                &mut party_two_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad ledger entry");
+        assert_eq!(party_one_master_key.epoch, party_two_master_key.epoch);
+        assert!(party_two_ledger.entries.is_empty());
+
+        // abort reported by party one before it committed
+        let (rotation_party_one_first_message, party_one_pending_rotation) =
+            party_one_master_key.rotation_prepare_first_message(&random1);
+        let (_, party_two_pending_rotation) = party_two_master_key
+            .rotation_prepare_first_message(
+                &random2,
+                &rotation_party_one_first_message,
+                SALT_STRING,
+            )
+            .expect("bad rotation");
+        assert_eq!(
+            party_one_pending_rotation.abort(),
+            party_two_pending_rotation.abort()
+        );
+        test_sign(&party_one_master_key, &party_two_master_key);
+        test_sign(&party_one_master_key, &party_two_master_key);
+
+        // commit
+        let q = party_one_master_key.public.q.clone();
+        let (rotation_party_one_first_message, party_one_pending_rotation) =
+            party_one_master_key.rotation_prepare_first_message(&random1);
+        let (rotation_party_two_first_message, party_two_pending_rotation) = party_two_master_key
+            .rotation_prepare_first_message(
+                &random2,
+                &rotation_party_one_first_message,
+                SALT_STRING,
+            )
+            .expect("bad rotation");
+        let (rotation_party_one_second_message, party_one_master_key) = party_one_pending_rotation
+            .commit(
+                &rotation_party_two_first_message,
+                &mut party_one_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad rotation");
+        let party_two_master_key = party_two_pending_rotation
+            .commit(
+                &rotation_party_one_second_message,
+                &mut party_two_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad rotation");
+        assert_eq!(party_one_master_key.public.q, q);
+        assert_eq!(party_one_master_key.epoch.epoch, 1);
+        assert_eq!(party_one_master_key.epoch, party_two_master_key.epoch);
+        assert_eq!(party_one_ledger, party_two_ledger);
+        party_one_ledger
+            .verify_epoch(&party_one_master_key.epoch)
+            .expect("bad epoch");
+        test_sign(&party_one_master_key, &party_two_master_key);
+
+        // only party one committed: the confirmation is lost, party two recovers from the epoch
+        // party one presents when signing
+        let (random1, random2) = test_coin_flip();
+        let (rotation_party_one_first_message, party_one_pending_rotation) =
+            party_one_master_key.rotation_prepare_first_message(&random1);
+        let (rotation_party_two_first_message, party_two_pending_rotation) = party_two_master_key
+            .rotation_prepare_first_message(
+                &random2,
+                &rotation_party_one_first_message,
+                SALT_STRING,
+            )
+            .expect("bad rotation");
+        let (_, party_one_master_key) = party_one_pending_rotation
+            .commit(
+                &rotation_party_two_first_message,
+                &mut party_one_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad rotation");
+        let (sign_party_one_first_message, _) = party_one_master_key.sign_first_message();
+        let party_two_master_key = party_two_pending_rotation
+            .resolve(
+                party_two_master_key,
+                &sign_party_one_first_message.epoch,
+                &mut party_two_ledger,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad ledger entry");
+        assert_eq!(party_one_master_key.epoch.epoch, 2);
+        assert_eq!(party_one_master_key.epoch, party_two_master_key.epoch);
+        assert_eq!(party_two_ledger.entries.len(), 2);
+        party_two_ledger
+            .verify_epoch(&party_two_master_key.epoch)
+            .expect("bad epoch");
+        test_sign(&party_one_master_key, &party_two_master_key);
+    }
+
+    #[test]
+    fn test_consistency() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        assert_eq!(
+            party_one_master_key.state_fingerprint(),
+            party_two_master_key.state_fingerprint()
+        );
+        party_one_master_key
+            .check_consistency(&party_two_master_key.consistency_first_message())
+            .expect("inconsistent keys");
+        party_two_master_key
+            .check_consistency(&party_one_master_key.consistency_first_message())
+            .expect("inconsistent keys");
+
+        let party_one_child =
+            party_one_master_key.get_child(vec![BigInt::from(10)], &mut log, &session_id);
+        let party_two_child =
+            party_two_master_key.get_child(vec![BigInt::from(10)], &mut log, &session_id);
+        party_two_child
+            .check_consistency(&party_one_child.consistency_first_message())
+            .expect("inconsistent keys");
+        assert_eq!(
+            party_two_master_key.check_consistency(&party_one_child.consistency_first_message()),
+            Err(ConsistencyError::PublicKeyMismatch)
+        );
+
+        let mut party_one_consistency_message = party_one_master_key.consistency_first_message();
+        party_one_consistency_message.entry.chain_code = BigInt::from(1);
+        assert_eq!(
+            party_two_master_key.check_consistency(&party_one_consistency_message),
+            Err(ConsistencyError::ChainCodeMismatch)
+        );
+        let mut party_one_consistency_message = party_one_master_key.consistency_first_message();
+        party_one_consistency_message.entry.p1 = party_two_master_key.public.p2.clone();
+        assert_eq!(
+            party_two_master_key.check_consistency(&party_one_consistency_message),
+            Err(ConsistencyError::P1Mismatch)
+        );
+
+        // party one rotated alone
+        let (random1, _) = test_coin_flip();
+        let (_, party_one_pending_rotation) =
+            party_one_master_key.rotation_prepare_first_message(&random1);
+        let party_one_rotated = party_one_pending_rotation.rotated;
+        assert_eq!(
+            party_one_rotated.fingerprint(),
+            party_two_master_key.fingerprint()
+        );
+        assert_ne!(
+            party_one_rotated.state_fingerprint(),
+            party_two_master_key.state_fingerprint()
+        );
+        assert_eq!(
+            party_two_master_key.check_consistency(&party_one_rotated.consistency_first_message()),
+            Err(ConsistencyError::EpochMismatch)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        party_one_master_key.validate().expect("invalid master key");
+        party_two_master_key.validate().expect("invalid master key");
+        party_one_master_key
+            .get_child(
+                vec![BigInt::from(10), BigInt::from(5)],
+                &mut log,
+                &session_id,
+            )
+            .validate()
+            .expect("invalid master key");
+        party_two_master_key
+            .get_child(
+                vec![BigInt::from(10), BigInt::from(5)],
+                &mut log,
+                &session_id,
+            )
+            .validate()
+            .expect("invalid master key");
+        let (mut party_one_master_key, mut party_two_master_key) =
+            test_rotation(party_one_master_key, party_two_master_key);
+        party_one_master_key.validate().expect("invalid master key");
+        party_two_master_key.validate().expect("invalid master key");
+
+        // loading a stored key runs the checks as well
+        let mut stored = serde_json::to_value(&party_one_master_key).expect("serialize");
+        stored["public"]["p1"] = stored["public"]["p2"].clone();
+        assert!(serde_json::from_value::<MasterKey1>(stored).is_err());
+        let mut stored = serde_json::to_value(&party_two_master_key).expect("serialize");
+        stored["public"]["q"] = stored["public"]["p1"].clone();
+        assert!(serde_json::from_value::<MasterKey2>(stored).is_err());
+        let stored = serde_json::to_value(&party_two_master_key).expect("serialize");
+        serde_json::from_value::<MasterKey2>(stored).expect("valid master key rejected");
+
+        party_one_master_key.public.c_key = BigInt::from(2);
+        assert_eq!(
+            party_one_master_key.validate(),
+            Err(ValidationError::CKeyMismatch)
+        );
+
+        let q = party_two_master_key.public.q.clone();
+        party_two_master_key.public.q = party_two_master_key.public.p1.clone();
+        assert_eq!(
+            party_two_master_key.validate(),
+            Err(ValidationError::PublicKeyMismatch)
+        );
+        party_two_master_key.public.q = q;
+        let p2 = party_two_master_key.public.p2.clone();
+        party_two_master_key.public.p2 = party_two_master_key.public.p1.clone();
+        assert_eq!(
+            party_two_master_key.validate(),
+            Err(ValidationError::PublicShareMismatch)
+        );
+        party_two_master_key.public.p2 = p2;
+        party_two_master_key.public.c_key = party_two_master_key.public.paillier_pub.nn.clone();
+        assert_eq!(
+            party_two_master_key.validate(),
+            Err(ValidationError::CKeyOutOfRange)
+        );
+        party_two_master_key.public.c_key = BigInt::from(0);
+        assert_eq!(
+            party_two_master_key.validate(),
+            Err(ValidationError::CKeyOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_health_check() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        test_run_health_check(&party_one_master_key, &party_two_master_key)
+            .expect("bad health check");
+        let (party_one_master_key, party_two_master_key) =
+            test_rotation(party_one_master_key, party_two_master_key);
+        test_run_health_check(&party_one_master_key, &party_two_master_key)
+            .expect("bad health check");
+
+        // proofs are bound to the challenge
+        let party_one_first_message = MasterKey1::health_check_first_message();
+        let (party_two_first_message, _) =
+            party_two_master_key.health_check_first_message(&party_one_first_message);
+        let result = party_one_master_key.health_check_second_message(
+            &MasterKey1::health_check_first_message(),
+            &party_two_first_message,
+        );
+        assert_eq!(result.err(), Some(HealthCheckError));
+
+        // party one does not answer a c_check that was not built from c_key
+        let (mut party_two_first_message, party_two_second_message) =
+            party_two_master_key.health_check_first_message(&party_one_first_message);
+        party_two_first_message.c_check = party_one_master_key.public.c_key.clone();
+        let (party_one_second_message, party_one_third_message) = party_one_master_key
+            .health_check_second_message(&party_one_first_message, &party_two_first_message)
+            .expect("bad health check");
+        let party_two_second_message = party_two_master_key
+            .health_check_second_message(
+                &party_two_first_message,
+                &party_one_second_message,
+                party_two_second_message,
+            )
+            .expect("bad health check");
+        let result = party_one_master_key.health_check_third_message(
+            &party_two_first_message,
+            &party_two_second_message,
+            party_one_third_message,
+        );
+        assert_eq!(result.err(), Some(HealthCheckError));
+    }
+
+    #[test]
+    fn test_sign_to_party_two() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let message = BigInt::from(1234);
+        let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
+            MasterKey2::sign_first_message();
+        let (sign_party_one_first_message, eph_ec_key_pair_party1) =
+            party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
+        let signature = party_one_master_key
+            .sign_second_message(
+                &sign_party_two_second_message,
+                &sign_party_two_first_message,
+                &eph_ec_key_pair_party1,
+                &message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad signature");
+        let mut sign_party_one_second_message = MasterKey1::sign_third_message(&signature);
+        let signature = party_two_master_key
+            .sign_third_message(&sign_party_one_second_message, &message)
+            .expect("bad signature");
+        assert_eq!(signature.r, sign_party_one_second_message.signature.r);
+
+        // a signature on another message is rejected by party two
+        let result = party_two_master_key
+            .sign_third_message(&sign_party_one_second_message, &BigInt::from(1));
+        assert_eq!(result.err(), Some(SignError));
+
+        // so is a recovery id that is out of range or recovers another key
+        sign_party_one_second_message.signature.recid = 4;
+        let result =
+            party_two_master_key.sign_third_message(&sign_party_one_second_message, &message);
+        assert_eq!(result.err(), Some(SignError));
+        sign_party_one_second_message.signature.recid = signature.recid ^ 1;
+        let result =
+            party_two_master_key.sign_third_message(&sign_party_one_second_message, &message);
+        assert_eq!(result.err(), Some(SignError));
+    }
+
+    #[test]
+    fn test_sign_with_policy() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let child_pubkey = |index: u32| {
+            let q = party_two_master_key
+                .derive_child(vec![BigInt::from(index)])
+                .public
+                .q;
+            PublicKey::from_slice(&BigInt::to_bytes(&q.bytes_compressed_to_big_int())).unwrap()
+        };
+        let p2wpkh = |pubkey: &PublicKey| Script::new_v0_p2wpkh(&pubkey.wpubkey_hash().unwrap());
+        let g: GE = ECPoint::generator();
+        let alice_q = &g * &FE::new_random();
+        let alice_pubkey =
+            PublicKey::from_slice(&BigInt::to_bytes(&alice_q.bytes_compressed_to_big_int()))
+                .unwrap();
+        let alice = Address::from_script(&p2wpkh(&alice_pubkey), Network::Bitcoin)
+            .unwrap()
+            .to_string();
+        let bob_q = &g * &FE::new_random();
+        let bob_pubkey =
+            PublicKey::from_slice(&BigInt::to_bytes(&bob_q.bytes_compressed_to_big_int())).unwrap();
+        let bob = Address::from_script(&p2wpkh(&bob_pubkey), Network::Bitcoin)
+            .unwrap()
+            .to_string();
+
+        // inputs of child 1 and child 3 paying alice and bob with change to child 2
+        let input_pubkeys = vec![child_pubkey(1), child_pubkey(3)];
+        let change_pubkey = child_pubkey(2);
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![],
+            output: input_pubkeys
+                .iter()
+                .map(|pubkey| TxOut {
+                    value: 50_000,
+                    script_pubkey: p2wpkh(pubkey),
+                })
+                .collect(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(funding.txid(), vout),
+                    script_sig: Script::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::default(),
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: 60_000,
+                    script_pubkey: p2wpkh(&alice_pubkey),
+                },
+                TxOut {
+                    value: 30_000,
+                    script_pubkey: p2wpkh(&bob_pubkey),
+                },
+                TxOut {
+                    value: 9_000,
+                    script_pubkey: p2wpkh(&change_pubkey),
+                },
+            ],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        let fingerprint = master_fingerprint(&party_two_master_key.public.q);
+        let path = |index: u32| DerivationPath::from(vec![ChildNumber::from(index)]);
+        for (index, pubkey) in input_pubkeys.iter().enumerate() {
+            psbt.inputs[index].witness_utxo = Some(funding.output[index].clone());
+            psbt.inputs[index]
+                .bip32_derivation
+                .insert(pubkey.inner, (fingerprint, path(2 * index as u32 + 1)));
+        }
+        psbt.outputs[2]
+            .bip32_derivation
+            .insert(change_pubkey.inner, (fingerprint, path(2)));
+
+        let mut engine = PolicyEngine::default();
+        engine.add_rule(Box::new(Allowlist {
+            destinations: vec![alice.clone(), bob.clone()],
+        }));
+        engine.add_rule(Box::new(DailyLimit { limit: 150_000 }));
+        engine.add_rule(Box::new(Velocity {
+            max_requests: 2,
+            window: 60,
+        }));
+
+        // the provider decodes one request for the whole transaction itself
+        let approved = party_one_master_key
+            .policy_request(&psbt, Network::Bitcoin, 1000)
+            .expect("bad psbt");
+        assert_eq!(approved.txid, psbt.unsigned_tx.txid().to_string());
+        assert_eq!(approved.inputs.len(), 2);
+        for (index, input) in approved.inputs.iter().enumerate() {
+            assert_eq!(input.input, index);
+            assert_eq!(input.message, sighash(&psbt, index).unwrap().2);
+            assert_eq!(input.path, vec![BigInt::from(2 * index as i32 + 1)]);
+        }
+        assert_eq!(
+            approved.outputs,
+            vec![(alice.clone(), 60_000), (bob.clone(), 30_000)]
+        );
+        assert_eq!(approved.amount(), 90_000);
+
+        let mut sign_with_policy = |engine: &mut PolicyEngine,
+                                    request: &PolicyRequest,
+                                    input: usize,
+                                    party_two_path: Vec<BigInt>,
+                                    message: &BigInt| {
+            let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
+                MasterKey2::sign_first_message();
+            let (sign_party_one_first_message, eph_ec_key_pair_party1, approval) =
+                party_one_master_key
+                    .sign_first_message_with_policy(engine, request, input)
+                    .expect("request denied");
+            let sign_party_two_second_message = party_two_master_key
+                .get_child(party_two_path, &mut log, &session_id)
+                .sign_second_message(
+                    &eph_ec_key_pair_party2,
+                    eph_comm_witness,
+                    &sign_party_one_first_message,
+                    message,
+                    &mut log,
+                    &session_id,
+                )
+                .expect("bad epoch");
+            party_one_master_key.sign_second_message_with_policy(
+                approval,
+                &sign_party_two_second_message,
+                &sign_party_two_first_message,
+                &eph_ec_key_pair_party1,
+                &mut log,
+                &session_id,
+            )
+        };
+        for input in &approved.inputs {
+            sign_with_policy(
+                &mut engine,
+                &approved,
+                input.input,
+                input.path.clone(),
+                &input.message,
+            )
+            .expect("bad signature");
+        }
+
+        // party two signs another message than the approved one, or with another child key
+        let input = &approved.inputs[0];
+        let result = sign_with_policy(
+            &mut engine,
+            &approved,
+            0,
+            input.path.clone(),
+            &BigInt::from(4321),
+        );
+        assert_eq!(result.err(), Some(SignError));
+        let result = sign_with_policy(
+            &mut engine,
+            &approved,
+            0,
+            vec![BigInt::from(2)],
+            &input.message,
+        );
+        assert_eq!(result.err(), Some(SignError));
+
+        // the approval only goes with the ephemeral key it was issued for
+        let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
+            MasterKey2::sign_first_message();
+        let (_, _, approval) = party_one_master_key
+            .sign_first_message_with_policy(&mut engine, &approved, 0)
+            .expect("request denied");
+        let (sign_party_one_first_message, eph_ec_key_pair_party1) =
+            party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = party_two_master_key
+            .get_child(input.path.clone(), &mut log, &session_id)
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &input.message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
+        let result = party_one_master_key.sign_second_message_with_policy(
+            approval,
             &sign_party_two_second_message,
             &sign_party_two_first_message,
             &eph_ec_key_pair_party1,
+            &mut log,
+            &session_id,
+        );
+        assert_eq!(result.err(), Some(SignError));
+
+        // the transaction was counted once for all its inputs and signing sessions
+        let request = |txid: &str, outputs: Vec<(&str, u64)>, time: u64| PolicyRequest {
+            txid: txid.to_string(),
+            outputs: outputs
+                .into_iter()
+                .map(|(destination, amount)| (destination.to_string(), amount))
+                .collect(),
+            time,
+            ..approved.clone()
+        };
+        let denied = |engine: &mut PolicyEngine, request: &PolicyRequest| {
+            party_one_master_key
+                .sign_first_message_with_policy(engine, request, 0)
+                .err()
+        };
+        assert_eq!(
+            denied(
+                &mut engine,
+                &request(&approved.txid, vec![(&alice[..], 1)], 1000)
+            ),
+            Some(TransactionInvalid)
+        );
+        assert_eq!(
+            denied(
+                &mut engine,
+                &request("tx2", vec![(&alice[..], 10), ("eve", 10)], 1010)
+            ),
+            Some(DestinationNotAllowed)
+        );
+        // the amounts of all the outputs count against the limit
+        assert_eq!(
+            denied(
+                &mut engine,
+                &request("tx2", vec![(&alice[..], 30_000), (&bob[..], 40_000)], 1010)
+            ),
+            Some(DailyLimitExceeded)
+        );
+        assert_eq!(
+            denied(
+                &mut engine,
+                &request("tx2", vec![(&alice[..], 30_000), (&bob[..], 30_000)], 1010)
+            ),
+            None
+        );
+        assert_eq!(
+            denied(&mut engine, &request("tx3", vec![(&alice[..], 1)], 1020)),
+            Some(VelocityExceeded)
+        );
+        // the daily limit frees up a day after the requests
+        assert_eq!(
+            denied(
+                &mut engine,
+                &request("tx4", vec![(&alice[..], 140_000)], 1010 + 86400)
+            ),
+            None
+        );
+        assert_eq!(
+            denied(&mut engine, &request("tx4", vec![], 1010 + 86400)),
+            Some(TransactionInvalid)
+        );
+
+        // transactions the provider can't decode
+        let mut not_ours = psbt.clone();
+        for input in not_ours.inputs.iter_mut() {
+            input.bip32_derivation.clear();
+        }
+        assert_eq!(
+            party_one_master_key
+                .policy_request(&not_ours, Network::Bitcoin, 1000)
+                .err(),
+            Some(TransactionInvalid)
+        );
+        let mut no_address = psbt.clone();
+        no_address.unsigned_tx.output[1].script_pubkey = Script::new_op_return(&[1, 2, 3]);
+        assert_eq!(
+            party_one_master_key
+                .policy_request(&no_address, Network::Bitcoin, 1000)
+                .err(),
+            Some(DestinationUnknown)
+        );
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let session_id = BigInt::from(7);
+        let mut party_one_log = AuditLog::default();
+        let mut party_two_log = AuditLog::default();
+        let (party_one_master_key, party_two_master_key) =
+            test_key_gen_with_logs(&mut party_one_log, &mut party_two_log, &session_id);
+        assert_eq!(party_one_log.entries.len(), 1);
+
+        let party_one_child =
+            party_one_master_key.get_child(vec![BigInt::from(10)], &mut party_one_log, &session_id);
+        let party_two_child =
+            party_two_master_key.get_child(vec![BigInt::from(10)], &mut party_two_log, &session_id);
+        let head = party_one_log.head();
+
+        // signing records itself, there is no separate append to forget
+        let message = BigInt::from(1234);
+        let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
+            MasterKey2::sign_first_message();
+        let (sign_party_one_first_message, eph_ec_key_pair_party1) =
+            party_one_child.sign_first_message();
+        let sign_party_two_second_message = party_two_child
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &message,
+                &mut party_two_log,
+                &session_id,
+            )
+            .expect("bad epoch");
+        party_one_child
+            .sign_second_message(
+                &sign_party_two_second_message,
+                &sign_party_two_first_message,
+                &eph_ec_key_pair_party1,
+                &message,
+                &mut party_one_log,
+                &session_id,
+            )
+            .expect("bad signature");
+        assert_eq!(party_one_log.entries.len(), 3);
+        assert_eq!(
+            party_one_log.entries[2].record,
+            party_one_child.audit_record(Operation::Sign, Some(&message), &session_id)
+        );
+
+        // both parties log the same operations on the same key
+        assert_eq!(party_one_log, party_two_log);
+        assert_eq!(party_one_log.verify(&head), Ok(()));
+        assert_eq!(party_one_log.verify(&party_two_log.head()), Ok(()));
+
+        let mut modified = party_one_log.clone();
+        modified.entries[1].record.epoch = 1;
+        assert_eq!(modified.verify(&head), Err(AuditError::Modified));
+
+        let mut truncated = party_one_log.clone();
+        truncated.entries.pop();
+        assert_eq!(
+            truncated.verify(&party_one_log.head()),
+            Err(AuditError::Truncated)
+        );
+
+        // the whole log replaced by another one
+        let mut replaced = AuditLog::default();
+        replaced.append(party_one_child.audit_record(Operation::KeyGen, None, &session_id));
+        replaced.append(party_one_child.audit_record(Operation::Sign, Some(&message), &session_id));
+        assert_eq!(replaced.verify(&head), Err(AuditError::Modified));
+
+        // bytes moved from the digest to the session id give another entry
+        let entry = |message: u32, session_id: u32| AuditEntry {
+            record: party_one_child.audit_record(
+                Operation::Sign,
+                Some(&BigInt::from(message)),
+                &BigInt::from(session_id),
+            ),
+            previous: BigInt::from(0),
+        };
+        assert_ne!(entry(0x0102, 0x03).hash(), entry(0x01, 0x0203).hash());
+    }
+
+    #[test]
+    fn test_sign_request() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let raw = |data: &[u8], hash: HashType| SignRequest::Raw {
+            data: data.to_vec(),
+            hash,
+        };
+        let vectors = vec![
+            (
+                raw(b"abc", HashType::Sha256),
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                raw(b"abc", HashType::DoubleSha256),
+                "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358",
+            ),
+            (
+                raw(b"", HashType::Keccak256),
+                "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            ),
+            (
+                raw(b"abc", HashType::Tagged("BIP0340/challenge".to_string())),
+                "770a5b7e7c304bbcc3ea107343ff951dd404312ef418db0c3b94e2ebfbb50087",
+            ),
+        ];
+        for (request, digest) in vectors {
+            assert_eq!(request.message(), Ok(BigInt::from_hex(digest)));
+        }
+        assert_eq!(
+            SignRequest::Digest(vec![1; 31]).message(),
+            Err(SignRequestError::DigestLength)
+        );
+        assert_eq!(
+            raw(b"abc", HashType::Tagged(String::new())).message(),
+            Err(SignRequestError::EmptyTag)
+        );
+
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let request = raw(b"pay bob 10", HashType::DoubleSha256);
+        let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
+            MasterKey2::sign_first_message();
+        let (sign_party_one_first_message, eph_ec_key_pair_party1) =
+            party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = party_two_master_key
+            .sign_second_message_request(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                &request,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
+        party_one_master_key
+            .sign_second_message_request(
+                &sign_party_two_second_message,
+                &sign_party_two_first_message,
+                &eph_ec_key_pair_party1,
+                &request,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad signature");
+
+        // the same data under another hash is another message
+        let result = party_one_master_key.sign_second_message_request(
+            &sign_party_two_second_message,
+            &sign_party_two_first_message,
+            &eph_ec_key_pair_party1,
+            &raw(b"pay bob 10", HashType::Sha256),
+            &mut log,
+            &session_id,
+        );
+        assert_eq!(result.err(), Some(SignError));
+    }
+
+    #[test]
+    fn test_psbt_sighash() {
+        // bip143 native p2wpkh example
+        let tx: Transaction = deserialize(
+            &Vec::<u8>::from_hex(
+                "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f000000\
+                 0000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a01000000\
+                 00ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093\
+                 510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        let pubkey = PublicKey::from_slice(
+            &Vec::<u8>::from_hex(
+                "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 600_000_000,
+            script_pubkey: Script::new_v0_p2wpkh(&pubkey.wpubkey_hash().unwrap()),
+        });
+        assert_eq!(
+            sighash(&psbt, 1),
+            Ok((
+                SighashKind::SegwitV0,
+                1,
+                BigInt::from_hex(
+                    "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+                )
+            ))
+        );
+        assert_eq!(sighash(&psbt, 0).err(), Some(PsbtError::MissingUtxo));
+
+        // bip174 valid vector: a p2pkh input and a p2sh-p2wpkh input with keys of another wallet
+        let psbt: PartiallySignedTransaction = deserialize(
+            &Vec::<u8>::from_hex(
+                "70736274ff0100a00200000002ab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7b\
+                 e40000000000feffffffab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40100\
+                 000000feffffff02603bea0b000000001976a914768a40bbd740cbe81d988e71de2a4d5c71396b1d88ac8e24\
+                 0000000000001976a9146f4620b553fa095e721b9ee0efe9fa039cca459788ac00000000000100df02000000\
+                 01268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf6000000006a473044022070\
+                 b2245123e6bf474d60c5b50c043d4c691a5d2435f09a34a7662a9dc251790a022001329ca9dacf280bdf3074\
+                 0ec0390422422c81cb45839457aeb76fc12edd95b3012102657d118d3357b8e0f4c2cd46db7b39f6d9c38d9a\
+                 70abcb9b2de5dc8dbfe4ce31feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4\
+                 665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e130000\
+                 01012000e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787010416001485d13537\
+                 f2e265405a34dbafa9e3dda01fb8230800220202ead596687ca806043edc3de116cdf29d5e9257c196cd055c\
+                 f698c8d02bf24e9910b4a6ba670000008000000080020000800022020394f62be9df19952c5587768aeb7698\
+                 061ad2c4a25c894f47d8c162b4d7213d0510b4a6ba6700000080010000800200008000",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(sighash(&psbt, 0).unwrap().0, SighashKind::Legacy);
+        assert_eq!(sighash(&psbt, 1).unwrap().0, SighashKind::SegwitV0);
+        let (party_one_master_key, _) = test_key_gen();
+        assert_eq!(party_one_master_key.psbt_sign_jobs(&psbt), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_psbt_bip174_signer() {
+        // the psbts of the two signers of the bip174 test vectors, each adds one signature to the
+        // p2sh multisig input and one to the p2sh-p2wsh multisig input
+        let signed = vec![
+            "cHNidP8BAJoCAAAAAljoeiG1ba8MI76OcHBFbDNvfLqlyHV5JPVFiHuyq911AAAAAAD/////g40EJ9DsZQpoqk\
+             a7CwmK6kQiwHGyyng1Kgd5WdB86h0BAAAAAP////8CcKrwCAAAAAAWABTYXCtx0AYLCcmIauuBXlCZHdoSTQDh\
+             9QUAAAAAFgAUAK6pouXw+HaliN9VRuh0LR2HAI8AAAAAAAEAuwIAAAABqtc5MQGL0l+ErkALaISL4J23BurCrB\
+             gpi6vucatlb4sAAAAASEcwRAIgWPb8fGoz4bMVSNSByCbAFb0wE1qtQs1neQ2rZtKtJDsCIEoc7SYExnNbY5Pl\
+             tBaR3XiwDwxZQvufdRhW+qk4FX26Af7///8CgPD6AgAAAAAXqRQPuUY0IWlrgsgzryQceMF9295JNIfQ8gonAQ\
+             AAABepFCnKdPigj4GZlCgYXJe12FLkBj9hh2UAAAAiAgKVg785rgpgl0etGZrd1jT6YQhVnWxc05tMIYPxq5bg\
+             f0cwRAIgdAGK1BgAl7hzMjwAFXILNoTMgSOJEEjn282bVa1nnJkCIHPTabdA4+tT3O+jOCPIBwUUylWn3ZVE8V\
+             fBZ5EyYRGMAQEDBAEAAAABBEdSIQKVg785rgpgl0etGZrd1jT6YQhVnWxc05tMIYPxq5bgfyEC2rYf9JoU22p9\
+             ArDNH7t4/EsYMStbTlTa5Nui+/71NtdSriIGApWDvzmuCmCXR60Zmt3WNPphCFWdbFzTm0whg/GrluB/ENkMak\
+             8AAACAAAAAgAAAAIAiBgLath/0mhTban0CsM0fu3j8SxgxK1tOVNrk26L7/vU21xDZDGpPAAAAgAAAAIABAACA\
+             AAEBIADC6wsAAAAAF6kUt/X69A49QKWkWbHbNTXyty+pIeiHIgIDCJ3BDHrG21T5EymvYXMz2ziM6tDCMfcjN5\
+             0bmQMLAtxHMEQCIGLrelVhB6fHP0WsSrWh3d9vcHX7EnWWmn84Pv/3hLyyAiAMBdu3Rw2/LwhVfdNWxzJcHtMJ\
+             E+mWzThAlF2xIijaXwEBAwQBAAAAAQQiACCMI1MXN0O1ld+0oHtyuo5C43l9p06H/n2ddJfjsgKJAwEFR1IhAw\
+             idwQx6xttU+RMpr2FzM9s4jOrQwjH3IzedG5kDCwLcIQI63ZBPPW3PWd25BrDe4jUpt/+57VDl6GFRkmhgIh8O\
+             c1KuIgYCOt2QTz1tz1nduQaw3uI1Kbf/ue1Q5ehhUZJoYCIfDnMQ2QxqTwAAAIAAAACAAwAAgCIGAwidwQx6xt\
+             tU+RMpr2FzM9s4jOrQwjH3IzedG5kDCwLcENkMak8AAACAAAAAgAIAAIAAIgIDqaTDf1mW06ol26xrVwrwZQOU\
+             SSlCRgs1R1Ptnuylh3EQ2QxqTwAAAIAAAACABAAAgAAiAgJ/Y5l1fS7/VaE2rQLGhLGDi2VW5fG2s0KCqUtrUA\
+             UQlhDZDGpPAAAAgAAAAIAFAACAAA==",
+            "cHNidP8BAJoCAAAAAljoeiG1ba8MI76OcHBFbDNvfLqlyHV5JPVFiHuyq911AAAAAAD/////g40EJ9DsZQpoqk\
+             a7CwmK6kQiwHGyyng1Kgd5WdB86h0BAAAAAP////8CcKrwCAAAAAAWABTYXCtx0AYLCcmIauuBXlCZHdoSTQDh\
+             9QUAAAAAFgAUAK6pouXw+HaliN9VRuh0LR2HAI8AAAAAAAEAuwIAAAABqtc5MQGL0l+ErkALaISL4J23BurCrB\
+             gpi6vucatlb4sAAAAASEcwRAIgWPb8fGoz4bMVSNSByCbAFb0wE1qtQs1neQ2rZtKtJDsCIEoc7SYExnNbY5Pl\
+             tBaR3XiwDwxZQvufdRhW+qk4FX26Af7///8CgPD6AgAAAAAXqRQPuUY0IWlrgsgzryQceMF9295JNIfQ8gonAQ\
+             AAABepFCnKdPigj4GZlCgYXJe12FLkBj9hh2UAAAAiAgLath/0mhTban0CsM0fu3j8SxgxK1tOVNrk26L7/vU2\
+             10gwRQIhAPYQOLMI3B2oZaNIUnRvAVdyk0IIxtJEVDk82ZvfIhd3AiAFbmdaZ1ptCgK4WxTl4pB02KJam1dgvq\
+             KBb2YZEKAG6gEBAwQBAAAAAQRHUiEClYO/Oa4KYJdHrRma3dY0+mEIVZ1sXNObTCGD8auW4H8hAtq2H/SaFNtq\
+             fQKwzR+7ePxLGDErW05U2uTbovv+9TbXUq4iBgKVg785rgpgl0etGZrd1jT6YQhVnWxc05tMIYPxq5bgfxDZDG\
+             pPAAAAgAAAAIAAAACAIgYC2rYf9JoU22p9ArDNH7t4/EsYMStbTlTa5Nui+/71NtcQ2QxqTwAAAIAAAACAAQAA\
+             gAABASAAwusLAAAAABepFLf1+vQOPUClpFmx2zU18rcvqSHohyICAjrdkE89bc9Z3bkGsN7iNSm3/7ntUOXoYV\
+             GSaGAiHw5zRzBEAiBl9FulmYtZon/+GnvtAWrx8fkNVLOqj3RQql9WolEDvQIgf3JHA60e25ZoCyhLVtT/y4j3\
+             +3Weq74IqjDym4UTg9IBAQMEAQAAAAEEIgAgjCNTFzdDtZXftKB7crqOQuN5fadOh/59nXSX47ICiQMBBUdSIQ\
+             MIncEMesbbVPkTKa9hczPbOIzq0MIx9yM3nRuZAwsC3CECOt2QTz1tz1nduQaw3uI1Kbf/ue1Q5ehhUZJoYCIf\
+             DnNSriIGAjrdkE89bc9Z3bkGsN7iNSm3/7ntUOXoYVGSaGAiHw5zENkMak8AAACAAAAAgAMAAIAiBgMIncEMes\
+             bbVPkTKa9hczPbOIzq0MIx9yM3nRuZAwsC3BDZDGpPAAAAgAAAAIACAACAACICA6mkw39ZltOqJdusa1cK8GUD\
+             lEkpQkYLNUdT7Z7spYdxENkMak8AAACAAAAAgAQAAIAAIgICf2OZdX0u/1WhNq0CxoSxg4tlVuXxtrNCgqlLa1\
+             AFEJYQ2QxqTwAAAIAAAACABQAAgAA=",
+        ];
+        let kinds = [SighashKind::Legacy, SighashKind::SegwitV0];
+        let secp = Secp256k1::verification_only();
+        for vector in signed {
+            let signed: PartiallySignedTransaction =
+                deserialize(&base64::decode(vector).unwrap()).unwrap();
+            let mut unsigned = signed.clone();
+            for index in 0..2 {
+                let (kind, sighash_type, message) = sighash(&signed, index).unwrap();
+                assert_eq!(kind, kinds[index]);
+                assert_eq!(signed.inputs[index].partial_sigs.len(), 1);
+                let (pubkey, sig) = signed.inputs[index].partial_sigs.iter().next().unwrap();
+                let digest = Message::from_slice(&to_32_bytes(&message)).unwrap();
+                assert!(secp.verify_ecdsa(&digest, &sig.sig, &pubkey.inner).is_ok());
+
+                // the signature added through a sign job gives the psbt of the signer
+                let uncompressed = pubkey.inner.serialize_uncompressed();
+                let job = SignJob {
+                    input: index,
+                    path: vec![],
+                    pubkey: GE::from_coor(
+                        &BigInt::from(&uncompressed[1..33]),
+                        &BigInt::from(&uncompressed[33..]),
+                    ),
+                    kind,
+                    sighash_type,
+                    message,
+                };
+                let compact = sig.sig.serialize_compact();
+                let signature = party_one::SignatureRecid {
+                    r: BigInt::from(&compact[..32]),
+                    s: BigInt::from(&compact[32..]),
+                    recid: 0,
+                };
+                unsigned.inputs[index].partial_sigs.clear();
+                assert_eq!(add_ecdsa_signature(&mut unsigned, &job, &signature), Ok(()));
+            }
+            assert_eq!(unsigned, signed);
+        }
+    }
+
+    #[test]
+    fn test_psbt_sign() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let child_pubkey = |index: u32| {
+            let q = party_two_master_key
+                .derive_child(vec![BigInt::from(index)])
+                .public
+                .q;
+            PublicKey::from_slice(&BigInt::to_bytes(&q.bytes_compressed_to_big_int())).unwrap()
+        };
+        let legacy_pubkey = child_pubkey(0);
+        let segwit_pubkey = child_pubkey(1);
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: 50_000,
+                    script_pubkey: Script::new_p2pkh(&legacy_pubkey.pubkey_hash()),
+                },
+                TxOut {
+                    value: 70_000,
+                    script_pubkey: Script::new_v0_p2wpkh(&segwit_pubkey.wpubkey_hash().unwrap()),
+                },
+            ],
+        };
+        let spend = |vout| TxIn {
+            previous_output: OutPoint::new(funding.txid(), vout),
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::default(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![spend(0), spend(1)],
+            output: vec![funding.output[1].clone()],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(funding.clone());
+        psbt.inputs[1].witness_utxo = Some(funding.output[1].clone());
+        let fingerprint = master_fingerprint(&party_two_master_key.public.q);
+        for (index, pubkey) in vec![legacy_pubkey, segwit_pubkey].into_iter().enumerate() {
+            let path = DerivationPath::from(vec![ChildNumber::from(index as u32)]);
+            psbt.inputs[index]
+                .bip32_derivation
+                .insert(pubkey.inner, (fingerprint, path));
+        }
+
+        // both parties find the same inputs and sighashes
+        let jobs = party_one_master_key.psbt_sign_jobs(&psbt).unwrap();
+        assert_eq!(party_two_master_key.psbt_sign_jobs(&psbt), Ok(jobs.clone()));
+        assert_eq!(
+            jobs.iter()
+                .map(|job| job.kind)
+                .collect::<Vec<SighashKind>>(),
+            vec![SighashKind::Legacy, SighashKind::SegwitV0]
+        );
+
+        let mut signatures = Vec::new();
+        for job in &jobs {
+            let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
+                MasterKey2::sign_first_message();
+            let (sign_party_one_first_message, eph_ec_key_pair_party1) =
+                party_one_master_key.sign_first_message();
+            let sign_party_two_second_message = party_two_master_key
+                .psbt_sign_second_message(
+                    &psbt,
+                    job,
+                    &eph_ec_key_pair_party2,
+                    eph_comm_witness,
+                    &sign_party_one_first_message,
+                    &mut log,
+                    &session_id,
+                )
+                .expect("bad psbt job");
+            let signature = party_one_master_key
+                .psbt_sign_second_message(
+                    &mut psbt,
+                    job,
+                    &sign_party_two_second_message,
+                    &sign_party_two_first_message,
+                    &eph_ec_key_pair_party1,
+                    &mut log,
+                    &session_id,
+                )
+                .expect("bad psbt signature");
+            signatures.push(signature);
+        }
+        assert_eq!(psbt.inputs[0].partial_sigs.len(), 1);
+        assert!(psbt.inputs[1].partial_sigs.contains_key(&segwit_pubkey));
+
+        // a job not matching the psbt and a signature of another input are refused
+        let mut job = jobs[1].clone();
+        job.message = BigInt::from(1);
+        assert_eq!(
+            add_ecdsa_signature(&mut psbt, &job, &signatures[1]),
+            Err(PsbtError::SighashMismatch)
+        );
+        assert_eq!(
+            add_ecdsa_signature(&mut psbt, &jobs[1], &signatures[0]),
+            Err(PsbtError::SignatureInvalid)
+        );
+        let (_, eph_comm_witness, eph_ec_key_pair_party2) = MasterKey2::sign_first_message();
+        let (sign_party_one_first_message, _) = party_one_master_key.sign_first_message();
+        assert_eq!(
+            party_two_master_key
+                .psbt_sign_second_message(
+                    &psbt,
+                    &job,
+                    &eph_ec_key_pair_party2,
+                    eph_comm_witness,
+                    &sign_party_one_first_message,
+                    &mut log,
+                    &session_id,
+                )
+                .err(),
+            Some(PsbtError::SighashMismatch)
+        );
+
+        // a witness utxo has to agree with the previous transaction
+        psbt.inputs[0].witness_utxo = Some(funding.output[1].clone());
+        assert_eq!(
+            party_one_master_key.psbt_sign_jobs(&psbt),
+            Err(PsbtError::UtxoMismatch)
+        );
+    }
+
+    #[test]
+    fn test_message_signing() {
+        // bip322 vector for the empty message
+        let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap();
+        let to_spend = bip322_to_spend(b"", &address.script_pubkey());
+        assert_eq!(
+            to_spend.txid().to_hex(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            bip322_to_sign(&to_spend).txid().to_hex(),
+            "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6"
+        );
+
+        assert_eq!(
+            eip191_digest(b"Hello World"),
+            BigInt::from_hex("a1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2")
+        );
+
+        // eip712 example mail
+        let address = |hex: &str| {
+            let mut address = [0u8; 20];
+            address.copy_from_slice(&Vec::<u8>::from_hex(hex).unwrap());
+            Eip712Value::Address(address)
+        };
+        let person = |name: &str, wallet: &str| {
+            Eip712Value::Struct(Eip712Struct {
+                name: "Person".to_string(),
+                fields: vec![
+                    ("name".to_string(), Eip712Value::String(name.to_string())),
+                    ("wallet".to_string(), address(wallet)),
+                ],
+            })
+        };
+        let domain = Eip712Struct {
+            name: "EIP712Domain".to_string(),
+            fields: vec![
+                (
+                    "name".to_string(),
+                    Eip712Value::String("Ether Mail".to_string()),
+                ),
+                ("version".to_string(), Eip712Value::String("1".to_string())),
+                (
+                    "chainId".to_string(),
+                    Eip712Value::Uint {
+                        bits: 256,
+                        value: BigInt::from(1),
+                    },
+                ),
+                (
+                    "verifyingContract".to_string(),
+                    address("cccccccccccccccccccccccccccccccccccccccc"),
+                ),
+            ],
+        };
+        let mail = Eip712Struct {
+            name: "Mail".to_string(),
+            fields: vec![
+                (
+                    "from".to_string(),
+                    person("Cow", "cd2a3d9f938e13cd947ec05abc7fe734df8dd826"),
+                ),
+                (
+                    "to".to_string(),
+                    person("Bob", "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"),
+                ),
+                (
+                    "contents".to_string(),
+                    Eip712Value::String("Hello, Bob!".to_string()),
+                ),
+            ],
+        };
+        assert_eq!(
+            eip712_encode_type(&mail),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        let typed_data_digest = eip712_digest(&domain, &mail).unwrap();
+        assert_eq!(
+            typed_data_digest,
+            BigInt::from_hex("be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2")
+        );
+
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let q = party_two_master_key.public.q.clone();
+        let message = b"proof of ownership";
+
+        let signature = test_sign_message(
+            &party_one_master_key,
+            &party_two_master_key,
+            &bip137_digest(message),
+        );
+        let encoded = bip137_signature(&signature, Bip137AddressType::P2wpkh);
+        assert_eq!(
+            bip137_verify(message, &encoded, &q),
+            Ok(Bip137AddressType::P2wpkh)
+        );
+        assert_eq!(
+            bip137_verify(b"another message", &encoded, &q),
+            Err(MessageSignError::SignatureInvalid)
+        );
+
+        let digest = bip322_simple_digest(message, &q).unwrap();
+        let signature = test_sign_message(&party_one_master_key, &party_two_master_key, &digest);
+        let encoded = bip322_simple_signature(&signature, &q).unwrap();
+        assert_eq!(bip322_simple_verify(message, &encoded, &q), Ok(()));
+        assert_eq!(
+            bip322_simple_verify(b"another message", &encoded, &q),
+            Err(MessageSignError::SignatureInvalid)
+        );
+
+        let digest = eip191_digest(message);
+        let signature = test_sign_message(&party_one_master_key, &party_two_master_key, &digest);
+        let encoded = eth_signature(&signature);
+        assert_eq!(eth_verify(&digest, &encoded, &q), Ok(()));
+
+        let signature = test_sign_message(
+            &party_one_master_key,
+            &party_two_master_key,
+            &typed_data_digest,
+        );
+        let encoded = eth_signature(&signature);
+        assert_eq!(eth_verify(&typed_data_digest, &encoded, &q), Ok(()));
+        assert_eq!(
+            eth_verify(&digest, &encoded, &q),
+            Err(MessageSignError::SignatureInvalid)
+        );
+    }
+
+    #[test]
+    fn test_ecdh() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let g: GE = ECPoint::generator();
+        let r: FE = ECScalar::new_random();
+        let point = &g * &r;
+
+        let party_one_first_message = party_one_master_key.ecdh_first_message(&point);
+        let (party_two_first_message, shared) = party_two_master_key
+            .ecdh_first_message(&point, &party_one_first_message)
+            .expect("bad ecdh");
+        assert_eq!(shared, &party_two_master_key.public.q * &r);
+        assert_eq!(
+            party_one_master_key
+                .ecdh_second_message(&party_one_first_message, &party_two_first_message),
+            Ok(shared)
+        );
+
+        // partial points are bound to the public shares
+        let mut party_one_first_message = party_one_master_key.ecdh_first_message(&point);
+        party_one_first_message.partial_ecdh.point = &point * &r;
+        let result = party_two_master_key.ecdh_first_message(&point, &party_one_first_message);
+        assert_eq!(result.err(), Some(EcdhError::ProofInvalid));
+
+        // ecies to the joint key of a child
+        let party_one_child =
+            party_one_master_key.get_child(vec![BigInt::from(10)], &mut log, &session_id);
+        let party_two_child =
+            party_two_master_key.get_child(vec![BigInt::from(10)], &mut log, &session_id);
+        let ciphertext = ecies_encrypt(&party_two_child.public.q, b"for both parties").unwrap();
+        let party_one_first_message = party_one_child.ecdh_first_message(&ciphertext.ephemeral);
+        let (_, shared) = party_two_child
+            .ecdh_first_message(&ciphertext.ephemeral, &party_one_first_message)
+            .expect("bad ecdh");
+        assert_eq!(
+            ecies_decrypt(&ciphertext, &shared),
+            Ok(b"for both parties".to_vec())
+        );
+        assert_eq!(
+            ecies_decrypt(&ciphertext, &point),
+            Err(EcdhError::Decryption)
+        );
+    }
+
+    #[test]
+    fn test_adaptor_sign() {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (party_one_master_key, party_two_master_key) = test_key_gen();
+        let g: GE = ECPoint::generator();
+        let t: FE = ECScalar::new_random();
+        let adaptor_point = &g * &t;
+        let message = BigInt::from(1234);
+
+        let (party_two_first_message, party_two_eph_key_pair) =
+            MasterKey2::adaptor_sign_first_message(&adaptor_point);
+        let (party_one_first_message, party_one_eph_key_pair) =
+            party_one_master_key.adaptor_sign_first_message(&adaptor_point);
+        let party_two_second_message = party_two_master_key
+            .adaptor_sign_second_message(
+                party_two_eph_key_pair,
+                &party_one_first_message,
+                &message,
+                &adaptor_point,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad adaptor sign");
+        let pre_signature = party_one_master_key
+            .adaptor_pre_signature(
+                &party_two_first_message,
+                &party_two_second_message,
+                &party_one_eph_key_pair,
+                &message,
+                &adaptor_point,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad pre signature");
+
+        // party two and the counter party of the swap check it against public data only
+        let q = party_two_master_key.public.q.clone();
+        assert_eq!(pre_signature.verify(&q, &message, &adaptor_point), Ok(()));
+        assert_eq!(
+            pre_signature.verify(&q, &BigInt::from(4321), &adaptor_point),
+            Err(AdaptorError::PreSignatureInvalid)
+        );
+        assert_eq!(
+            pre_signature.verify(&q, &message, &g),
+            Err(AdaptorError::ProofInvalid)
+        );
+        let unadapted = party_one::Signature {
+            r: adaptor::nonce_r(&pre_signature.adapted_nonce).to_big_int(),
+            s: pre_signature.s.clone(),
+        };
+        assert!(party_one::verify(&unadapted, &q, &message).is_err());
+
+        let signature = pre_signature.adapt(&t);
+        assert!(party_one::verify(&signature, &q, &message).is_ok());
+        assert_eq!(pre_signature.extract(&signature, &adaptor_point), Ok(t));
+        let other_signature = pre_signature.adapt(&ECScalar::new_random());
+        assert_eq!(
+            pre_signature.extract(&other_signature, &adaptor_point),
+            Err(AdaptorError::WitnessMismatch)
+        );
+
+        // party two is bound to the nonce it committed to
+        let (party_two_first_message, party_two_eph_key_pair) =
+            MasterKey2::adaptor_sign_first_message(&adaptor_point);
+        let (party_one_first_message, party_one_eph_key_pair) =
+            party_one_master_key.adaptor_sign_first_message(&adaptor_point);
+        let mut party_two_second_message = party_two_master_key
+            .adaptor_sign_second_message(
+                party_two_eph_key_pair,
+                &party_one_first_message,
+                &message,
+                &adaptor_point,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad adaptor sign");
+        party_two_second_message.r2 = &party_two_second_message.r2 + &g;
+        let result = party_one_master_key.adaptor_pre_signature(
+            &party_two_first_message,
+            &party_two_second_message,
+            &party_one_eph_key_pair,
             &message,
+            &adaptor_point,
+            &mut log,
+            &session_id,
         );
-        sign_party_one_second_message.expect("bad signature");
+        assert_eq!(result.err(), Some(SignError));
     }
 
     pub fn test_key_gen() -> (MasterKey1, MasterKey2) {
+        test_key_gen_with_logs(
+            &mut AuditLog::default(),
+            &mut AuditLog::default(),
+            &BigInt::from(1),
+        )
+    }
+
+    pub fn test_key_gen_with_logs(
+        party_one_log: &mut AuditLog,
+        party_two_log: &mut AuditLog,
+        session_id: &BigInt,
+    ) -> (MasterKey1, MasterKey2) {
         // key gen
         let (kg_party_one_first_message, kg_comm_witness, kg_ec_key_pair_party1) =
             MasterKey1::key_gen_first_message();
@@ -423,6 +2280,33 @@ mod tests {
         let party_two_paillier = key_gen_second_message.unwrap().1;
 
         // chain code
+        let (party1_cc, party2_cc) = test_chain_code();
+        // set master keys:
+        let party_one_master_key = MasterKey1::set_master_key(
+            &party1_cc.chain_code,
+            party_one_private,
+            &kg_comm_witness.public_share,
+            &kg_party_two_first_message.public_share,
+            party_one_paillier_key_pair,
+            party_one_log,
+            session_id,
+        );
+
+        let party_two_master_key = MasterKey2::set_master_key(
+            &party2_cc.chain_code,
+            &kg_ec_key_pair_party2,
+            &kg_party_one_second_message
+                .ecdh_second_message
+                .comm_witness
+                .public_share,
+            &party_two_paillier,
+            party_two_log,
+            session_id,
+        );
+        (party_one_master_key, party_two_master_key)
+    }
+
+    pub fn test_chain_code() -> (party1::ChainCode1, party2::ChainCode2) {
         let (cc_party_one_first_message, cc_comm_witness, cc_ec_key_pair1) =
             party1::ChainCode1::chain_code_first_message();
         let (cc_party_two_first_message, cc_ec_key_pair2) =
@@ -447,31 +2331,95 @@ mod tests {
             &cc_ec_key_pair2,
             &cc_party_one_second_message.comm_witness.public_share,
         );
-        // set master keys:
-        let party_one_master_key = MasterKey1::set_master_key(
-            &party1_cc.chain_code,
-            party_one_private,
-            &kg_comm_witness.public_share,
-            &kg_party_two_first_message.public_share,
-            party_one_paillier_key_pair,
-        );
+        (party1_cc, party2_cc)
+    }
 
-        let party_two_master_key = MasterKey2::set_master_key(
-            &party2_cc.chain_code,
-            &kg_ec_key_pair_party2,
-            &kg_party_one_second_message
-                .ecdh_second_message
-                .comm_witness
-                .public_share,
-            &party_two_paillier,
+    pub fn test_sign(party_one_master_key: &MasterKey1, party_two_master_key: &MasterKey2) {
+        let message = BigInt::from(1234);
+        let signature = test_sign_message(party_one_master_key, party_two_master_key, &message);
+        party_two_master_key
+            .sign_third_message(&MasterKey1::sign_third_message(&signature), &message)
+            .expect("bad signature");
+    }
+
+    pub fn test_sign_message(
+        party_one_master_key: &MasterKey1,
+        party_two_master_key: &MasterKey2,
+        message: &BigInt,
+    ) -> party_one::SignatureRecid {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
+        let (sign_party_two_first_message, eph_comm_witness, eph_ec_key_pair_party2) =
+            MasterKey2::sign_first_message();
+        let (sign_party_one_first_message, eph_ec_key_pair_party1) =
+            party_one_master_key.sign_first_message();
+        let sign_party_two_second_message = party_two_master_key
+            .sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness,
+                &sign_party_one_first_message,
+                message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad epoch");
+        party_one_master_key
+            .sign_second_message(
+                &sign_party_two_second_message,
+                &sign_party_two_first_message,
+                &eph_ec_key_pair_party1,
+                message,
+                &mut log,
+                &session_id,
+            )
+            .expect("bad signature")
+    }
+
+    pub fn test_run_health_check(
+        party_one_master_key: &MasterKey1,
+        party_two_master_key: &MasterKey2,
+    ) -> Result<(), Errors> {
+        let party_one_first_message = MasterKey1::health_check_first_message();
+        let (party_two_first_message, party_two_second_message) =
+            party_two_master_key.health_check_first_message(&party_one_first_message);
+        let (party_one_second_message, party_one_third_message) = party_one_master_key
+            .health_check_second_message(&party_one_first_message, &party_two_first_message)?;
+        let party_two_second_message = party_two_master_key.health_check_second_message(
+            &party_two_first_message,
+            &party_one_second_message,
+            party_two_second_message,
+        )?;
+        let party_one_third_message = party_one_master_key.health_check_third_message(
+            &party_two_first_message,
+            &party_two_second_message,
+            party_one_third_message,
+        )?;
+        party_two_master_key.health_check_third_message(
+            &party_two_second_message,
+            &party_one_second_message,
+            &party_one_third_message,
+        )
+    }
+
+    pub fn test_coin_flip() -> (Rotation, Rotation) {
+        let (party1_first_message, m1, r1) = Rotation1::key_rotate_first_message();
+        let party2_first_message = Rotation2::key_rotate_first_message(&party1_first_message);
+        let (party1_second_message, random1) =
+            Rotation1::key_rotate_second_message(&party2_first_message, &m1, &r1);
+        let random2 = Rotation2::key_rotate_second_message(
+            &party1_second_message,
+            &party2_first_message,
+            &party1_first_message,
         );
-        (party_one_master_key, party_two_master_key)
+        (random1, random2)
     }
 
     pub fn test_rotation(
         party_one_master_key: MasterKey1,
         party_two_master_key: MasterKey2,
     ) -> (MasterKey1, MasterKey2) {
+        let mut log = AuditLog::default();
+        let session_id = BigInt::from(1);
         //coin flip:
         let (party1_first_message, m1, r1) = Rotation1::key_rotate_first_message();
         let party2_first_message = Rotation2::key_rotate_first_message(&party1_first_message);
@@ -484,11 +2432,18 @@ mod tests {
         );
 
         //rotation:
-        let (rotation_party_one_first_message, party_one_master_key_rotated) =
-            party_one_master_key.rotation_first_message(&random1);
+        let (rotation_party_one_first_message, party_one_master_key_rotated) = party_one_master_key
+            .rotation_first_message(&random1, &mut EpochLedger::new(), &mut log, &session_id)
+            .expect("bad rotation");
 
-        let result_rotate_party_two =
-            party_two_master_key.rotate_first_message(&random2, &rotation_party_one_first_message, SALT_STRING);
+        let result_rotate_party_two = party_two_master_key.rotate_first_message(
+            &random2,
+            &rotation_party_one_first_message,
+            SALT_STRING,
+            &mut EpochLedger::new(),
+            &mut log,
+            &session_id,
+        );
         assert!(result_rotate_party_two.is_ok());
 
         (
@@ -0,0 +1,72 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// building blocks of the share health check: a party proves it still holds the share behind
+// its public point, bound to a fresh challenge of the counter party so that old proofs can't
+// be replayed and nothing that verifies as a signature is produced.
+
+use curv::arithmetic::traits::Samplable;
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::BigInt;
+use Errors::{self, HealthCheckError};
+
+const CHALLENGE_BITS: usize = 256;
+
+// schnorr proof of knowledge of the share: z = r + e * x with e = H(challenge, public share, com)
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ShareProof {
+    pub com: GE,
+    pub z: FE,
+}
+
+pub fn new_challenge() -> BigInt {
+    BigInt::sample(CHALLENGE_BITS)
+}
+
+pub fn challenge_hash(challenge: &BigInt, public_share: &GE, com: &GE) -> FE {
+    let e = HSha256::create_hash(&[
+        challenge,
+        &public_share.bytes_compressed_to_big_int(),
+        &com.bytes_compressed_to_big_int(),
+    ]);
+    ECScalar::from(&e)
+}
+
+impl ShareProof {
+    pub fn prove(secret_share: &FE, challenge: &BigInt) -> ShareProof {
+        let g: GE = ECPoint::generator();
+        let r: FE = ECScalar::new_random();
+        let com = &g * &r;
+        let e = challenge_hash(challenge, &(&g * secret_share), &com);
+        ShareProof {
+            com,
+            z: r + &(e * secret_share),
+        }
+    }
+
+    pub fn verify(&self, public_share: &GE, challenge: &BigInt) -> Result<(), Errors> {
+        let g: GE = ECPoint::generator();
+        let e = challenge_hash(challenge, public_share, &self.com);
+        if &g * &self.z == &self.com + &(public_share * &e) {
+            Ok(())
+        } else {
+            Err(HealthCheckError)
+        }
+    }
+}
@@ -16,24 +16,181 @@
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+extern crate serde_json;
+
+extern crate aes_gcm;
+extern crate base64;
+extern crate bitcoin;
+extern crate bs58;
 
 extern crate centipede;
 extern crate curv;
 extern crate multi_party_ecdsa;
 extern crate multi_party_schnorr;
 extern crate paillier;
+extern crate sha2;
+extern crate sha3;
 extern crate zk_paillier;
 
+use std::fmt;
+
+pub mod audit;
 pub mod chain_code;
+pub mod ecdh;
 pub mod ecdsa;
+pub mod health_check;
+pub mod message_signing;
+pub mod policy;
+pub mod psbt;
 pub mod rotation;
 pub mod schnorr;
+pub mod sign_request;
 pub mod traits;
+pub mod util;
+pub mod vrf;
 pub use traits::*;
 pub mod poc;
+pub mod validation;
 
 #[derive(Copy, PartialEq, Eq, Clone, Debug)]
 pub enum Errors {
     KeyGenError,
     SignError,
+    ExportError,
+    ProviderReplaceError,
+    DeviceMoveError,
+    RefreshError,
+    RotationError,
+    EpochError,
+    HealthCheckError,
+    ChainCodeError,
+}
+
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum RecoveryError {
+    PublicShareMismatch,
+    PublicKeyMismatch,
+    FingerprintMismatch,
+    PaillierProofError,
+    // the recovered key does not follow the last entry of the epoch ledger
+    EpochMismatch,
+}
+
+// a log that does not chain up or no longer reaches a head taken earlier
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum AuditError {
+    Modified,
+    Truncated,
+}
+
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum EcdhError {
+    // a partial point was not computed with the share behind the public share
+    ProofInvalid,
+    Encryption,
+    Decryption,
+}
+
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum AdaptorError {
+    PreSignatureInvalid,
+    // the dleq proofs of the ecdsa pre signature
+    ProofInvalid,
+    // the signature does not complete the pre signature with the dlog of the adaptor point
+    WitnessMismatch,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum BatchVerifyError {
+    // indices of the signatures in the batch that do not verify on their own
+    SignatureInvalid(Vec<usize>),
+}
+
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum VrfError {
+    // the counter party did not compute its part with its share
+    PartialInvalid,
+    // the opened share is not the one party one committed to
+    CommitmentInvalid,
+    ProofInvalid,
+    // no counter gave a point
+    HashToCurve,
+}
+
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum SignRequestError {
+    // a prehashed digest is not 32 bytes
+    DigestLength,
+    EmptyTag,
+}
+
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum MessageSignError {
+    // malformed signature or message
+    Encoding,
+    SignatureInvalid,
+}
+
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum PsbtError {
+    InputOutOfRange,
+    MissingUtxo,
+    // the previous transaction does not match the outpoint spent
+    UtxoMismatch,
+    MissingScript,
+    SighashError,
+    // the key of a derivation path with our fingerprint is not our child key
+    PublicKeyMismatch,
+    // a sign job does not match the psbt
+    SighashMismatch,
+    SignatureInvalid,
+    // an ecdsa signature for a taproot input or a bip340 signature for another input
+    SignatureKindMismatch,
+}
+
+// reason the provider refuses to co-sign, returned to the owner
+#[derive(Copy, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub enum PolicyError {
+    // the psbt has no input to sign with this key at the index, or a transaction approved
+    // before decodes differently
+    TransactionInvalid,
+    // an output paying outside of the key without an address
+    DestinationUnknown,
+    DestinationNotAllowed,
+    DailyLimitExceeded,
+    VelocityExceeded,
+}
+
+// first field found to differ between the public data of the two parties
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum ConsistencyError {
+    EpochMismatch,
+    PublicKeyMismatch,
+    P1Mismatch,
+    P2Mismatch,
+    PaillierKeyMismatch,
+    CKeyMismatch,
+    ChainCodeMismatch,
+}
+
+// first invariant found broken by validate, checked on load for the ecdsa master keys
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum ValidationError {
+    PointNotOnCurve,
+    // the private share does not match its public point
+    PublicShareMismatch,
+    // the shares do not combine to the public key
+    PublicKeyMismatch,
+    PaillierKeySize,
+    CKeyOutOfRange,
+    // c_key does not decrypt to the share of party one
+    CKeyMismatch,
+    ChainCodeMalformed,
+}
+
+// needed by serde to report a master key failing validation on load
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
@@ -0,0 +1,372 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// message signing standards on top of the two party ecdsa: both parties compute the digest,
+// run the usual signing on it and the signature is formatted in the encoding of the standard.
+//
+// bitcoin: bip137 (legacy signed message) and bip322 simple for p2wpkh.
+// ethereum: eip191 personal_sign and eip712 typed data, without arrays.
+
+use base64;
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::util::sighash::SighashCache;
+use bitcoin::{EcdsaSig, EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, Script};
+use bitcoin::{Sequence, Transaction, TxIn, TxOut, Txid, VarInt, Witness};
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::BigInt;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one::SignatureRecid;
+use psbt::to_32_bytes;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use util::compressed;
+use MessageSignError::{self, Encoding, SignatureInvalid};
+
+const BIP137_PREFIX: &[u8] = b"\x18Bitcoin Signed Message:\n";
+const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
+const EIP191_PREFIX: &[u8] = b"\x19Ethereum Signed Message:\n";
+
+// address the bip137 signature is made for, selects the header byte
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Bip137AddressType {
+    P2pkh,
+    P2shP2wpkh,
+    P2wpkh,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Eip712Value {
+    Address([u8; 20]),
+    Bool(bool),
+    Uint { bits: usize, value: BigInt },
+    // bytes1 to bytes32
+    FixedBytes(Vec<u8>),
+    Bytes(Vec<u8>),
+    String(String),
+    Struct(Eip712Struct),
+}
+
+// fields in the order of the type definition. The domain is a struct named EIP712Domain.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Eip712Struct {
+    pub name: String,
+    pub fields: Vec<(String, Eip712Value)>,
+}
+
+fn double_sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(&Sha256::digest(data)).to_vec()
+}
+
+fn keccak256(data: &[u8]) -> Vec<u8> {
+    Keccak256::digest(data).to_vec()
+}
+
+fn compact(signature: &SignatureRecid) -> Vec<u8> {
+    let mut compact = to_32_bytes(&signature.r);
+    compact.extend_from_slice(&to_32_bytes(&signature.s));
+    compact
+}
+
+// a valid signature recovers to q
+fn verify_recoverable(
+    digest: &BigInt,
+    compact: &[u8],
+    recid: i32,
+    q: &GE,
+) -> Result<(), MessageSignError> {
+    let recovery_id = RecoveryId::from_i32(recid).or(Err(Encoding))?;
+    let signature = RecoverableSignature::from_compact(compact, recovery_id).or(Err(Encoding))?;
+    let message = Message::from_slice(&to_32_bytes(digest)).or(Err(Encoding))?;
+    let recovered = Secp256k1::verification_only()
+        .recover_ecdsa(&message, &signature)
+        .or(Err(SignatureInvalid))?;
+    if recovered.serialize()[..] == compressed(q)[..] {
+        Ok(())
+    } else {
+        Err(SignatureInvalid)
+    }
+}
+
+// the recovery id handed out with a signature of the signing code, 0 to 3, must recover q
+pub fn verify_recid(
+    digest: &BigInt,
+    signature: &SignatureRecid,
+    q: &GE,
+) -> Result<(), MessageSignError> {
+    verify_recoverable(digest, &compact(signature), i32::from(signature.recid), q)
+}
+
+pub fn bip137_digest(message: &[u8]) -> BigInt {
+    let mut data = BIP137_PREFIX.to_vec();
+    data.extend_from_slice(&serialize(&VarInt(message.len() as u64)));
+    data.extend_from_slice(message);
+    BigInt::from(&double_sha256(&data)[..])
+}
+
+// base64 of header || r || s, the header gives the recovery id and the address type
+pub fn bip137_signature(signature: &SignatureRecid, address_type: Bip137AddressType) -> String {
+    let header = match address_type {
+        Bip137AddressType::P2pkh => 31,
+        Bip137AddressType::P2shP2wpkh => 35,
+        Bip137AddressType::P2wpkh => 39,
+    };
+    let mut encoded = vec![header + signature.recid];
+    encoded.extend_from_slice(&compact(signature));
+    base64::encode(&encoded)
+}
+
+// headers of uncompressed keys are refused, the two party keys are always compressed
+pub fn bip137_verify(
+    message: &[u8],
+    signature: &str,
+    q: &GE,
+) -> Result<Bip137AddressType, MessageSignError> {
+    let decoded = base64::decode(signature).or(Err(Encoding))?;
+    if decoded.len() != 65 {
+        return Err(Encoding);
+    }
+    let address_type = match decoded[0] {
+        31..=34 => Bip137AddressType::P2pkh,
+        35..=38 => Bip137AddressType::P2shP2wpkh,
+        39..=42 => Bip137AddressType::P2wpkh,
+        _ => return Err(Encoding),
+    };
+    let recid = (decoded[0] - 27) % 4;
+    verify_recoverable(&bip137_digest(message), &decoded[1..], recid as i32, q)?;
+    Ok(address_type)
+}
+
+pub fn bip322_to_spend(message: &[u8], script_pubkey: &Script) -> Transaction {
+    let tag_hash = Sha256::digest(BIP322_TAG);
+    let mut hasher = Sha256::new();
+    hasher.input(&tag_hash);
+    hasher.input(&tag_hash);
+    hasher.input(message);
+    let message_hash = hasher.result();
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime(0),
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFF_FFFF),
+            script_sig: Builder::new()
+                .push_int(0)
+                .push_slice(&message_hash)
+                .into_script(),
+            sequence: Sequence(0),
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+pub fn bip322_to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime(0),
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: Sequence(0),
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+fn p2wpkh_public_key(q: &GE) -> Result<PublicKey, MessageSignError> {
+    PublicKey::from_slice(&compressed(q)).or(Err(Encoding))
+}
+
+// bip143 sighash of the to_sign transaction for the p2wpkh address of q
+pub fn bip322_simple_digest(message: &[u8], q: &GE) -> Result<BigInt, MessageSignError> {
+    let public_key = p2wpkh_public_key(q)?;
+    let wpubkey_hash = public_key.wpubkey_hash().ok_or(Encoding)?;
+    let script_pubkey = Script::new_v0_p2wpkh(&wpubkey_hash);
+    let to_sign = bip322_to_sign(&bip322_to_spend(message, &script_pubkey));
+    let script_code = script_pubkey.p2wpkh_script_code().ok_or(Encoding)?;
+    let sighash = SighashCache::new(&to_sign)
+        .segwit_signature_hash(0, &script_code, 0, EcdsaSighashType::All)
+        .or(Err(Encoding))?;
+    Ok(BigInt::from(&sighash.into_inner()[..]))
+}
+
+// base64 of the witness of the to_sign transaction
+pub fn bip322_simple_signature(
+    signature: &SignatureRecid,
+    q: &GE,
+) -> Result<String, MessageSignError> {
+    let sig = bitcoin::secp256k1::ecdsa::Signature::from_compact(&compact(signature))
+        .or(Err(Encoding))?;
+    let ecdsa_sig = EcdsaSig {
+        sig,
+        hash_ty: EcdsaSighashType::All,
+    };
+    let witness = Witness::from_vec(vec![ecdsa_sig.to_vec(), compressed(q)]);
+    Ok(base64::encode(&serialize(&witness)))
+}
+
+pub fn bip322_simple_verify(
+    message: &[u8],
+    signature: &str,
+    q: &GE,
+) -> Result<(), MessageSignError> {
+    let decoded = base64::decode(signature).or(Err(Encoding))?;
+    let witness: Witness = deserialize(&decoded).or(Err(Encoding))?;
+    let items = witness.to_vec();
+    if items.len() != 2 || items[1] != compressed(q) {
+        return Err(SignatureInvalid);
+    }
+    let ecdsa_sig = EcdsaSig::from_slice(&items[0]).or(Err(Encoding))?;
+    if ecdsa_sig.hash_ty != EcdsaSighashType::All {
+        return Err(Encoding);
+    }
+    let digest = bip322_simple_digest(message, q)?;
+    let message = Message::from_slice(&to_32_bytes(&digest)).or(Err(Encoding))?;
+    Secp256k1::verification_only()
+        .verify_ecdsa(&message, &ecdsa_sig.sig, &p2wpkh_public_key(q)?.inner)
+        .or(Err(SignatureInvalid))
+}
+
+pub fn eip191_digest(message: &[u8]) -> BigInt {
+    let mut data = EIP191_PREFIX.to_vec();
+    data.extend_from_slice(message.len().to_string().as_bytes());
+    data.extend_from_slice(message);
+    BigInt::from(&keccak256(&data)[..])
+}
+
+fn eip712_type(value: &Eip712Value) -> String {
+    match *value {
+        Eip712Value::Address(_) => "address".to_string(),
+        Eip712Value::Bool(_) => "bool".to_string(),
+        Eip712Value::Uint { bits, .. } => format!("uint{}", bits),
+        Eip712Value::FixedBytes(ref bytes) => format!("bytes{}", bytes.len()),
+        Eip712Value::Bytes(_) => "bytes".to_string(),
+        Eip712Value::String(_) => "string".to_string(),
+        Eip712Value::Struct(ref inner) => inner.name.clone(),
+    }
+}
+
+fn eip712_struct_type(eip712_struct: &Eip712Struct) -> String {
+    let fields: Vec<String> = eip712_struct
+        .fields
+        .iter()
+        .map(|&(ref name, ref value)| format!("{} {}", eip712_type(value), name))
+        .collect();
+    format!("{}({})", eip712_struct.name, fields.join(","))
+}
+
+// struct types referenced from eip712_struct, itself excluded
+fn eip712_referenced<'a>(eip712_struct: &'a Eip712Struct, found: &mut Vec<&'a Eip712Struct>) {
+    for &(_, ref value) in &eip712_struct.fields {
+        if let Eip712Value::Struct(ref inner) = *value {
+            if found.iter().all(|known| known.name != inner.name) {
+                found.push(inner);
+                eip712_referenced(inner, found);
+            }
+        }
+    }
+}
+
+// the primary type followed by the referenced ones sorted by name
+pub fn eip712_encode_type(eip712_struct: &Eip712Struct) -> String {
+    let mut referenced = Vec::new();
+    eip712_referenced(eip712_struct, &mut referenced);
+    referenced.retain(|inner| inner.name != eip712_struct.name);
+    referenced.sort_by(|a, b| a.name.cmp(&b.name));
+    referenced
+        .iter()
+        .fold(eip712_struct_type(eip712_struct), |encoded, inner| {
+            encoded + &eip712_struct_type(inner)
+        })
+}
+
+fn eip712_encode_value(value: &Eip712Value) -> Result<Vec<u8>, MessageSignError> {
+    let mut word = vec![0u8; 32];
+    match *value {
+        Eip712Value::Address(ref address) => word[12..].copy_from_slice(address),
+        Eip712Value::Bool(flag) => word[31] = flag as u8,
+        Eip712Value::Uint { bits, ref value } => {
+            if bits == 0 || bits > 256 || bits % 8 != 0 {
+                return Err(Encoding);
+            }
+            if value < &BigInt::from(0) || value >= &(BigInt::from(1) << bits) {
+                return Err(Encoding);
+            }
+            word = to_32_bytes(value);
+        }
+        Eip712Value::FixedBytes(ref bytes) => {
+            if bytes.is_empty() || bytes.len() > 32 {
+                return Err(Encoding);
+            }
+            word[..bytes.len()].copy_from_slice(bytes);
+        }
+        Eip712Value::Bytes(ref bytes) => word = keccak256(bytes),
+        Eip712Value::String(ref string) => word = keccak256(string.as_bytes()),
+        Eip712Value::Struct(ref inner) => word = eip712_hash_struct(inner)?,
+    }
+    Ok(word)
+}
+
+pub fn eip712_hash_struct(eip712_struct: &Eip712Struct) -> Result<Vec<u8>, MessageSignError> {
+    let mut data = keccak256(eip712_encode_type(eip712_struct).as_bytes());
+    for &(_, ref value) in &eip712_struct.fields {
+        data.extend_from_slice(&eip712_encode_value(value)?);
+    }
+    Ok(keccak256(&data))
+}
+
+pub fn eip712_digest(
+    domain: &Eip712Struct,
+    message: &Eip712Struct,
+) -> Result<BigInt, MessageSignError> {
+    let mut data = b"\x19\x01".to_vec();
+    data.extend_from_slice(&eip712_hash_struct(domain)?);
+    data.extend_from_slice(&eip712_hash_struct(message)?);
+    Ok(BigInt::from(&keccak256(&data)[..]))
+}
+
+// 0x prefixed hex of r || s || v, v = 27 + recovery id
+pub fn eth_signature(signature: &SignatureRecid) -> String {
+    let mut encoded = compact(signature);
+    encoded.push(27 + signature.recid);
+    format!("0x{}", encoded.to_hex())
+}
+
+// v as 27 / 28 or as the bare recovery id
+pub fn eth_verify(digest: &BigInt, signature: &str, q: &GE) -> Result<(), MessageSignError> {
+    let hex = signature.trim_start_matches("0x");
+    let decoded = Vec::<u8>::from_hex(hex).or(Err(Encoding))?;
+    if decoded.len() != 65 {
+        return Err(Encoding);
+    }
+    let recid = match decoded[64] {
+        0 | 1 => decoded[64],
+        27 | 28 => decoded[64] - 27,
+        _ => return Err(Encoding),
+    };
+    verify_recoverable(digest, &decoded[..64], recid as i32, q)
+}
@@ -0,0 +1,214 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// provider side signing policy: the provider evaluates what it is asked to co-sign against a
+// set of rules before it takes part in signing and returns the deny reason to the owner.
+
+use bitcoin::{Address, Network, TxOut};
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::BigInt;
+use psbt::SignJob;
+use PolicyError::{
+    self, DailyLimitExceeded, DestinationNotAllowed, DestinationUnknown, TransactionInvalid,
+    VelocityExceeded,
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+// one input of the transaction to co-sign: the digest and the path of the child key signing it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolicyInput {
+    pub input: usize,
+    pub message: BigInt,
+    pub path: Vec<BigInt>,
+}
+
+// the decoded transaction behind the digests, built by the provider from the psbt (see
+// MasterKey1::policy_request) rather than taken from the owner, so the messages are the digests
+// of the transaction paying outputs. One request covers all the inputs and is evaluated once.
+// outputs are the (destination, amount) pairs not paying back to the key. time (unix seconds)
+// is set by the provider on receipt.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolicyRequest {
+    pub txid: String,
+    pub inputs: Vec<PolicyInput>,
+    pub outputs: Vec<(String, u64)>,
+    pub time: u64,
+}
+
+// issued by MasterKey1::sign_first_message_with_policy for one input of an approved request and
+// bound to the ephemeral key handed out with it, sign_second_message_with_policy requires it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolicyApproval {
+    pub(crate) input: PolicyInput,
+    pub(crate) eph_public: GE,
+}
+
+impl PolicyRequest {
+    // message and path from the sign jobs of the inputs, see psbt::sign_jobs, and every output
+    // of the transaction that does not pay back to the key, see psbt::external_outputs
+    pub fn from_psbt(
+        txid: String,
+        jobs: &[SignJob],
+        external_outputs: &[TxOut],
+        network: Network,
+        time: u64,
+    ) -> Result<PolicyRequest, PolicyError> {
+        if jobs.is_empty() {
+            return Err(TransactionInvalid);
+        }
+        let inputs = jobs
+            .iter()
+            .map(|job| PolicyInput {
+                input: job.input,
+                message: job.message.clone(),
+                path: job.path.clone(),
+            })
+            .collect();
+        let outputs = external_outputs
+            .iter()
+            .map(|output| {
+                Address::from_script(&output.script_pubkey, network)
+                    .map(|destination| (destination.to_string(), output.value))
+                    .ok_or(DestinationUnknown)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(PolicyRequest {
+            txid,
+            inputs,
+            outputs,
+            time,
+        })
+    }
+
+    // total paid to the outputs
+    pub fn amount(&self) -> u64 {
+        self.outputs
+            .iter()
+            .fold(0, |sum, (_, amount)| sum.saturating_add(*amount))
+    }
+
+    pub fn input(&self, input: usize) -> Result<&PolicyInput, PolicyError> {
+        self.inputs
+            .iter()
+            .find(|policy_input| policy_input.input == input)
+            .ok_or(TransactionInvalid)
+    }
+
+    fn same_transaction(&self, other: &PolicyRequest) -> bool {
+        self.txid == other.txid && self.inputs == other.inputs && self.outputs == other.outputs
+    }
+}
+
+// history holds the requests approved so far, oldest first, one per transaction
+pub trait Rule {
+    fn check(&self, request: &PolicyRequest, history: &[PolicyRequest]) -> Result<(), PolicyError>;
+}
+
+// total amount over the 24 hours up to the request
+pub struct DailyLimit {
+    pub limit: u64,
+}
+
+pub struct Allowlist {
+    pub destinations: Vec<String>,
+}
+
+// number of approved requests within window seconds before the request
+pub struct Velocity {
+    pub max_requests: usize,
+    pub window: u64,
+}
+
+fn within(history: &[PolicyRequest], time: u64, window: u64) -> Vec<&PolicyRequest> {
+    history
+        .iter()
+        .filter(|approved| approved.time <= time && time - approved.time < window)
+        .collect()
+}
+
+impl Rule for DailyLimit {
+    fn check(&self, request: &PolicyRequest, history: &[PolicyRequest]) -> Result<(), PolicyError> {
+        let spent = within(history, request.time, DAY)
+            .iter()
+            .fold(request.amount(), |sum, approved| {
+                sum.saturating_add(approved.amount())
+            });
+        if spent > self.limit {
+            Err(DailyLimitExceeded)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Rule for Allowlist {
+    fn check(&self, request: &PolicyRequest, _: &[PolicyRequest]) -> Result<(), PolicyError> {
+        if request
+            .outputs
+            .iter()
+            .all(|(destination, _)| self.destinations.contains(destination))
+        {
+            Ok(())
+        } else {
+            Err(DestinationNotAllowed)
+        }
+    }
+}
+
+impl Rule for Velocity {
+    fn check(&self, request: &PolicyRequest, history: &[PolicyRequest]) -> Result<(), PolicyError> {
+        if within(history, request.time, self.window).len() >= self.max_requests {
+            Err(VelocityExceeded)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PolicyEngine {
+    rules: Vec<Box<dyn Rule>>,
+    history: Vec<PolicyRequest>,
+}
+
+impl PolicyEngine {
+    pub fn add_rule(&mut self, rule: Box<dyn Rule>) {
+        self.rules.push(rule);
+    }
+
+    // the first rule denying the request gives the reason, approved requests are recorded for
+    // the limits of the following ones. A transaction approved before passes again for its other
+    // inputs without being counted twice, as long as it decodes the same.
+    pub fn evaluate(&mut self, request: &PolicyRequest) -> Result<(), PolicyError> {
+        if let Some(approved) = self
+            .history
+            .iter()
+            .find(|approved| approved.txid == request.txid)
+        {
+            return if approved.same_transaction(request) {
+                Ok(())
+            } else {
+                Err(TransactionInvalid)
+            };
+        }
+        for rule in &self.rules {
+            rule.check(request, &self.history)?;
+        }
+        self.history.push(request.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// bip174 psbt signing with two party keys. Both parties find the inputs to sign and compute the
+// sighashes from the same psbt, run the two party signing on each with the child key of the
+// derivation path and party one (or party two, see sign_third_message) fills in the partial
+// signatures.
+//
+// The derivation paths of the psbt are read as paths of the hd derivation of this library,
+// which is not bip32: the keys of the psbt must have been derived with get_child. Hardened
+// indices are just indices with the top bit set.
+//
+// The ecdsa keys sign the legacy and segwit v0 inputs of sign_jobs. Taproot inputs are spent on
+// the key path by the two party schnorr keys, see taproot_sign_jobs and schnorr::two_party::taproot.
+
+use bitcoin::hashes::{hash160, Hash};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::secp256k1::{self, Message, Secp256k1, XOnlyPublicKey};
+use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::{
+    EcdsaSig, EcdsaSighashType, PublicKey, SchnorrSig, SchnorrSighashType, Script, TxOut,
+};
+use curv::arithmetic::traits::Converter;
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::BigInt;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one::SignatureRecid;
+use schnorr::two_party::taproot::{output_key, x_only, TaprootSignature};
+use util::compressed;
+use PsbtError::{
+    self, InputOutOfRange, MissingScript, MissingUtxo, PublicKeyMismatch, SighashError,
+    SighashMismatch, SignatureInvalid, SignatureKindMismatch, UtxoMismatch,
+};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum SighashKind {
+    Legacy,
+    // bip143
+    SegwitV0,
+    // bip341 key spend
+    Taproot,
+}
+
+// one input to sign with the child key at path, message is the sighash
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SignJob {
+    pub input: usize,
+    pub path: Vec<BigInt>,
+    pub pubkey: GE,
+    pub kind: SighashKind,
+    pub sighash_type: u32,
+    pub message: BigInt,
+}
+
+// big endian, left padded to 32 bytes
+pub fn to_32_bytes(n: &BigInt) -> Vec<u8> {
+    let bytes = BigInt::to_bytes(n);
+    let mut padded = vec![0u8; 32 - bytes.len().min(32)];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+// as in bip32: first 4 bytes of hash160 of the compressed master public key
+pub fn master_fingerprint(q: &GE) -> Fingerprint {
+    let hash = hash160::Hash::hash(&compressed(q)).into_inner();
+    Fingerprint::from(&hash[0..4])
+}
+
+// the full previous transaction is checked against the outpoint even when there is a witness
+// utxo, and the two have to agree
+fn spent_output(psbt: &PartiallySignedTransaction, index: usize) -> Result<TxOut, PsbtError> {
+    let input = &psbt.inputs[index];
+    let previous_output = psbt.unsigned_tx.input[index].previous_output;
+    let from_transaction = match input.non_witness_utxo {
+        Some(ref utxo) => {
+            if utxo.txid() != previous_output.txid {
+                return Err(UtxoMismatch);
+            }
+            let output = utxo.output.get(previous_output.vout as usize);
+            Some(output.cloned().ok_or(UtxoMismatch)?)
+        }
+        None => None,
+    };
+    match (&input.witness_utxo, from_transaction) {
+        (Some(witness_utxo), Some(ref output)) if witness_utxo != output => Err(UtxoMismatch),
+        (Some(witness_utxo), _) => Ok(witness_utxo.clone()),
+        (None, Some(output)) => Ok(output),
+        (None, None) => Err(MissingUtxo),
+    }
+}
+
+// kind, sighash type and sighash of an input. Nested segwit is recognized from the redeem script
+pub fn sighash(
+    psbt: &PartiallySignedTransaction,
+    index: usize,
+) -> Result<(SighashKind, u32, BigInt), PsbtError> {
+    if index >= psbt.inputs.len() || index >= psbt.unsigned_tx.input.len() {
+        return Err(InputOutOfRange);
+    }
+    let input = &psbt.inputs[index];
+    let spent = spent_output(psbt, index)?;
+    let script = if spent.script_pubkey.is_p2sh() {
+        input.redeem_script.clone().ok_or(MissingScript)?
+    } else {
+        spent.script_pubkey.clone()
+    };
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+
+    if script.is_v1_p2tr() {
+        let sighash_type = input.schnorr_hash_ty().or(Err(SighashError))?;
+        let prevouts = (0..psbt.inputs.len())
+            .map(|i| spent_output(psbt, i))
+            .collect::<Result<Vec<TxOut>, PsbtError>>()?;
+        let hash = cache
+            .taproot_key_spend_signature_hash(index, &Prevouts::All(&prevouts), sighash_type)
+            .or(Err(SighashError))?;
+        return Ok((
+            SighashKind::Taproot,
+            sighash_type as u32,
+            BigInt::from(&hash.into_inner()[..]),
+        ));
+    }
+
+    let sighash_type = input.ecdsa_hash_ty().or(Err(SighashError))?;
+    if script.is_v0_p2wpkh() || script.is_v0_p2wsh() {
+        let script_code = if script.is_v0_p2wpkh() {
+            script.p2wpkh_script_code().ok_or(MissingScript)?
+        } else {
+            input.witness_script.clone().ok_or(MissingScript)?
+        };
+        let hash = cache
+            .segwit_signature_hash(index, &script_code, spent.value, sighash_type)
+            .or(Err(SighashError))?;
+        Ok((
+            SighashKind::SegwitV0,
+            sighash_type.to_u32(),
+            BigInt::from(&hash.into_inner()[..]),
+        ))
+    } else {
+        // the amount of a legacy input is only known from the full previous transaction
+        if input.non_witness_utxo.is_none() {
+            return Err(MissingUtxo);
+        }
+        let hash = cache
+            .legacy_signature_hash(index, &script, sighash_type.to_u32())
+            .or(Err(SighashError))?;
+        Ok((
+            SighashKind::Legacy,
+            sighash_type.to_u32(),
+            BigInt::from(&hash.into_inner()[..]),
+        ))
+    }
+}
+
+fn hd_path(path: &DerivationPath) -> Vec<BigInt> {
+    path.into_iter()
+        .map(|child| BigInt::from(u32::from(*child)))
+        .collect()
+}
+
+// p2pkh, p2wpkh or p2sh-p2wpkh of the key
+fn pays_to(script: &Script, pubkey: &PublicKey) -> bool {
+    if script == &Script::new_p2pkh(&pubkey.pubkey_hash()) {
+        return true;
+    }
+    match pubkey.wpubkey_hash() {
+        Some(wpubkey_hash) => {
+            let p2wpkh = Script::new_v0_p2wpkh(&wpubkey_hash);
+            script == &p2wpkh || script == &Script::new_p2sh(&p2wpkh.script_hash())
+        }
+        None => false,
+    }
+}
+
+// legacy and segwit v0 inputs with a key whose origin is the master key q. derive_public
+// returns the public key of the child at a path, get_child of either party.
+pub fn sign_jobs<F>(
+    psbt: &PartiallySignedTransaction,
+    q: &GE,
+    derive_public: F,
+) -> Result<Vec<SignJob>, PsbtError>
+where
+    F: Fn(Vec<BigInt>) -> GE,
+{
+    let fingerprint = master_fingerprint(q);
+    let mut jobs = Vec::new();
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        for (pubkey, origin) in input.bip32_derivation.iter() {
+            if origin.0 != fingerprint {
+                continue;
+            }
+            let path = hd_path(&origin.1);
+            let child = derive_public(path.clone());
+            if pubkey.serialize().to_vec() != compressed(&child) {
+                return Err(PublicKeyMismatch);
+            }
+            let (kind, sighash_type, message) = sighash(psbt, index)?;
+            if kind == SighashKind::Taproot {
+                continue;
+            }
+            jobs.push(SignJob {
+                input: index,
+                path,
+                pubkey: child,
+                kind,
+                sighash_type,
+                message,
+            });
+        }
+    }
+    Ok(jobs)
+}
+
+// taproot inputs whose internal key has its origin in the master key q, to sign on the key path.
+// The output has to be the internal key tweaked without a script tree, inputs with a merkle root
+// are left out. The pubkey of the job is the internal key.
+pub fn taproot_sign_jobs<F>(
+    psbt: &PartiallySignedTransaction,
+    q: &GE,
+    derive_public: F,
+) -> Result<Vec<SignJob>, PsbtError>
+where
+    F: Fn(Vec<BigInt>) -> GE,
+{
+    let fingerprint = master_fingerprint(q);
+    let mut jobs = Vec::new();
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let internal_key = match input.tap_internal_key {
+            Some(internal_key) if input.tap_merkle_root.is_none() => internal_key,
+            _ => continue,
+        };
+        let origin = match input.tap_key_origins.get(&internal_key) {
+            Some((leaf_hashes, origin)) if leaf_hashes.is_empty() && origin.0 == fingerprint => {
+                origin
+            }
+            _ => continue,
+        };
+        let path = hd_path(&origin.1);
+        let child = derive_public(path.clone());
+        if internal_key.serialize().to_vec() != x_only(&child) {
+            return Err(PublicKeyMismatch);
+        }
+        let (kind, sighash_type, message) = sighash(psbt, index)?;
+        let spent = spent_output(psbt, index)?;
+        let key_path_only =
+            Script::new_v1_p2tr(&Secp256k1::verification_only(), internal_key, None);
+        if kind != SighashKind::Taproot || spent.script_pubkey != key_path_only {
+            return Err(PublicKeyMismatch);
+        }
+        jobs.push(SignJob {
+            input: index,
+            path,
+            pubkey: child,
+            kind,
+            sighash_type,
+            message,
+        });
+    }
+    Ok(jobs)
+}
+
+// outputs that do not pay back to a child of the master key q. An output is change only if one
+// of its derivation paths with our fingerprint gives its key and the output pays that key.
+pub fn external_outputs<F>(
+    psbt: &PartiallySignedTransaction,
+    q: &GE,
+    derive_public: F,
+) -> Vec<TxOut>
+where
+    F: Fn(Vec<BigInt>) -> GE,
+{
+    let fingerprint = master_fingerprint(q);
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .zip(psbt.outputs.iter())
+        .filter(|(txout, output)| {
+            !output.bip32_derivation.iter().any(|(pubkey, origin)| {
+                let pubkey = PublicKey::new(*pubkey);
+                origin.0 == fingerprint
+                    && pubkey.to_bytes() == compressed(&derive_public(hd_path(&origin.1)))
+                    && pays_to(&txout.script_pubkey, &pubkey)
+            })
+        })
+        .map(|(txout, _)| txout.clone())
+        .collect()
+}
+
+// the sighash is computed again, a job can come from the counter party. The signature is
+// checked before it goes into the psbt.
+pub fn add_ecdsa_signature(
+    psbt: &mut PartiallySignedTransaction,
+    job: &SignJob,
+    signature: &SignatureRecid,
+) -> Result<(), PsbtError> {
+    let (kind, sighash_type, message) = sighash(psbt, job.input)?;
+    if kind == SighashKind::Taproot {
+        return Err(SignatureKindMismatch);
+    }
+    if kind != job.kind || sighash_type != job.sighash_type || message != job.message {
+        return Err(SighashMismatch);
+    }
+
+    let mut compact = to_32_bytes(&signature.r);
+    compact.extend_from_slice(&to_32_bytes(&signature.s));
+    let mut sig = secp256k1::ecdsa::Signature::from_compact(&compact).or(Err(SignatureInvalid))?;
+    sig.normalize_s();
+    let pubkey = PublicKey::from_slice(&compressed(&job.pubkey)).or(Err(PublicKeyMismatch))?;
+    let message = Message::from_slice(&to_32_bytes(&message)).or(Err(SighashError))?;
+    Secp256k1::verification_only()
+        .verify_ecdsa(&message, &sig, &pubkey.inner)
+        .or(Err(SignatureInvalid))?;
+
+    let hash_ty = EcdsaSighashType::from_standard(sighash_type).or(Err(SighashError))?;
+    psbt.inputs[job.input]
+        .partial_sigs
+        .insert(pubkey, EcdsaSig { sig, hash_ty });
+    Ok(())
+}
+
+// the signature of the output key goes into tap_key_sig, it is checked with libsecp against the
+// key of the spent output
+pub fn add_taproot_signature(
+    psbt: &mut PartiallySignedTransaction,
+    job: &SignJob,
+    signature: &TaprootSignature,
+) -> Result<(), PsbtError> {
+    let (kind, sighash_type, message) = sighash(psbt, job.input)?;
+    if kind != SighashKind::Taproot {
+        return Err(SignatureKindMismatch);
+    }
+    if kind != job.kind || sighash_type != job.sighash_type || message != job.message {
+        return Err(SighashMismatch);
+    }
+
+    let script_pubkey = spent_output(psbt, job.input)?.script_pubkey;
+    let tweaked_key =
+        XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).or(Err(PublicKeyMismatch))?;
+    if tweaked_key.serialize().to_vec() != x_only(&output_key(&job.pubkey)) {
+        return Err(PublicKeyMismatch);
+    }
+    let sig = secp256k1::schnorr::Signature::from_slice(&signature.to_bytes())
+        .or(Err(SignatureInvalid))?;
+    let message = Message::from_slice(&to_32_bytes(&message)).or(Err(SighashError))?;
+    Secp256k1::verification_only()
+        .verify_schnorr(&sig, &message, &tweaked_key)
+        .or(Err(SignatureInvalid))?;
+
+    let hash_ty =
+        SchnorrSighashType::from_consensus_u8(sighash_type as u8).or(Err(SighashError))?;
+    psbt.inputs[job.input].tap_key_sig = Some(SchnorrSig { sig, hash_ty });
+    Ok(())
+}
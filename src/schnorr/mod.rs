@@ -14,4 +14,5 @@
     @license GPL-3.0+ <https://github.com/KZen-networks/Kms/blob/master/LICENSE>
 */
 
+pub mod multi_party;
 pub mod two_party;
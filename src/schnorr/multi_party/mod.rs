@@ -0,0 +1,34 @@
+/*
+    Kms
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/Kms/blob/master/LICENSE>
+*/
+
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::BigInt;
+use multi_party_schnorr::protocols::multisig::KeyPair;
+
+// n out of n generalization of the two party wrapper: the joint key is the sum of the local keys.
+// party 0 plays the role of party one in the two party case and absorbs the hd offset.
+
+pub struct MasterKey {
+    party_index: usize,
+    local_key_pair: KeyPair,
+    chain_code: BigInt,
+    // local public key of every party, by party index
+    pub pub_shares: Vec<GE>,
+    pub pubkey: GE,
+}
+
+pub mod party_i;
+mod test;
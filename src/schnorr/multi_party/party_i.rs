@@ -0,0 +1,387 @@
+#![allow(non_snake_case)]
+/*
+    KMS-secp256k1
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+use super::MasterKey;
+use curv::arithmetic::traits::{Converter, Samplable};
+use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
+use curv::cryptographic_primitives::commitments::traits::Commitment;
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::BigInt;
+use multi_party_schnorr::protocols::multisig::*;
+use rotation::two_party::Rotation;
+use schnorr::two_party::hd_key;
+
+use Errors::{self, KeyGenError, RotationError, SignError};
+
+// Broadcast inputs are slices with the message of every party including our own, by party index.
+
+const BLIND_BITS: usize = 256;
+
+pub struct KeyGen {
+    party_index: usize,
+    local_keys: Keys,
+    cc_share: FE,
+    cc_blind: BigInt,
+    pub first_message: KeyGenMessage1,
+}
+
+#[derive(Clone)]
+pub struct KeyGenMessage1 {
+    pub ix_pub: Vec<GE>,
+    pub cc_com: BigInt,
+}
+
+#[derive(Clone)]
+pub struct KeyGenMessage2 {
+    pub y_i: FE,
+    pub cc_share: FE,
+    pub cc_blind: BigInt,
+}
+
+pub struct HashE {
+    pub e: FE,
+}
+
+pub struct SignEph {
+    pub first_message: SignMessage1,
+    eph_key: EphKey,
+}
+
+#[derive(Clone)]
+pub struct SignMessage1 {
+    pub com: GE,
+}
+
+#[derive(Clone)]
+pub struct SignMessage2 {
+    pub y_i: FE,
+}
+
+pub struct SignHelper {
+    pub es: FE,
+    pub Xt: GE,
+}
+
+// n party coin flip: commit to a seed, reveal once all commitments are in, rotation is the sum
+pub struct KeyRotation {
+    seed: FE,
+    blind: BigInt,
+    pub first_message: RotationMessage1,
+}
+
+#[derive(Clone)]
+pub struct RotationMessage1 {
+    pub com: BigInt,
+}
+
+#[derive(Clone)]
+pub struct RotationMessage2 {
+    pub seed: FE,
+    pub blind: BigInt,
+}
+
+impl KeyGen {
+    pub fn first_message(party_index: usize) -> KeyGen {
+        let local_keys = Keys::create();
+        let ix_pub = Keys::broadcast(local_keys.clone());
+        let cc_share: FE = ECScalar::new_random();
+        let cc_blind = BigInt::sample(BLIND_BITS);
+        let cc_com = HashCommitment::create_commitment_with_user_defined_randomness(
+            &cc_share.to_big_int(),
+            &cc_blind,
+        );
+        KeyGen {
+            party_index,
+            local_keys,
+            cc_share,
+            cc_blind,
+            first_message: KeyGenMessage1 { ix_pub, cc_com },
+        }
+    }
+
+    // create local sig and reveal the chain code share
+    pub fn second_message(
+        &self,
+        received_message1: &[KeyGenMessage1],
+    ) -> Result<(HashE, KeyGenMessage2), Errors> {
+        let e = self.challenge(received_message1)?;
+        let y_i = partial_sign(&self.local_keys, e.clone());
+        Ok((
+            HashE { e },
+            KeyGenMessage2 {
+                y_i,
+                cc_share: self.cc_share.clone(),
+                cc_blind: self.cc_blind.clone(),
+            },
+        ))
+    }
+
+    // every first message carries I and X, and ours is at our party index
+    fn challenge(&self, received_message1: &[KeyGenMessage1]) -> Result<FE, Errors> {
+        if received_message1.len() <= self.party_index
+            || received_message1
+                .iter()
+                .any(|message| message.ix_pub.len() != 2)
+            || received_message1[self.party_index].ix_pub != self.first_message.ix_pub
+        {
+            return Err(KeyGenError);
+        }
+        let ix_vec = received_message1
+            .iter()
+            .map(|message| message.ix_pub.clone())
+            .collect::<Vec<Vec<GE>>>();
+        Ok(Keys::collect_and_compute_challenge(&ix_vec))
+    }
+
+    // verify the local sigs and chain code decommitments of all parties and output joint public key if valid
+    pub fn third_message(
+        &self,
+        received_message1: &[KeyGenMessage1],
+        received_message2: &[KeyGenMessage2],
+        e: &FE,
+    ) -> Result<GE, Errors> {
+        if &self.challenge(received_message1)? != e
+            || received_message1.len() != received_message2.len()
+        {
+            return Err(KeyGenError);
+        }
+        let valid =
+            received_message1
+                .iter()
+                .zip(received_message2.iter())
+                .all(|(message1, message2)| {
+                    let sig = Signature::set_signature(&message1.ix_pub[1], &message2.y_i);
+                    verify(&message1.ix_pub[0], &sig, e).is_ok()
+                        && HashCommitment::create_commitment_with_user_defined_randomness(
+                            &message2.cc_share.to_big_int(),
+                            &message2.cc_blind,
+                        ) == message1.cc_com
+                });
+        if !valid {
+            return Err(KeyGenError);
+        }
+        let pub_shares = received_message1
+            .iter()
+            .map(|message| message.ix_pub[0].clone())
+            .collect::<Vec<GE>>();
+        sum_points(&pub_shares).ok_or(KeyGenError)
+    }
+}
+
+impl MasterKey {
+    // runs the checks of third_message itself, the challenge is recomputed from the first messages
+    pub fn set_master_key(
+        local_key_gen: &KeyGen,
+        received_message1: &[KeyGenMessage1],
+        received_message2: &[KeyGenMessage2],
+    ) -> Result<MasterKey, Errors> {
+        let e = local_key_gen.challenge(received_message1)?;
+        let pubkey = local_key_gen.third_message(received_message1, received_message2, &e)?;
+        let g: GE = ECPoint::generator();
+        let cc_sum = received_message2
+            .iter()
+            .skip(1)
+            .fold(received_message2[0].cc_share.clone(), |acc, message| {
+                acc + &message.cc_share
+            });
+        let pub_shares = received_message1
+            .iter()
+            .map(|message| message.ix_pub[0].clone())
+            .collect::<Vec<GE>>();
+        Ok(MasterKey {
+            party_index: local_key_gen.party_index,
+            local_key_pair: local_key_gen.local_keys.I.clone(),
+            chain_code: (&g * &cc_sum).bytes_compressed_to_big_int(),
+            pubkey,
+            pub_shares,
+        })
+    }
+
+    pub fn party_index(&self) -> usize {
+        self.party_index
+    }
+
+    pub fn sign_first_message() -> SignEph {
+        let eph_key = EphKey::gen_commit();
+        SignEph {
+            first_message: SignMessage1 {
+                com: eph_key.eph_key_pair.public_key.clone(),
+            },
+            eph_key,
+        }
+    }
+
+    pub fn sign_second_message(
+        &self,
+        eph_sign: &SignEph,
+        received_message1: &[SignMessage1],
+        message: &BigInt,
+    ) -> (SignHelper, SignMessage2) {
+        let eph_pub_key_vec = received_message1
+            .iter()
+            .map(|message| message.com.clone())
+            .collect::<Vec<GE>>();
+        let (_It, Xt, es) = EphKey::compute_joint_comm_e(
+            vec![self.pubkey.clone()],
+            eph_pub_key_vec,
+            &BigInt::to_bytes(message),
+        );
+        let y_i = eph_sign
+            .eph_key
+            .partial_sign(&self.local_key_pair, es.clone());
+        (SignHelper { es, Xt }, SignMessage2 { y_i })
+    }
+
+    pub fn signature(
+        &self,
+        received_message2: &[SignMessage2],
+        sign_helper: &SignHelper,
+    ) -> Result<Signature, Errors> {
+        if received_message2.len() != self.pub_shares.len() {
+            return Err(SignError);
+        }
+        let y = EphKey::add_signature_parts(
+            received_message2
+                .iter()
+                .map(|message| message.y_i.clone())
+                .collect(),
+        );
+        let sig = Signature::set_signature(&sign_helper.Xt, &y);
+        if verify(&self.pubkey, &sig, &sign_helper.es).is_ok() {
+            Ok(sig)
+        } else {
+            Err(SignError)
+        }
+    }
+
+    // every party is moved by a public offset derived from the joint coin flip, offsets sum to zero
+    pub fn rotate(mut self, cf: &Rotation) -> MasterKey {
+        let g: GE = ECPoint::generator();
+        let offsets = rotation_offsets(cf, self.pub_shares.len());
+        self.local_key_pair
+            .update_key_pair(offsets[self.party_index].clone());
+        let pub_shares = self
+            .pub_shares
+            .iter()
+            .zip(offsets.iter())
+            .map(|(pub_share, offset)| pub_share + &(&g * offset))
+            .collect::<Vec<GE>>();
+        MasterKey {
+            party_index: self.party_index,
+            local_key_pair: self.local_key_pair,
+            chain_code: self.chain_code,
+            pub_shares,
+            pubkey: self.pubkey,
+        }
+    }
+
+    pub fn get_child(&self, location_in_hir: Vec<BigInt>) -> MasterKey {
+        let g: GE = ECPoint::generator();
+        let (public_key_new_child, f_l_new, cc_new) =
+            hd_key(location_in_hir, &self.pubkey, &self.chain_code);
+        let mut local_key_pair_updated = self.local_key_pair.clone();
+        let mut pub_shares = self.pub_shares.clone();
+        if self.party_index == 0 {
+            local_key_pair_updated.update_key_pair(f_l_new.clone());
+        }
+        pub_shares[0] = &pub_shares[0] + &(&g * &f_l_new);
+        MasterKey {
+            party_index: self.party_index,
+            local_key_pair: local_key_pair_updated,
+            chain_code: cc_new.bytes_compressed_to_big_int(),
+            pub_shares,
+            pubkey: public_key_new_child,
+        }
+    }
+}
+
+impl KeyRotation {
+    pub fn first_message() -> KeyRotation {
+        let seed: FE = ECScalar::new_random();
+        let blind = BigInt::sample(BLIND_BITS);
+        let com = HashCommitment::create_commitment_with_user_defined_randomness(
+            &seed.to_big_int(),
+            &blind,
+        );
+        KeyRotation {
+            seed,
+            blind,
+            first_message: RotationMessage1 { com },
+        }
+    }
+
+    // sent once the first message of every party was received
+    pub fn second_message(&self) -> RotationMessage2 {
+        RotationMessage2 {
+            seed: self.seed.clone(),
+            blind: self.blind.clone(),
+        }
+    }
+
+    pub fn finalize(
+        received_message1: &[RotationMessage1],
+        received_message2: &[RotationMessage2],
+    ) -> Result<Rotation, Errors> {
+        if received_message1.is_empty() || received_message1.len() != received_message2.len() {
+            return Err(RotationError);
+        }
+        let valid =
+            received_message1
+                .iter()
+                .zip(received_message2.iter())
+                .all(|(message1, message2)| {
+                    HashCommitment::create_commitment_with_user_defined_randomness(
+                        &message2.seed.to_big_int(),
+                        &message2.blind,
+                    ) == message1.com
+                });
+        if !valid {
+            return Err(RotationError);
+        }
+        let rotation = received_message2
+            .iter()
+            .skip(1)
+            .fold(received_message2[0].seed.clone(), |acc, message| {
+                acc + &message.seed
+            });
+        Ok(Rotation { rotation })
+    }
+}
+
+// offset of party i > 0 is H(cf, i), party 0 takes minus their sum
+fn rotation_offsets(cf: &Rotation, party_count: usize) -> Vec<FE> {
+    let cf_bn = cf.rotation.to_big_int();
+    let tail = (1..party_count)
+        .map(|party_index| {
+            let offset_bn = HSha256::create_hash(&[&cf_bn, &BigInt::from(party_index as i32)]);
+            ECScalar::from(&offset_bn)
+        })
+        .collect::<Vec<FE>>();
+    let head = tail
+        .iter()
+        .fold(FE::zero(), |acc, offset| acc.sub(&offset.get_element()));
+    let mut offsets = vec![head];
+    offsets.extend(tail);
+    offsets
+}
+
+fn sum_points(points: &[GE]) -> Option<GE> {
+    let (first, rest) = points.split_first()?;
+    Some(rest.iter().fold(first.clone(), |acc, point| acc + point))
+}
@@ -0,0 +1,178 @@
+#![allow(non_snake_case)]
+/*
+    KMS-secp256k1
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::super::party_i::{
+        KeyGen, KeyGenMessage1, KeyRotation, RotationMessage1, RotationMessage2, SignMessage1,
+    };
+    use super::super::MasterKey;
+    use curv::BigInt;
+    use Errors::KeyGenError;
+
+    #[test]
+    fn test_n_party_sign() {
+        let master_keys = test_key_gen(4);
+        for master_key in master_keys.iter() {
+            assert_eq!(master_key.pubkey, master_keys[0].pubkey);
+        }
+        test_sign(&master_keys);
+    }
+
+    #[test]
+    fn test_key_gen_malformed_messages() {
+        let key_gens = (0..3).map(KeyGen::first_message).collect::<Vec<KeyGen>>();
+        let messages1 = key_gens
+            .iter()
+            .map(|key_gen| key_gen.first_message.clone())
+            .collect::<Vec<KeyGenMessage1>>();
+        let (_, messages2): (Vec<_>, Vec<_>) = key_gens
+            .iter()
+            .map(|key_gen| {
+                key_gen
+                    .second_message(&messages1)
+                    .expect("bad first messages")
+            })
+            .unzip();
+
+        let mut short_messages1 = messages1.clone();
+        short_messages1[1].ix_pub.truncate(1);
+        assert_eq!(
+            key_gens[0].second_message(&short_messages1).err(),
+            Some(KeyGenError)
+        );
+        assert_eq!(
+            MasterKey::set_master_key(&key_gens[0], &short_messages1, &messages2).err(),
+            Some(KeyGenError)
+        );
+        assert_eq!(
+            MasterKey::set_master_key(&key_gens[2], &messages1[..2], &messages2[..2]).err(),
+            Some(KeyGenError)
+        );
+        assert_eq!(
+            MasterKey::set_master_key(&key_gens[0], &messages1, &[]).err(),
+            Some(KeyGenError)
+        );
+        assert_eq!(
+            MasterKey::set_master_key(&key_gens[0], &[], &[]).err(),
+            Some(KeyGenError)
+        );
+    }
+
+    #[test]
+    fn test_get_child() {
+        let master_keys = test_key_gen(3);
+        let child_keys = master_keys
+            .iter()
+            .map(|master_key| master_key.get_child(vec![BigInt::from(10), BigInt::from(5)]))
+            .collect::<Vec<MasterKey>>();
+        assert_eq!(child_keys[0].pubkey, child_keys[2].pubkey);
+        assert_ne!(child_keys[0].pubkey, master_keys[0].pubkey);
+        test_sign(&child_keys);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let master_keys = test_key_gen(3);
+        let pubkey = master_keys[0].pubkey.clone();
+        let pub_shares = master_keys[0].pub_shares.clone();
+
+        let key_rotations = (0..3)
+            .map(|_| KeyRotation::first_message())
+            .collect::<Vec<KeyRotation>>();
+        let messages1 = key_rotations
+            .iter()
+            .map(|key_rotation| key_rotation.first_message.clone())
+            .collect::<Vec<RotationMessage1>>();
+        let messages2 = key_rotations
+            .iter()
+            .map(|key_rotation| key_rotation.second_message())
+            .collect::<Vec<RotationMessage2>>();
+        let rotated_keys = master_keys
+            .into_iter()
+            .map(|master_key| {
+                let cf = KeyRotation::finalize(&messages1, &messages2).expect("bad coin flip");
+                master_key.rotate(&cf)
+            })
+            .collect::<Vec<MasterKey>>();
+
+        for (rotated_key, pub_share) in rotated_keys.iter().zip(pub_shares.iter()) {
+            assert_eq!(rotated_key.pubkey, pubkey);
+            assert_ne!(&rotated_key.local_key_pair.public_key, pub_share);
+            assert_eq!(
+                rotated_key.local_key_pair.public_key,
+                rotated_key.pub_shares[rotated_key.party_index()]
+            );
+        }
+        test_sign(&rotated_keys);
+    }
+
+    pub fn test_key_gen(party_count: usize) -> Vec<MasterKey> {
+        let key_gens = (0..party_count)
+            .map(KeyGen::first_message)
+            .collect::<Vec<KeyGen>>();
+        let messages1 = key_gens
+            .iter()
+            .map(|key_gen| key_gen.first_message.clone())
+            .collect::<Vec<KeyGenMessage1>>();
+        let (hash_e_vec, messages2): (Vec<_>, Vec<_>) = key_gens
+            .iter()
+            .map(|key_gen| {
+                key_gen
+                    .second_message(&messages1)
+                    .expect("bad first messages")
+            })
+            .unzip();
+        key_gens
+            .iter()
+            .zip(hash_e_vec.iter())
+            .map(|(key_gen, hash_e)| {
+                let pubkey = key_gen
+                    .third_message(&messages1, &messages2, &hash_e.e)
+                    .expect("bad key proof");
+                let master_key = MasterKey::set_master_key(key_gen, &messages1, &messages2)
+                    .expect("bad key gen");
+                assert_eq!(master_key.pubkey, pubkey);
+                master_key
+            })
+            .collect()
+    }
+
+    pub fn test_sign(master_keys: &[MasterKey]) {
+        let message = BigInt::from(1234);
+        let eph_signs = master_keys
+            .iter()
+            .map(|_| MasterKey::sign_first_message())
+            .collect::<Vec<_>>();
+        let messages1 = eph_signs
+            .iter()
+            .map(|eph_sign| eph_sign.first_message.clone())
+            .collect::<Vec<SignMessage1>>();
+        let (sign_helpers, messages2): (Vec<_>, Vec<_>) = master_keys
+            .iter()
+            .zip(eph_signs.iter())
+            .map(|(master_key, eph_sign)| {
+                master_key.sign_second_message(eph_sign, &messages1, &message)
+            })
+            .unzip();
+        for (master_key, sign_helper) in master_keys.iter().zip(sign_helpers.iter()) {
+            master_key
+                .signature(&messages2, sign_helper)
+                .expect("bad signature");
+        }
+    }
+}
@@ -0,0 +1,83 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// adaptor signatures for the two party schnorr key. The adaptor point T is added to the joint
+// nonce X, so the partial signatures add up to a pre signature y' with y' * G + T = X + e * I.
+// y = y' + t completes it to a signature and anyone holding both learns t = y - y'.
+
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::BigInt;
+use multi_party_schnorr::protocols::multisig::{EphKey, Signature};
+use AdaptorError::{self, PreSignatureInvalid, WitnessMismatch};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PreSignature {
+    // joint nonce including T
+    pub nonce: GE,
+    pub y: FE,
+}
+
+impl PreSignature {
+    // e as computed by the signers, see verify for the counter party of a swap
+    pub fn verify_with_challenge(
+        &self,
+        pubkey: &GE,
+        adaptor_point: &GE,
+        e: &FE,
+    ) -> Result<(), AdaptorError> {
+        let g: GE = ECPoint::generator();
+        if &(&g * &self.y) + adaptor_point == &self.nonce + &(pubkey * e) {
+            Ok(())
+        } else {
+            Err(PreSignatureInvalid)
+        }
+    }
+
+    // run by the counter party of a swap before it commits to anything locked to T
+    pub fn verify(
+        &self,
+        pubkey: &GE,
+        message: &BigInt,
+        adaptor_point: &GE,
+    ) -> Result<(), AdaptorError> {
+        let (_, _, e) = EphKey::compute_joint_comm_e(
+            vec![pubkey.clone()],
+            vec![self.nonce.clone()],
+            &BigInt::to_bytes(message),
+        );
+        self.verify_with_challenge(pubkey, adaptor_point, &e)
+    }
+
+    pub fn adapt(&self, t: &FE) -> Signature {
+        Signature::set_signature(&self.nonce, &(self.y.clone() + t))
+    }
+
+    // t from the published signature, checked against the adaptor point
+    pub fn extract(&self, signature: &Signature, adaptor_point: &GE) -> Result<FE, AdaptorError> {
+        let g: GE = ECPoint::generator();
+        if signature.X != self.nonce {
+            return Err(WitnessMismatch);
+        }
+        let t = signature.y.sub(&self.y.get_element());
+        if &g * &t == *adaptor_point {
+            Ok(t)
+        } else {
+            Err(WitnessMismatch)
+        }
+    }
+}
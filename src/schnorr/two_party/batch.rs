@@ -0,0 +1,261 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// batch verification of the two party schnorr signatures. Each signature satisfies
+// y * G = X + e * I, with random a_i the batch checks that
+// sum a_i * X_i + sum (a_i * e_i) * I_i - (sum a_i * y_i) * G is the point at infinity. A forged
+// signature passes only if it cancels out for coefficients it did not know, so 2^-128.
+
+use curv::arithmetic::traits::{Converter, Modulo, Samplable};
+use curv::arithmetic::BasicOps;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::BigInt;
+use multi_party_schnorr::protocols::multisig::{verify, EphKey, Signature};
+use psbt::to_32_bytes;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use util::compressed;
+use BatchVerifyError::{self, SignatureInvalid};
+
+const COEFFICIENT_BITS: usize = 128;
+// digits of the interleaved multiplication, each point has a table of 1..2^WINDOW_BITS - 1 times
+const WINDOW_BITS: usize = 4;
+
+fn challenge(pubkey: &GE, message: &BigInt, signature: &Signature) -> FE {
+    let (_, _, e) = EphKey::compute_joint_comm_e(
+        vec![pubkey.clone()],
+        vec![signature.X.clone()],
+        &BigInt::to_bytes(message),
+    );
+    e
+}
+
+// x = X / Z^2, y = Y / Z^3 over the field of secp256k1. The point at infinity has no GE, here
+// it is Z = 0, so the sums never have to leave the curve group.
+#[derive(Clone)]
+struct Jacobian {
+    x: BigInt,
+    y: BigInt,
+    z: BigInt,
+}
+
+impl Jacobian {
+    fn infinity() -> Jacobian {
+        Jacobian {
+            x: BigInt::from(1),
+            y: BigInt::from(1),
+            z: BigInt::from(0),
+        }
+    }
+
+    fn from_point(point: &GE) -> Jacobian {
+        Jacobian {
+            x: point.x_coor().unwrap(),
+            y: point.y_coor().unwrap(),
+            z: BigInt::from(1),
+        }
+    }
+
+    fn is_infinity(&self) -> bool {
+        self.z == BigInt::from(0)
+    }
+
+    // dbl-2009-l for a = 0
+    fn double(&self, p: &BigInt) -> Jacobian {
+        if self.is_infinity() {
+            return self.clone();
+        }
+        let a = BigInt::mod_mul(&self.x, &self.x, p);
+        let b = BigInt::mod_mul(&self.y, &self.y, p);
+        let c = BigInt::mod_mul(&b, &b, p);
+        let x_b = BigInt::mod_add(&self.x, &b, p);
+        let d = BigInt::mod_sub(
+            &BigInt::mod_sub(&BigInt::mod_mul(&x_b, &x_b, p), &a, p),
+            &c,
+            p,
+        );
+        let d = BigInt::mod_add(&d, &d, p);
+        let e = BigInt::mod_mul(&a, &BigInt::from(3), p);
+        let f = BigInt::mod_mul(&e, &e, p);
+        let x = BigInt::mod_sub(&f, &BigInt::mod_add(&d, &d, p), p);
+        let y = BigInt::mod_sub(
+            &BigInt::mod_mul(&e, &BigInt::mod_sub(&d, &x, p), p),
+            &BigInt::mod_mul(&c, &BigInt::from(8), p),
+            p,
+        );
+        let z = BigInt::mod_mul(&BigInt::mod_add(&self.y, &self.y, p), &self.z, p);
+        Jacobian { x, y, z }
+    }
+
+    // add-2007-bl, P + P is a doubling and P + (-P) the point at infinity
+    fn add(&self, other: &Jacobian, p: &BigInt) -> Jacobian {
+        if self.is_infinity() {
+            return other.clone();
+        }
+        if other.is_infinity() {
+            return self.clone();
+        }
+        let z1z1 = BigInt::mod_mul(&self.z, &self.z, p);
+        let z2z2 = BigInt::mod_mul(&other.z, &other.z, p);
+        let u1 = BigInt::mod_mul(&self.x, &z2z2, p);
+        let u2 = BigInt::mod_mul(&other.x, &z1z1, p);
+        let s1 = BigInt::mod_mul(&BigInt::mod_mul(&self.y, &other.z, p), &z2z2, p);
+        let s2 = BigInt::mod_mul(&BigInt::mod_mul(&other.y, &self.z, p), &z1z1, p);
+        if u1 == u2 {
+            return if s1 == s2 {
+                self.double(p)
+            } else {
+                Jacobian::infinity()
+            };
+        }
+        let h = BigInt::mod_sub(&u2, &u1, p);
+        let h2 = BigInt::mod_add(&h, &h, p);
+        let i = BigInt::mod_mul(&h2, &h2, p);
+        let j = BigInt::mod_mul(&h, &i, p);
+        let s = BigInt::mod_sub(&s2, &s1, p);
+        let r = BigInt::mod_add(&s, &s, p);
+        let v = BigInt::mod_mul(&u1, &i, p);
+        let x = BigInt::mod_sub(
+            &BigInt::mod_sub(&BigInt::mod_mul(&r, &r, p), &j, p),
+            &BigInt::mod_add(&v, &v, p),
+            p,
+        );
+        let s1j = BigInt::mod_mul(&s1, &j, p);
+        let y = BigInt::mod_sub(
+            &BigInt::mod_mul(&r, &BigInt::mod_sub(&v, &x, p), p),
+            &BigInt::mod_add(&s1j, &s1j, p),
+            p,
+        );
+        let z1_z2 = BigInt::mod_add(&self.z, &other.z, p);
+        let z = BigInt::mod_mul(
+            &BigInt::mod_sub(
+                &BigInt::mod_sub(&BigInt::mod_mul(&z1_z2, &z1_z2, p), &z1z1, p),
+                &z2z2,
+                p,
+            ),
+            &h,
+            p,
+        );
+        Jacobian { x, y, z }
+    }
+
+    fn to_point(&self, p: &BigInt) -> Option<GE> {
+        if self.is_infinity() {
+            return None;
+        }
+        let z_inv = BigInt::mod_inv(&self.z, p);
+        let z_inv2 = BigInt::mod_mul(&z_inv, &z_inv, p);
+        let x = BigInt::mod_mul(&self.x, &z_inv2, p);
+        let y = BigInt::mod_mul(&BigInt::mod_mul(&self.y, &z_inv2, p), &z_inv, p);
+        Some(GE::from_coor(&x, &y))
+    }
+}
+
+// WINDOW_BITS digits of the scalar, most significant first
+fn digits(scalar: &FE) -> Vec<usize> {
+    to_32_bytes(&scalar.to_big_int())
+        .iter()
+        .flat_map(|byte| vec![(byte >> 4) as usize, (byte & 0xf) as usize])
+        .collect()
+}
+
+// sum of scalar_i * point_i, None for the point at infinity. Strauss' interleaved windowed
+// multiplication: all the points share one chain of doublings and each window adds the table
+// entry of every point for its digit. Zero scalars are skipped, the sums are in jacobian
+// coordinates with one inversion at the end.
+pub fn multi_scalar_mul(scalars: &[FE], points: &[GE]) -> Option<GE> {
+    let p = BigInt::from(2).pow(256) - BigInt::from(2).pow(32) - BigInt::from(977);
+    let terms: Vec<(Vec<usize>, Vec<Jacobian>)> = scalars
+        .iter()
+        .zip(points.iter())
+        .filter(|(scalar, _)| scalar.to_big_int() != BigInt::from(0))
+        .map(|(scalar, point)| {
+            let point = Jacobian::from_point(point);
+            let mut table = vec![point.clone()];
+            for _ in 2..1 << WINDOW_BITS {
+                let next = table[table.len() - 1].add(&point, &p);
+                table.push(next);
+            }
+            (digits(scalar), table)
+        })
+        .collect();
+
+    let mut acc = Jacobian::infinity();
+    for window in 0..256 / WINDOW_BITS {
+        for _ in 0..WINDOW_BITS {
+            acc = acc.double(&p);
+        }
+        for (digits, table) in terms.iter() {
+            if digits[window] != 0 {
+                acc = acc.add(&table[digits[window] - 1], &p);
+            }
+        }
+    }
+    acc.to_point(&p)
+}
+
+// (public key, message, signature) as produced by MasterKey1::signature. On failure each
+// signature is checked on its own to report the ones that do not verify.
+pub fn batch_verify(batch: &[(GE, BigInt, Signature)]) -> Result<(), BatchVerifyError> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let challenges: Vec<FE> = batch
+        .iter()
+        .map(|(pubkey, message, signature)| challenge(pubkey, message, signature))
+        .collect();
+
+    let mut y_sum = BigInt::from(0);
+    let mut scalars: Vec<FE> = Vec::new();
+    let mut points: Vec<GE> = Vec::new();
+    // child keys repeat across a batch, their coefficients are added up
+    let mut pubkeys: BTreeMap<Vec<u8>, (GE, FE)> = BTreeMap::new();
+    for ((pubkey, _, signature), e) in batch.iter().zip(challenges.iter()) {
+        let a: FE = ECScalar::from(&(BigInt::sample(COEFFICIENT_BITS) + BigInt::from(1)));
+        y_sum = (y_sum + (a.clone() * &signature.y).to_big_int()) % FE::q();
+        scalars.push(a.clone());
+        points.push(signature.X.clone());
+        let ae = a * e;
+        match pubkeys.entry(compressed(pubkey)) {
+            Entry::Occupied(mut entry) => {
+                let sum = entry.get().1.clone() + &ae;
+                entry.get_mut().1 = sum;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((pubkey.clone(), ae));
+            }
+        }
+    }
+    for (_, (pubkey, ae)) in pubkeys {
+        scalars.push(ae);
+        points.push(pubkey);
+    }
+    scalars.push(ECScalar::from(&((FE::q() - y_sum) % FE::q())));
+    points.push(ECPoint::generator());
+
+    if multi_scalar_mul(&scalars, &points).is_none() {
+        return Ok(());
+    }
+    let failed: Vec<usize> = batch
+        .iter()
+        .zip(challenges.iter())
+        .enumerate()
+        .filter(|(_, ((pubkey, _, signature), e))| verify(pubkey, signature, e).is_err())
+        .map(|(i, _)| i)
+        .collect();
+    Err(SignatureInvalid(failed))
+}
@@ -14,6 +14,7 @@
     @license GPL-3.0+ <https://github.com/KZen-networks/Kms/blob/master/LICENSE>
 */
 
+use centipede::juggling::segmentation::Msegmentation;
 use chain_code::two_party::party1::ChainCode1;
 use chain_code::two_party::party2::ChainCode2;
 use curv::cryptographic_primitives::hashing::hmac_sha512;
@@ -22,7 +23,18 @@ use curv::elliptic::curves::traits::{ECPoint, ECScalar};
 use curv::BigInt;
 use curv::arithmetic::{One, BasicOps, Converter};
 use curv::elliptic::curves::secp256_k1::{FE, GE};
-use multi_party_schnorr::protocols::multisig::KeyPair;
+use ecdh::PartialEcdh;
+use health_check::{challenge_hash, ShareProof};
+use multi_party_schnorr::protocols::multisig::{
+    partial_sign, verify, EphKey, KeyPair, Keys, Signature,
+};
+use validation::{check_chain_code, check_point};
+use vrf::{joint_challenge, verify_with_point, VrfProof, VrfShare};
+use EcdhError;
+use Errors::{self, RotationError};
+use ValidationError;
+use VrfError;
+
 // since this special case requires two out of two signers we ignore the "accountable" property
 
 pub struct MasterKey1 {
@@ -37,8 +49,11 @@ pub struct MasterKey2 {
     pubkey: GE,
 }
 
+pub mod adaptor;
+pub mod batch;
 pub mod party1;
 pub mod party2;
+pub mod taproot;
 mod test;
 
 pub fn hd_key(
@@ -81,3 +96,132 @@ pub fn hd_key(
             });
     (public_key_new_child, f_l_new, cc_new)
 }
+
+// proof of knowledge of a local share: the key gen local sig, with a fresh X and the challenge
+// computed over the prover points only
+fn prove_local_key(local_key_pair: &KeyPair) -> (Vec<GE>, FE) {
+    let mut keys = Keys::create();
+    keys.I = local_key_pair.clone();
+    let ix_pub = Keys::broadcast(keys.clone());
+    let e = Keys::collect_and_compute_challenge(&vec![ix_pub.clone()]);
+    let y = partial_sign(&keys, e);
+    (ix_pub, y)
+}
+
+// share proof of the health check, the nonce and response come from the multisig code
+fn prove_share(local_key_pair: &KeyPair, challenge: &BigInt) -> ShareProof {
+    let eph_key = EphKey::gen_commit();
+    let com = eph_key.eph_key_pair.public_key.clone();
+    let e = challenge_hash(challenge, &local_key_pair.public_key, &com);
+    ShareProof {
+        com,
+        z: eph_key.partial_sign(local_key_pair, e),
+    }
+}
+
+const SEGMENT_SIZE: usize = 8;
+const NUM_OF_SEGMENTS: usize = 32;
+
+// share for the ecdh and the vrf. KeyPair does not expose it, only its segments through the
+// verifiable encryption of the share; the encryption is thrown away with its one time key.
+fn local_share(local_key_pair: &KeyPair) -> FE {
+    let g: GE = ECPoint::generator();
+    let y_pub = &g * &FE::new_random();
+    let (segments, _) =
+        local_key_pair.to_encrypted_segment(&SEGMENT_SIZE, NUM_OF_SEGMENTS, &y_pub, &g);
+    Msegmentation::assemble_fe(&segments.x_vec, &SEGMENT_SIZE)
+}
+
+// (x1 + x2) * R from the local partial point and the proven one of the counter party
+fn combine_ecdh(
+    pubkey: &GE,
+    local_key_pair: &KeyPair,
+    point: &GE,
+    local_partial_ecdh: &PartialEcdh,
+    received_partial_ecdh: &PartialEcdh,
+) -> Result<GE, EcdhError> {
+    received_partial_ecdh.verify(&counter_public_share(pubkey, local_key_pair), point)?;
+    Ok(&local_partial_ecdh.point + &received_partial_ecdh.point)
+}
+
+// s_i = k_i + c * x_i of the local share, once the counter party proved its partial gamma
+fn vrf_response(
+    pubkey: &GE,
+    local_key_pair: &KeyPair,
+    h: &GE,
+    k: &FE,
+    local_vrf_share: &VrfShare,
+    received_vrf_share: &VrfShare,
+) -> Result<FE, VrfError> {
+    received_vrf_share.verify(&counter_public_share(pubkey, local_key_pair), h)?;
+    let c = joint_challenge(pubkey, h, local_vrf_share, received_vrf_share);
+    Ok(k.clone() + &(c * &local_share(local_key_pair)))
+}
+
+// the proof is checked against the joint key before the output is used
+fn vrf_output(
+    pubkey: &GE,
+    local_key_pair: &KeyPair,
+    h: &GE,
+    local_vrf_share: &VrfShare,
+    received_vrf_share: &VrfShare,
+    local_s: &FE,
+    received_s: &FE,
+) -> Result<(VrfProof, Vec<u8>), VrfError> {
+    let c = joint_challenge(pubkey, h, local_vrf_share, received_vrf_share);
+    received_vrf_share.verify_response(
+        &counter_public_share(pubkey, local_key_pair),
+        h,
+        &c,
+        received_s,
+    )?;
+    let proof = VrfProof {
+        gamma: &local_vrf_share.partial_gamma.point + &received_vrf_share.partial_gamma.point,
+        c,
+        s: local_s.clone() + received_s,
+    };
+    let beta = verify_with_point(pubkey, h, &proof)?;
+    Ok((proof, beta))
+}
+
+// public share of the counter party, the joint key minus the local one
+fn counter_public_share(pubkey: &GE, local_key_pair: &KeyPair) -> GE {
+    pubkey.sub_point(&local_key_pair.public_key.get_element())
+}
+
+// the share of the counter party is not kept, so the joint key is only checked to be a point
+fn validate_master_key(
+    local_key_pair: &KeyPair,
+    chain_code: &BigInt,
+    pubkey: &GE,
+) -> Result<(), ValidationError> {
+    check_point(pubkey)?;
+    check_point(&local_key_pair.public_key)?;
+    check_chain_code(chain_code)?;
+    let g: GE = ECPoint::generator();
+    if &g * &local_share(local_key_pair) != local_key_pair.public_key {
+        return Err(ValidationError::PublicShareMismatch);
+    }
+    Ok(())
+}
+
+// the counter party proved its rotated share and the two rotated shares still add up to the joint key
+fn verify_rotated_key(
+    pubkey: &GE,
+    local_public_key: &GE,
+    received_ix_pub: &[GE],
+    received_y: &FE,
+) -> Result<(), Errors> {
+    if received_ix_pub.len() != 2 {
+        return Err(RotationError);
+    }
+    let e = Keys::collect_and_compute_challenge(&vec![received_ix_pub.to_vec()]);
+    let sig = Signature::set_signature(&received_ix_pub[1], received_y);
+    if verify(&received_ix_pub[0], &sig, &e).is_ok()
+        && &(local_public_key + &received_ix_pub[0]) == pubkey
+    {
+        Ok(())
+    } else {
+        Err(RotationError)
+    }
+}
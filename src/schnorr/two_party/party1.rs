@@ -14,8 +14,13 @@
 
     @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
 */
-use super::hd_key;
+use super::adaptor::PreSignature;
+use super::taproot::{KeySpendHelper, TaprootSignature};
+use super::{combine_ecdh, counter_public_share, hd_key, local_share};
+use super::{prove_local_key, prove_share, vrf_output, vrf_response};
+use super::{validate_master_key, verify_rotated_key};
 use super::{MasterKey1, MasterKey2};
+use bitcoin::psbt::PartiallySignedTransaction;
 use chain_code::two_party::party1::ChainCode1;
 use chain_code::two_party::party2::ChainCode2;
 use curv::elliptic::curves::traits::ECPoint;
@@ -23,15 +28,26 @@ use curv::elliptic::curves::traits::ECScalar;
 use curv::BigInt;
 use curv::elliptic::curves::secp256_k1::{FE, GE};
 use curv::arithmetic::Converter;
+use ecdh::PartialEcdh;
+use health_check::{new_challenge, ShareProof};
 use multi_party_schnorr::protocols::multisig::*;
+use psbt::{add_taproot_signature, taproot_sign_jobs, SignJob};
 use rotation::two_party::Rotation;
 use schnorr::two_party::party2::{
-    KeyGenParty2Message1, KeyGenParty2Message2, SignParty2Message1, SignParty2Message2,
+    EcdhParty2Message1, HealthCheckParty2Message1, KeyGenParty2Message1, KeyGenParty2Message2,
+    RotateParty2Message1, SignParty2Message1, SignParty2Message2, VrfParty2Message1,
+    VrfParty2Message2,
 };
 
+use vrf::{hash_to_curve, VrfProof, VrfShare};
 use ManagementSystem2PSchnorr;
 
+use AdaptorError;
+use EcdhError;
 use Errors::{self, KeyGenError, SignError};
+use PsbtError::{self, SighashMismatch, SignatureInvalid};
+use ValidationError;
+use VrfError;
 
 pub struct SignEph {
     pub first_message: SignParty1Message1,
@@ -68,6 +84,43 @@ pub struct HashE {
     pub e: FE,
 }
 
+pub struct HealthCheckParty1Message1 {
+    pub challenge: BigInt,
+}
+
+pub struct HealthCheckParty1Message2 {
+    pub share_proof: ShareProof,
+}
+
+pub struct EcdhParty1Message1 {
+    pub partial_ecdh: PartialEcdh,
+}
+
+// nonce of the joint vrf proof and the opening of its commitment, kept until the response
+pub struct VrfEph {
+    pub first_message: VrfParty1Message1,
+    pub vrf_share: VrfShare,
+    blind: BigInt,
+    h: GE,
+    k: FE,
+}
+
+pub struct VrfParty1Message1 {
+    pub com: BigInt,
+}
+
+// the opened share and the response
+pub struct VrfParty1Message2 {
+    pub vrf_share: VrfShare,
+    pub blind: BigInt,
+    pub s: FE,
+}
+
+pub struct RotateParty1Message1 {
+    pub ix_pub: Vec<GE>,
+    pub y1: FE,
+}
+
 impl MasterKey1 {
     pub fn set_master_key(
         chain_code: &ChainCode1,
@@ -117,6 +170,117 @@ impl MasterKey1 {
         }
     }
 
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_master_key(
+            &self.local_key_pair,
+            &self.chain_code.chain_code,
+            &self.pubkey,
+        )
+    }
+
+    // share health check: both parties prove their share against a challenge of the other,
+    // no signature is produced
+    pub fn health_check_first_message() -> HealthCheckParty1Message1 {
+        HealthCheckParty1Message1 {
+            challenge: new_challenge(),
+        }
+    }
+
+    pub fn health_check_second_message(
+        &self,
+        party_one_health_check_first_message: &HealthCheckParty1Message1,
+        party_two_health_check_first_message: &HealthCheckParty2Message1,
+    ) -> Result<HealthCheckParty1Message2, Errors> {
+        party_two_health_check_first_message.share_proof.verify(
+            &counter_public_share(&self.pubkey, &self.local_key_pair),
+            &party_one_health_check_first_message.challenge,
+        )?;
+        Ok(HealthCheckParty1Message2 {
+            share_proof: prove_share(
+                &self.local_key_pair,
+                &party_two_health_check_first_message.challenge,
+            ),
+        })
+    }
+
+    // two party ecdh with an external point R: each party sends its share times R, the
+    // shared point is (x1 + x2) * R
+    pub fn ecdh_first_message(&self, point: &GE) -> EcdhParty1Message1 {
+        EcdhParty1Message1 {
+            partial_ecdh: PartialEcdh::compute(&local_share(&self.local_key_pair), point),
+        }
+    }
+
+    pub fn ecdh_second_message(
+        &self,
+        point: &GE,
+        party_one_ecdh_first_message: &EcdhParty1Message1,
+        party_two_ecdh_first_message: &EcdhParty2Message1,
+    ) -> Result<GE, EcdhError> {
+        combine_ecdh(
+            &self.pubkey,
+            &self.local_key_pair,
+            point,
+            &party_one_ecdh_first_message.partial_ecdh,
+            &party_two_ecdh_first_message.partial_ecdh,
+        )
+    }
+
+    // two party ecvrf of alpha under the joint key: gamma = (x1 + x2) * H and a dleq proof that
+    // both parties answer with their share. Party one only sends a commitment to its share.
+    pub fn vrf_first_message(&self, alpha: &[u8]) -> Result<VrfEph, VrfError> {
+        let h = hash_to_curve(&self.pubkey, alpha)?;
+        let (vrf_share, k) = VrfShare::create(&local_share(&self.local_key_pair), &h);
+        let (com, blind) = vrf_share.commit();
+        Ok(VrfEph {
+            first_message: VrfParty1Message1 { com },
+            vrf_share,
+            blind,
+            h,
+            k,
+        })
+    }
+
+    // opens the commitment once the share of party two arrived
+    pub fn vrf_second_message(
+        &self,
+        eph: &VrfEph,
+        party_two_vrf_first_message: &VrfParty2Message1,
+    ) -> Result<VrfParty1Message2, VrfError> {
+        let s = vrf_response(
+            &self.pubkey,
+            &self.local_key_pair,
+            &eph.h,
+            &eph.k,
+            &eph.vrf_share,
+            &party_two_vrf_first_message.vrf_share,
+        )?;
+        Ok(VrfParty1Message2 {
+            vrf_share: eph.vrf_share.clone(),
+            blind: eph.blind.clone(),
+            s,
+        })
+    }
+
+    // returns the proof and the vrf output beta
+    pub fn vrf_third_message(
+        &self,
+        eph: &VrfEph,
+        party_two_vrf_first_message: &VrfParty2Message1,
+        party_one_vrf_second_message: &VrfParty1Message2,
+        party_two_vrf_second_message: &VrfParty2Message2,
+    ) -> Result<(VrfProof, Vec<u8>), VrfError> {
+        vrf_output(
+            &self.pubkey,
+            &self.local_key_pair,
+            &eph.h,
+            &eph.vrf_share,
+            &party_two_vrf_first_message.vrf_share,
+            &party_one_vrf_second_message.s,
+            &party_two_vrf_second_message.s,
+        )
+    }
+
     pub fn sign_first_message() -> SignEph {
         let party1_com = EphKey::gen_commit();
         SignEph {
@@ -165,6 +329,150 @@ impl MasterKey1 {
             Err(SignError)
         }
     }
+
+    // adaptor mode: the adaptor point T is added to the joint nonce, so the partial signatures
+    // only give a pre signature that the dlog t of T completes
+    pub fn adaptor_sign_second_message(
+        &self,
+        eph_sign: &SignEph,
+        received_message1: &SignParty2Message1,
+        message: &BigInt,
+        adaptor_point: &GE,
+    ) -> (SignHelper, SignParty1Message2) {
+        let eph_pub_key_vec = vec![
+            eph_sign.first_message.com.clone(),
+            received_message1.com.clone(),
+            adaptor_point.clone(),
+        ];
+        let (_It, Xt, es) = EphKey::compute_joint_comm_e(
+            vec![self.pubkey.clone()],
+            eph_pub_key_vec,
+            &BigInt::to_bytes(message),
+        );
+        let y1 = eph_sign
+            .eph_key
+            .partial_sign(&self.local_key_pair, es.clone());
+        (SignHelper { es, Xt }, SignParty1Message2 { y1 })
+    }
+
+    pub fn pre_signature(
+        &self,
+        party_one_sign_second_message: &SignParty1Message2,
+        received_message2: &SignParty2Message2,
+        sign_helper: &SignHelper,
+        adaptor_point: &GE,
+    ) -> Result<PreSignature, AdaptorError> {
+        let y = EphKey::add_signature_parts(vec![
+            party_one_sign_second_message.y1.clone(),
+            received_message2.y2.clone(),
+        ]);
+        let pre_signature = PreSignature {
+            nonce: sign_helper.Xt.clone(),
+            y,
+        };
+        pre_signature.verify_with_challenge(&self.pubkey, adaptor_point, &sign_helper.es)?;
+        Ok(pre_signature)
+    }
+
+    // bip340 signing at the bip341 output key of this key, see taproot. The nonces are the same
+    // as in sign_first_message, the public tweak is added by party one
+    pub fn taproot_sign_second_message(
+        &self,
+        eph_sign: &SignEph,
+        received_message1: &SignParty2Message1,
+        message: &BigInt,
+    ) -> (KeySpendHelper, SignParty1Message2) {
+        let helper = KeySpendHelper::new(
+            &self.pubkey,
+            &eph_sign.first_message.com,
+            &received_message1.com,
+            message,
+        );
+        let y1 = helper.partial_sign(&eph_sign.eph_key, &self.local_key_pair, true);
+        (helper, SignParty1Message2 { y1 })
+    }
+
+    pub fn taproot_signature(
+        &self,
+        party_one_sign_second_message: &SignParty1Message2,
+        received_message2: &SignParty2Message2,
+        helper: &KeySpendHelper,
+    ) -> Result<TaprootSignature, Errors> {
+        let signature = TaprootSignature {
+            nonce: helper.nonce.clone(),
+            s: party_one_sign_second_message.y1.clone() + &received_message2.y2,
+        };
+        signature.verify_with_challenge(&helper.output_key, &helper.e)?;
+        Ok(signature)
+    }
+
+    // taproot inputs of the psbt to sign with children of this master key, the same for both
+    // parties
+    pub fn psbt_sign_jobs(
+        &self,
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<SignJob>, PsbtError> {
+        taproot_sign_jobs(psbt, &self.pubkey, |path| self.get_child(path).pubkey)
+    }
+
+    // party one's side of signing one taproot input: the job must be one of its own, the child
+    // key at its path signs the sighash
+    pub fn psbt_sign_second_message(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        job: &SignJob,
+        eph_sign: &SignEph,
+        received_message1: &SignParty2Message1,
+    ) -> Result<(KeySpendHelper, SignParty1Message2), PsbtError> {
+        if !self.psbt_sign_jobs(psbt)?.contains(job) {
+            return Err(SighashMismatch);
+        }
+        Ok(self
+            .get_child(job.path.clone())
+            .taproot_sign_second_message(eph_sign, received_message1, &job.message))
+    }
+
+    // the signature from both partial signatures goes into tap_key_sig of the input
+    pub fn psbt_signature(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        job: &SignJob,
+        party_one_sign_second_message: &SignParty1Message2,
+        received_message2: &SignParty2Message2,
+        helper: &KeySpendHelper,
+    ) -> Result<TaprootSignature, PsbtError> {
+        let signature = self
+            .taproot_signature(party_one_sign_second_message, received_message2, helper)
+            .or(Err(SignatureInvalid))?;
+        add_taproot_signature(psbt, job, &signature)?;
+        Ok(signature)
+    }
+
+    // proves knowledge of the share rotated by -cf, the local key is not changed yet
+    pub fn rotate_first_message(&self, cf: &Rotation) -> RotateParty1Message1 {
+        let zero: FE = FE::zero();
+        let minus_cf = zero.sub(&cf.rotation.get_element());
+        let mut local_key_pair_rotated = self.local_key_pair.clone();
+        local_key_pair_rotated.update_key_pair(minus_cf);
+        let (ix_pub, y1) = prove_local_key(&local_key_pair_rotated);
+        RotateParty1Message1 { ix_pub, y1 }
+    }
+
+    // rotated master key, only if party two rotated by the same factor
+    pub fn rotate_second_message(
+        self,
+        cf: &Rotation,
+        received_message1: &RotateParty2Message1,
+    ) -> Result<MasterKey1, Errors> {
+        let rotated = self.rotate(cf);
+        verify_rotated_key(
+            &rotated.pubkey,
+            &rotated.local_key_pair.public_key,
+            &received_message1.ix_pub,
+            &received_message1.y2,
+        )?;
+        Ok(rotated)
+    }
 }
 
 impl ManagementSystem2PSchnorr for MasterKey1 {
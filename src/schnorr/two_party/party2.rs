@@ -14,8 +14,13 @@
 
     @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
 */
-use super::hd_key;
+use super::adaptor::PreSignature;
+use super::taproot::{KeySpendHelper, TaprootSignature};
+use super::{combine_ecdh, counter_public_share, hd_key, local_share};
+use super::{prove_local_key, prove_share, vrf_output, vrf_response};
+use super::{validate_master_key, verify_rotated_key};
 use super::{MasterKey1, MasterKey2};
+use bitcoin::psbt::PartiallySignedTransaction;
 use chain_code::two_party::party1::ChainCode1;
 use chain_code::two_party::party2::ChainCode2;
 use curv::elliptic::curves::traits::ECPoint;
@@ -23,14 +28,25 @@ use curv::elliptic::curves::traits::ECScalar;
 use curv::BigInt;
 use curv::elliptic::curves::secp256_k1::{FE, GE};
 use curv::arithmetic::Converter;
+use ecdh::PartialEcdh;
+use health_check::{new_challenge, ShareProof};
 use multi_party_schnorr::protocols::multisig::*;
+use psbt::{taproot_sign_jobs, SignJob};
 use rotation::two_party::Rotation;
 use schnorr::two_party::party1::{
-    KeyGenParty1Message1, KeyGenParty1Message2, SignParty1Message1, SignParty1Message2,
+    EcdhParty1Message1, HealthCheckParty1Message1, HealthCheckParty1Message2, KeyGenParty1Message1,
+    KeyGenParty1Message2, RotateParty1Message1, SignParty1Message1, SignParty1Message2,
+    VrfParty1Message1, VrfParty1Message2,
 };
+use vrf::{hash_to_curve, VrfProof, VrfShare};
 use ManagementSystem2PSchnorr;
 
+use AdaptorError;
+use EcdhError;
 use Errors::{self, KeyGenError, SignError};
+use PsbtError::{self, SighashMismatch};
+use ValidationError;
+use VrfError;
 
 pub struct SignEph {
     pub first_message: SignParty2Message1,
@@ -66,6 +82,35 @@ pub struct KeyGenParty2Message2 {
 pub struct HashE {
     pub e: FE,
 }
+
+pub struct HealthCheckParty2Message1 {
+    pub challenge: BigInt,
+    pub share_proof: ShareProof,
+}
+
+pub struct EcdhParty2Message1 {
+    pub partial_ecdh: PartialEcdh,
+}
+
+// nonce of the joint vrf proof, kept until the response
+pub struct VrfEph {
+    pub first_message: VrfParty2Message1,
+    h: GE,
+    k: FE,
+}
+
+pub struct VrfParty2Message1 {
+    pub vrf_share: VrfShare,
+}
+
+pub struct VrfParty2Message2 {
+    pub s: FE,
+}
+
+pub struct RotateParty2Message1 {
+    pub ix_pub: Vec<GE>,
+    pub y2: FE,
+}
 impl MasterKey2 {
     pub fn set_master_key(
         chain_code: &ChainCode2,
@@ -115,6 +160,109 @@ impl MasterKey2 {
         }
     }
 
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_master_key(
+            &self.local_key_pair,
+            &self.chain_code.chain_code,
+            &self.pubkey,
+        )
+    }
+
+    pub fn health_check_first_message(
+        &self,
+        party_one_health_check_first_message: &HealthCheckParty1Message1,
+    ) -> HealthCheckParty2Message1 {
+        HealthCheckParty2Message1 {
+            challenge: new_challenge(),
+            share_proof: prove_share(
+                &self.local_key_pair,
+                &party_one_health_check_first_message.challenge,
+            ),
+        }
+    }
+
+    pub fn health_check_second_message(
+        &self,
+        party_two_health_check_first_message: &HealthCheckParty2Message1,
+        party_one_health_check_second_message: &HealthCheckParty1Message2,
+    ) -> Result<(), Errors> {
+        party_one_health_check_second_message.share_proof.verify(
+            &counter_public_share(&self.pubkey, &self.local_key_pair),
+            &party_two_health_check_first_message.challenge,
+        )
+    }
+
+    pub fn ecdh_first_message(&self, point: &GE) -> EcdhParty2Message1 {
+        EcdhParty2Message1 {
+            partial_ecdh: PartialEcdh::compute(&local_share(&self.local_key_pair), point),
+        }
+    }
+
+    pub fn ecdh_second_message(
+        &self,
+        point: &GE,
+        party_two_ecdh_first_message: &EcdhParty2Message1,
+        party_one_ecdh_first_message: &EcdhParty1Message1,
+    ) -> Result<GE, EcdhError> {
+        combine_ecdh(
+            &self.pubkey,
+            &self.local_key_pair,
+            point,
+            &party_two_ecdh_first_message.partial_ecdh,
+            &party_one_ecdh_first_message.partial_ecdh,
+        )
+    }
+
+    // sent only after the commitment of party one arrived
+    pub fn vrf_first_message(&self, alpha: &[u8]) -> Result<VrfEph, VrfError> {
+        let h = hash_to_curve(&self.pubkey, alpha)?;
+        let (vrf_share, k) = VrfShare::create(&local_share(&self.local_key_pair), &h);
+        Ok(VrfEph {
+            first_message: VrfParty2Message1 { vrf_share },
+            h,
+            k,
+        })
+    }
+
+    pub fn vrf_second_message(
+        &self,
+        eph: &VrfEph,
+        party_one_vrf_first_message: &VrfParty1Message1,
+        party_one_vrf_second_message: &VrfParty1Message2,
+    ) -> Result<VrfParty2Message2, VrfError> {
+        party_one_vrf_second_message.vrf_share.verify_commitment(
+            &party_one_vrf_first_message.com,
+            &party_one_vrf_second_message.blind,
+        )?;
+        let s = vrf_response(
+            &self.pubkey,
+            &self.local_key_pair,
+            &eph.h,
+            &eph.k,
+            &eph.first_message.vrf_share,
+            &party_one_vrf_second_message.vrf_share,
+        )?;
+        Ok(VrfParty2Message2 { s })
+    }
+
+    // returns the proof and the vrf output beta
+    pub fn vrf_third_message(
+        &self,
+        eph: &VrfEph,
+        party_two_vrf_second_message: &VrfParty2Message2,
+        party_one_vrf_second_message: &VrfParty1Message2,
+    ) -> Result<(VrfProof, Vec<u8>), VrfError> {
+        vrf_output(
+            &self.pubkey,
+            &self.local_key_pair,
+            &eph.h,
+            &eph.first_message.vrf_share,
+            &party_one_vrf_second_message.vrf_share,
+            &party_two_vrf_second_message.s,
+            &party_one_vrf_second_message.s,
+        )
+    }
+
     pub fn sign_first_message() -> SignEph {
         let party2_com = EphKey::gen_commit();
         SignEph {
@@ -163,6 +311,130 @@ impl MasterKey2 {
             Err(SignError)
         }
     }
+
+    pub fn adaptor_sign_second_message(
+        &self,
+        eph_sign: &SignEph,
+        received_message1: &SignParty1Message1,
+        message: &BigInt,
+        adaptor_point: &GE,
+    ) -> (SignHelper, SignParty2Message2) {
+        let eph_pub_key_vec = vec![
+            eph_sign.first_message.com.clone(),
+            received_message1.com.clone(),
+            adaptor_point.clone(),
+        ];
+        let (_It, Xt, es) = EphKey::compute_joint_comm_e(
+            vec![self.pubkey.clone()],
+            eph_pub_key_vec,
+            &BigInt::to_bytes(message),
+        );
+        let y2 = eph_sign
+            .eph_key
+            .partial_sign(&self.local_key_pair, es.clone());
+        (SignHelper { es, Xt }, SignParty2Message2 { y2 })
+    }
+
+    pub fn pre_signature(
+        &self,
+        party_two_sign_second_message: &SignParty2Message2,
+        received_message2: &SignParty1Message2,
+        sign_helper: &SignHelper,
+        adaptor_point: &GE,
+    ) -> Result<PreSignature, AdaptorError> {
+        let y = EphKey::add_signature_parts(vec![
+            party_two_sign_second_message.y2.clone(),
+            received_message2.y1.clone(),
+        ]);
+        let pre_signature = PreSignature {
+            nonce: sign_helper.Xt.clone(),
+            y,
+        };
+        pre_signature.verify_with_challenge(&self.pubkey, adaptor_point, &sign_helper.es)?;
+        Ok(pre_signature)
+    }
+
+    // bip340 signing at the bip341 output key of this key, see taproot. The nonces are the same
+    // as in sign_first_message, party one adds the public tweak
+    pub fn taproot_sign_second_message(
+        &self,
+        eph_sign: &SignEph,
+        received_message1: &SignParty1Message1,
+        message: &BigInt,
+    ) -> (KeySpendHelper, SignParty2Message2) {
+        let helper = KeySpendHelper::new(
+            &self.pubkey,
+            &received_message1.com,
+            &eph_sign.first_message.com,
+            message,
+        );
+        let y2 = helper.partial_sign(&eph_sign.eph_key, &self.local_key_pair, false);
+        (helper, SignParty2Message2 { y2 })
+    }
+
+    pub fn taproot_signature(
+        &self,
+        party_two_sign_second_message: &SignParty2Message2,
+        received_message2: &SignParty1Message2,
+        helper: &KeySpendHelper,
+    ) -> Result<TaprootSignature, Errors> {
+        let signature = TaprootSignature {
+            nonce: helper.nonce.clone(),
+            s: party_two_sign_second_message.y2.clone() + &received_message2.y1,
+        };
+        signature.verify_with_challenge(&helper.output_key, &helper.e)?;
+        Ok(signature)
+    }
+
+    // taproot inputs of the psbt to sign with children of this master key, the same for both
+    // parties
+    pub fn psbt_sign_jobs(
+        &self,
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<SignJob>, PsbtError> {
+        taproot_sign_jobs(psbt, &self.pubkey, |path| self.get_child(path).pubkey)
+    }
+
+    // party two's side of signing one taproot input: the job must be one of its own, party one
+    // puts the signature into the psbt
+    pub fn psbt_sign_second_message(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        job: &SignJob,
+        eph_sign: &SignEph,
+        received_message1: &SignParty1Message1,
+    ) -> Result<(KeySpendHelper, SignParty2Message2), PsbtError> {
+        if !self.psbt_sign_jobs(psbt)?.contains(job) {
+            return Err(SighashMismatch);
+        }
+        Ok(self
+            .get_child(job.path.clone())
+            .taproot_sign_second_message(eph_sign, received_message1, &job.message))
+    }
+
+    // proves knowledge of the share rotated by cf, the local key is not changed yet
+    pub fn rotate_first_message(&self, cf: &Rotation) -> RotateParty2Message1 {
+        let mut local_key_pair_rotated = self.local_key_pair.clone();
+        local_key_pair_rotated.update_key_pair(cf.rotation.clone());
+        let (ix_pub, y2) = prove_local_key(&local_key_pair_rotated);
+        RotateParty2Message1 { ix_pub, y2 }
+    }
+
+    // rotated master key, only if party one rotated by the same factor
+    pub fn rotate_second_message(
+        self,
+        cf: &Rotation,
+        received_message1: &RotateParty1Message1,
+    ) -> Result<MasterKey2, Errors> {
+        let rotated = self.rotate(cf);
+        verify_rotated_key(
+            &rotated.pubkey,
+            &rotated.local_key_pair.public_key,
+            &received_message1.ix_pub,
+            &received_message1.y1,
+        )?;
+        Ok(rotated)
+    }
 }
 
 impl ManagementSystem2PSchnorr for MasterKey2 {
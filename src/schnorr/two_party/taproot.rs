@@ -0,0 +1,179 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// bip340 signatures at the bip341 output key of the two party key, for taproot key path spends
+// of outputs without a script tree. The internal key P is the joint key with even y, the output
+// key is Q = P + t * G with t = hash_TapTweak(x(P)), and the joint nonce R is also taken with
+// even y. The secret of Q is d = +-(+-(x1 + x2) + t), so each party signs with its share negated
+// as needed and party one, which adds the public t, takes the tweak.
+//
+// The shares are not exposed, partial_sign gives r_i + e' * x_i for the challenge e' it is
+// handed: with e' = +-e and the result negated when R had odd y this is k_i + e * d_i.
+
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::BigInt;
+use multi_party_schnorr::protocols::multisig::{EphKey, KeyPair};
+use psbt::to_32_bytes;
+use sha2::{Digest, Sha256};
+use util::compressed;
+use Errors::{self, SignError};
+
+const TAP_TWEAK_TAG: &[u8] = b"TapTweak";
+const CHALLENGE_TAG: &[u8] = b"BIP0340/challenge";
+
+// x(R) || s
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TaprootSignature {
+    // even y
+    pub nonce: GE,
+    pub s: FE,
+}
+
+// what both parties compute from the two nonces before their partial signatures
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct KeySpendHelper {
+    pub output_key: GE,
+    // even y
+    pub nonce: GE,
+    pub e: FE,
+    // the shares are negated, one of P and Q had odd y but not both
+    key_negated: bool,
+    nonce_negated: bool,
+    // +-t, the tweak with the sign of Q
+    tweak: FE,
+}
+
+fn tagged_hash(tag: &[u8], data: &[&[u8]]) -> BigInt {
+    let tag_hash = Sha256::digest(tag);
+    let mut hasher = Sha256::new();
+    hasher.input(&tag_hash);
+    hasher.input(&tag_hash);
+    for bytes in data {
+        hasher.input(bytes);
+    }
+    BigInt::from(&hasher.result()[..])
+}
+
+fn has_even_y(point: &GE) -> bool {
+    compressed(point)[0] == 2
+}
+
+fn negate(scalar: &FE) -> FE {
+    let zero: FE = FE::zero();
+    zero.sub(&scalar.get_element())
+}
+
+// 32 byte x coordinate, the bip340 encoding of a key with even y
+pub fn x_only(point: &GE) -> Vec<u8> {
+    compressed(point)[1..].to_vec()
+}
+
+fn tap_tweak(internal_key: &GE) -> FE {
+    ECScalar::from(&tagged_hash(TAP_TWEAK_TAG, &[&x_only(internal_key)]))
+}
+
+// P + t * G for the internal key P with even y
+pub fn output_key(internal_key: &GE) -> GE {
+    let g: GE = ECPoint::generator();
+    lift_x(internal_key) + &g * &tap_tweak(internal_key)
+}
+
+// the point with the same x and even y, from_bytes reads 32 bytes as x with even y
+fn lift_x(point: &GE) -> GE {
+    if has_even_y(point) {
+        point.clone()
+    } else {
+        GE::from_bytes(&x_only(point)).unwrap()
+    }
+}
+
+pub fn challenge(nonce: &GE, output_key: &GE, message: &BigInt) -> FE {
+    ECScalar::from(&tagged_hash(
+        CHALLENGE_TAG,
+        &[&x_only(nonce), &x_only(output_key), &to_32_bytes(message)],
+    ))
+}
+
+impl KeySpendHelper {
+    // pubkey is the joint key (the child key of the input), the nonces are the coms of the first
+    // messages of both parties
+    pub fn new(pubkey: &GE, party_one_nonce: &GE, party_two_nonce: &GE, message: &BigInt) -> Self {
+        let output_key = output_key(pubkey);
+        let nonce = party_one_nonce + party_two_nonce;
+        let nonce_negated = !has_even_y(&nonce);
+        let even_nonce = lift_x(&nonce);
+        let e = challenge(&even_nonce, &output_key, message);
+        let tweak = if has_even_y(&output_key) {
+            tap_tweak(pubkey)
+        } else {
+            negate(&tap_tweak(pubkey))
+        };
+        KeySpendHelper {
+            key_negated: has_even_y(pubkey) != has_even_y(&output_key),
+            nonce_negated,
+            output_key,
+            nonce: even_nonce,
+            e,
+            tweak,
+        }
+    }
+
+    // k_i + e * d_i, with_tweak for party one only
+    pub(super) fn partial_sign(
+        &self,
+        eph_key: &EphKey,
+        local_key_pair: &KeyPair,
+        with_tweak: bool,
+    ) -> FE {
+        let e = if self.key_negated != self.nonce_negated {
+            negate(&self.e)
+        } else {
+            self.e.clone()
+        };
+        let y = eph_key.partial_sign(local_key_pair, e);
+        let y = if self.nonce_negated { negate(&y) } else { y };
+        if with_tweak {
+            y + &(self.e.clone() * &self.tweak)
+        } else {
+            y
+        }
+    }
+}
+
+impl TaprootSignature {
+    // 64 bytes, the sighash type byte is added by the psbt unless it is the default
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = x_only(&self.nonce);
+        bytes.extend_from_slice(&to_32_bytes(&self.s.to_big_int()));
+        bytes
+    }
+
+    // s * G = R + e * Q with Q taken with even y
+    pub fn verify_with_challenge(&self, output_key: &GE, e: &FE) -> Result<(), Errors> {
+        let g: GE = ECPoint::generator();
+        if has_even_y(&self.nonce) && &g * &self.s == &self.nonce + &(lift_x(output_key) * e) {
+            Ok(())
+        } else {
+            Err(SignError)
+        }
+    }
+
+    pub fn verify(&self, output_key: &GE, message: &BigInt) -> Result<(), Errors> {
+        let e = challenge(&self.nonce, output_key, message);
+        self.verify_with_challenge(output_key, &e)
+    }
+}
@@ -17,18 +17,45 @@
 
 #[cfg(test)]
 mod tests {
+    use super::super::batch::{batch_verify, multi_scalar_mul};
+    use super::super::taproot::{output_key, x_only, TaprootSignature};
     use super::super::{MasterKey1, MasterKey2};
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::hashes::Hash;
+    use bitcoin::psbt::PartiallySignedTransaction;
+    use bitcoin::secp256k1::{schnorr, Message, Secp256k1, XOnlyPublicKey};
+    use bitcoin::util::bip32::{ChildNumber, DerivationPath};
+    use bitcoin::util::schnorr::TapTweak;
+    use bitcoin::util::taproot::TapBranchHash;
+    use bitcoin::{
+        OutPoint, PackedLockTime, SchnorrSighashType, Script, Sequence, Transaction, TxIn, TxOut,
+        Witness,
+    };
     use centipede::juggling::segmentation::Msegmentation;
     use chain_code::two_party::party1::ChainCode1;
     use chain_code::two_party::party2::ChainCode2;
+    use curv::arithmetic::traits::Converter;
     use curv::elliptic::curves::traits::ECPoint;
     use curv::elliptic::curves::traits::ECScalar;
     use curv::BigInt;
     use curv::elliptic::curves::secp256_k1::{FE, GE};
+    use ecdh::{ecies_decrypt, ecies_encrypt};
+    use multi_party_schnorr::protocols::multisig::{verify, Signature};
+    use psbt::{add_taproot_signature, master_fingerprint, to_32_bytes, SighashKind};
     use rotation::two_party::party1::Rotation1;
     use rotation::two_party::party2::Rotation2;
+    use rotation::two_party::Rotation;
     use schnorr::two_party::{party1, party2};
+    use std::time::Instant;
+    use vrf::{self, VrfProof};
+    use AdaptorError;
+    use BatchVerifyError;
+    use EcdhError;
+    use Errors::{HealthCheckError, SignError};
     use ManagementSystem2PSchnorr;
+    use PsbtError;
+    use ValidationError;
+    use VrfError;
 
     #[test]
     fn test_recovery_scenarios() {
@@ -600,4 +627,669 @@ mod tests {
             pubkey_view_party2.get_element()
         );
     }
+
+    #[test]
+    fn test_verified_rotation() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen_with_chain_code();
+        let pubkey = party_one_master_key.pubkey.clone();
+
+        //coin flip:
+        let (party1_first_message, m1, r1) = Rotation1::key_rotate_first_message();
+        let party2_first_message = Rotation2::key_rotate_first_message(&party1_first_message);
+        let (party1_second_message, random1) =
+            Rotation1::key_rotate_second_message(&party2_first_message, &m1, &r1);
+        let random2 = Rotation2::key_rotate_second_message(
+            &party1_second_message,
+            &party2_first_message,
+            &party1_first_message,
+        );
+
+        let rotate_party1_message1 = party_one_master_key.rotate_first_message(&random1);
+        let rotate_party2_message1 = party_two_master_key.rotate_first_message(&random2);
+
+        let party_one_master_key_rotated = party_one_master_key
+            .rotate_second_message(&random1, &rotate_party2_message1)
+            .expect("bad rotation proof");
+        let party_two_master_key_rotated = party_two_master_key
+            .rotate_second_message(&random2, &rotate_party1_message1)
+            .expect("bad rotation proof");
+        assert_eq!(party_one_master_key_rotated.pubkey, pubkey);
+        assert_eq!(party_two_master_key_rotated.pubkey, pubkey);
+
+        test_sign(&party_one_master_key_rotated, &party_two_master_key_rotated);
+
+        // a party that rotated by another factor is rejected
+        let bad_random = Rotation {
+            rotation: FE::new_random(),
+        };
+        let bad_rotate_party2_message1 =
+            party_two_master_key_rotated.rotate_first_message(&bad_random);
+        assert!(party_one_master_key_rotated
+            .rotate_second_message(&random1, &bad_rotate_party2_message1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let (party_one_master_key, mut party_two_master_key) = test_key_gen_with_chain_code();
+        party_one_master_key.validate().expect("invalid master key");
+        party_two_master_key.validate().expect("invalid master key");
+        party_one_master_key
+            .get_child(vec![BigInt::from(10), BigInt::from(5)])
+            .validate()
+            .expect("invalid master key");
+
+        let g: GE = ECPoint::generator();
+        party_two_master_key.local_key_pair.public_key = g;
+        assert_eq!(
+            party_two_master_key.validate(),
+            Err(ValidationError::PublicShareMismatch)
+        );
+        party_two_master_key.chain_code.chain_code = BigInt::from(1);
+        assert_eq!(
+            party_two_master_key.validate(),
+            Err(ValidationError::ChainCodeMalformed)
+        );
+    }
+
+    #[test]
+    fn test_health_check() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen_with_chain_code();
+        let party_one_first_message = MasterKey1::health_check_first_message();
+        let party_two_first_message =
+            party_two_master_key.health_check_first_message(&party_one_first_message);
+        let party_one_second_message = party_one_master_key
+            .health_check_second_message(&party_one_first_message, &party_two_first_message)
+            .expect("bad health check");
+        party_two_master_key
+            .health_check_second_message(&party_two_first_message, &party_one_second_message)
+            .expect("bad health check");
+
+        // proofs are bound to the challenge
+        let result = party_one_master_key.health_check_second_message(
+            &MasterKey1::health_check_first_message(),
+            &party_two_first_message,
+        );
+        assert!(result.is_err());
+        let party_two_replayed_message =
+            party_two_master_key.health_check_first_message(&party_one_first_message);
+        assert_eq!(
+            party_two_master_key.health_check_second_message(
+                &party_two_replayed_message,
+                &party_one_second_message
+            ),
+            Err(HealthCheckError)
+        );
+    }
+
+    #[test]
+    fn test_ecdh() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen_with_chain_code();
+        let ciphertext = ecies_encrypt(&party_one_master_key.pubkey, b"for both parties").unwrap();
+        let point = &ciphertext.ephemeral;
+        let party_one_first_message = party_one_master_key.ecdh_first_message(point);
+        let party_two_first_message = party_two_master_key.ecdh_first_message(point);
+        let shared = party_one_master_key
+            .ecdh_second_message(point, &party_one_first_message, &party_two_first_message)
+            .expect("bad ecdh");
+        assert_eq!(
+            party_two_master_key.ecdh_second_message(
+                point,
+                &party_two_first_message,
+                &party_one_first_message
+            ),
+            Ok(shared.clone())
+        );
+        assert_eq!(
+            ecies_decrypt(&ciphertext, &shared),
+            Ok(b"for both parties".to_vec())
+        );
+
+        // partial points are bound to the public shares
+        let mut party_one_first_message = party_one_master_key.ecdh_first_message(point);
+        party_one_first_message.partial_ecdh.point =
+            &party_one_first_message.partial_ecdh.point + point;
+        assert_eq!(
+            party_two_master_key.ecdh_second_message(
+                point,
+                &party_two_first_message,
+                &party_one_first_message
+            ),
+            Err(EcdhError::ProofInvalid)
+        );
+    }
+
+    #[test]
+    fn test_vrf() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen_with_chain_code();
+        let (proof, beta) = test_vrf_eval(&party_one_master_key, &party_two_master_key, b"round 1");
+        assert_eq!(
+            vrf::verify(&party_one_master_key.pubkey, b"round 1", &proof),
+            Ok(beta.clone())
+        );
+        assert_eq!(
+            vrf::verify(&party_one_master_key.pubkey, b"round 2", &proof),
+            Err(VrfError::ProofInvalid)
+        );
+
+        // the output only depends on the key and the input
+        let (_, beta_again) =
+            test_vrf_eval(&party_one_master_key, &party_two_master_key, b"round 1");
+        assert_eq!(beta, beta_again);
+        let (_, beta_other) =
+            test_vrf_eval(&party_one_master_key, &party_two_master_key, b"round 2");
+        assert_ne!(beta, beta_other);
+
+        // partial gammas are bound to the public shares
+        let eph_party1 = party_one_master_key.vrf_first_message(b"round 1").unwrap();
+        let mut eph_party2 = party_two_master_key.vrf_first_message(b"round 1").unwrap();
+        eph_party2.first_message.vrf_share.partial_gamma =
+            eph_party1.vrf_share.partial_gamma.clone();
+        let result =
+            party_one_master_key.vrf_second_message(&eph_party1, &eph_party2.first_message);
+        assert_eq!(result.err(), Some(VrfError::PartialInvalid));
+
+        // party one can't open another share than the one it committed to
+        let eph_party2 = party_two_master_key.vrf_first_message(b"round 1").unwrap();
+        let party1_second_message = party_one_master_key
+            .vrf_second_message(&eph_party1, &eph_party2.first_message)
+            .unwrap();
+        let eph_other = party_one_master_key.vrf_first_message(b"round 1").unwrap();
+        let result = party_two_master_key.vrf_second_message(
+            &eph_party2,
+            &eph_other.first_message,
+            &party1_second_message,
+        );
+        assert_eq!(result.err(), Some(VrfError::CommitmentInvalid));
+    }
+
+    #[test]
+    fn test_adaptor_sign() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen_with_chain_code();
+        let party_one_child = party_one_master_key.get_child(vec![BigInt::from(10)]);
+        let party_two_child = party_two_master_key.get_child(vec![BigInt::from(10)]);
+        let g: GE = ECPoint::generator();
+        let t: FE = ECScalar::new_random();
+        let adaptor_point = &g * &t;
+        let message = BigInt::from(1234);
+
+        let eph_keygen_party1 = MasterKey1::sign_first_message();
+        let eph_keygen_party2 = MasterKey2::sign_first_message();
+        let (sign_helper_party1, sign_party1_message2) = party_one_child
+            .adaptor_sign_second_message(
+                &eph_keygen_party1,
+                &eph_keygen_party2.first_message,
+                &message,
+                &adaptor_point,
+            );
+        let (sign_helper_party2, sign_party2_message2) = party_two_child
+            .adaptor_sign_second_message(
+                &eph_keygen_party2,
+                &eph_keygen_party1.first_message,
+                &message,
+                &adaptor_point,
+            );
+        let pre_signature = party_one_child
+            .pre_signature(
+                &sign_party1_message2,
+                &sign_party2_message2,
+                &sign_helper_party1,
+                &adaptor_point,
+            )
+            .expect("bad pre signature");
+        assert_eq!(
+            party_two_child.pre_signature(
+                &sign_party2_message2,
+                &sign_party1_message2,
+                &sign_helper_party2,
+                &adaptor_point,
+            ),
+            Ok(pre_signature.clone())
+        );
+
+        // the counter party of the swap checks it against public data only
+        let pubkey = party_one_child.pubkey.clone();
+        assert_eq!(
+            pre_signature.verify(&pubkey, &message, &adaptor_point),
+            Ok(())
+        );
+        assert_eq!(
+            pre_signature.verify(&pubkey, &message, &g),
+            Err(AdaptorError::PreSignatureInvalid)
+        );
+        let unadapted = Signature::set_signature(&pre_signature.nonce, &pre_signature.y);
+        assert!(verify(&pubkey, &unadapted, &sign_helper_party1.es).is_err());
+
+        let signature = pre_signature.adapt(&t);
+        assert!(verify(&pubkey, &signature, &sign_helper_party1.es).is_ok());
+        assert_eq!(pre_signature.extract(&signature, &adaptor_point), Ok(t));
+        let other_signature = pre_signature.adapt(&ECScalar::new_random());
+        assert_eq!(
+            pre_signature.extract(&other_signature, &adaptor_point),
+            Err(AdaptorError::WitnessMismatch)
+        );
+    }
+
+    #[test]
+    fn test_batch_verify() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen_with_chain_code();
+        let mut batch: Vec<(GE, BigInt, Signature)> = Vec::new();
+        for i in 0..6 {
+            // two signatures per child key
+            let location = vec![BigInt::from(i / 2)];
+            let party_one_child = party_one_master_key.get_child(location.clone());
+            let party_two_child = party_two_master_key.get_child(location);
+            let message = BigInt::from(1000 + i);
+            let signature = test_sign_message(&party_one_child, &party_two_child, &message);
+            batch.push((party_one_child.pubkey.clone(), message, signature));
+        }
+        assert_eq!(batch_verify(&batch), Ok(()));
+        assert_eq!(batch_verify(&[]), Ok(()));
+
+        let mut bad_batch = batch.clone();
+        bad_batch[1].1 = BigInt::from(4321);
+        bad_batch[4].0 = batch[0].0.clone();
+        assert_eq!(
+            batch_verify(&bad_batch),
+            Err(BatchVerifyError::SignatureInvalid(vec![1, 4]))
+        );
+
+        // sums at the point at infinity do not panic
+        let g: GE = ECPoint::generator();
+        let one: FE = ECScalar::from(&BigInt::from(1));
+        let minus_one: FE = ECScalar::from(&(FE::q() - BigInt::from(1)));
+        assert_eq!(
+            multi_scalar_mul(&[one.clone(), minus_one], &[g.clone(), g.clone()]),
+            None
+        );
+        assert_eq!(
+            multi_scalar_mul(&[one.clone(), one], &[g.clone(), g.clone()]),
+            Some(&g + &g)
+        );
+    }
+
+    #[test]
+    fn test_multi_scalar_mul() {
+        let g: GE = ECPoint::generator();
+        let scalars: Vec<FE> = (0..32).map(|_| ECScalar::new_random()).collect();
+        let points: Vec<GE> = (0..32).map(|_| &g * &FE::new_random()).collect();
+        let products = scalars
+            .iter()
+            .zip(points.iter())
+            .map(|(scalar, point)| point * scalar)
+            .collect::<Vec<GE>>();
+        let expected = products[1..]
+            .iter()
+            .fold(products[0].clone(), |acc, product| &acc + product);
+
+        // the points share one chain of doublings, so the sum is faster than the products
+        // computed one by one the same way
+        let start = Instant::now();
+        let sum = multi_scalar_mul(&scalars, &points);
+        let interleaved = start.elapsed();
+        let start = Instant::now();
+        let one_by_one = scalars
+            .iter()
+            .zip(points.iter())
+            .map(|(scalar, point)| multi_scalar_mul(&[scalar.clone()], &[point.clone()]).unwrap())
+            .collect::<Vec<GE>>();
+        let separate = start.elapsed();
+        assert_eq!(sum, Some(expected));
+        assert_eq!(one_by_one, products);
+        assert!(interleaved * 2 < separate);
+
+        // terms cancelling out on the way
+        let minus: FE = ECScalar::from(&(FE::q() - scalars[0].to_big_int()));
+        assert_eq!(
+            multi_scalar_mul(
+                &[scalars[0].clone(), minus, scalars[1].clone()],
+                &[points[0].clone(), points[0].clone(), points[1].clone()]
+            ),
+            Some(products[1].clone())
+        );
+    }
+
+    #[test]
+    fn test_taproot_sign() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen_with_chain_code();
+        let secp = Secp256k1::verification_only();
+        // children and nonces with odd and even y
+        for index in 0..8 {
+            let party_one_child = party_one_master_key.get_child(vec![BigInt::from(index)]);
+            let party_two_child = party_two_master_key.get_child(vec![BigInt::from(index)]);
+            let message = BigInt::from(1000 + index);
+
+            let eph_keygen_party1 = MasterKey1::sign_first_message();
+            let eph_keygen_party2 = MasterKey2::sign_first_message();
+            let (helper_party1, sign_party1_message2) = party_one_child
+                .taproot_sign_second_message(
+                    &eph_keygen_party1,
+                    &eph_keygen_party2.first_message,
+                    &message,
+                );
+            let (helper_party2, sign_party2_message2) = party_two_child
+                .taproot_sign_second_message(
+                    &eph_keygen_party2,
+                    &eph_keygen_party1.first_message,
+                    &message,
+                );
+            assert_eq!(helper_party1, helper_party2);
+            let signature = party_one_child
+                .taproot_signature(&sign_party1_message2, &sign_party2_message2, &helper_party1)
+                .expect("bad taproot signature");
+            assert_eq!(
+                party_two_child.taproot_signature(
+                    &sign_party2_message2,
+                    &sign_party1_message2,
+                    &helper_party2
+                ),
+                Ok(signature.clone())
+            );
+
+            // the output key and the signature as libsecp sees them
+            let internal_key =
+                XOnlyPublicKey::from_slice(&x_only(&party_one_child.pubkey)).unwrap();
+            let (tweaked_key, _) = internal_key.tap_tweak(&secp, None);
+            assert_eq!(
+                tweaked_key.to_inner().serialize().to_vec(),
+                x_only(&output_key(&party_one_child.pubkey))
+            );
+            let sig = schnorr::Signature::from_slice(&signature.to_bytes()).unwrap();
+            let digest = Message::from_slice(&to_32_bytes(&message)).unwrap();
+            assert!(secp
+                .verify_schnorr(&sig, &digest, &tweaked_key.to_inner())
+                .is_ok());
+            assert_eq!(
+                signature.verify(&output_key(&party_one_child.pubkey), &BigInt::from(1)),
+                Err(SignError)
+            );
+        }
+
+        // bip340 vectors 0 and 1
+        let vectors = vec![
+            (
+                "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA8215\
+                 25F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0",
+            ),
+            (
+                "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+                "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+                "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE3341\
+                 8906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A",
+            ),
+        ];
+        for (pubkey, message, signature) in vectors {
+            let even_point = |x: &str| GE::from_bytes(&Vec::<u8>::from_hex(x).unwrap()).unwrap();
+            let signature = TaprootSignature {
+                nonce: even_point(&signature[..64]),
+                s: ECScalar::from(&BigInt::from_hex(&signature[64..])),
+            };
+            let pubkey = even_point(pubkey);
+            let message = BigInt::from_hex(message);
+            assert_eq!(signature.verify(&pubkey, &message), Ok(()));
+            let one: FE = ECScalar::from(&BigInt::from(1));
+            let mut forged = signature.clone();
+            forged.s = forged.s + &one;
+            assert_eq!(forged.verify(&pubkey, &message), Err(SignError));
+        }
+    }
+
+    #[test]
+    fn test_psbt_taproot_sign() {
+        let (party_one_master_key, party_two_master_key) = test_key_gen_with_chain_code();
+        let secp = Secp256k1::verification_only();
+        let internal_key = |index: u32| {
+            let child = party_two_master_key.get_child(vec![BigInt::from(index)]);
+            XOnlyPublicKey::from_slice(&x_only(&child.pubkey)).unwrap()
+        };
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![],
+            output: (0..3)
+                .map(|index| TxOut {
+                    value: 50_000 + u64::from(index),
+                    script_pubkey: Script::new_v1_p2tr(&secp, internal_key(index), None),
+                })
+                .collect(),
+        };
+        let spend = |vout| TxIn {
+            previous_output: OutPoint::new(funding.txid(), vout),
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::default(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![spend(0), spend(1), spend(2)],
+            output: vec![funding.output[0].clone()],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        let fingerprint = master_fingerprint(&party_two_master_key.pubkey);
+        for index in 0..3 {
+            let path = DerivationPath::from(vec![ChildNumber::from(index as u32)]);
+            let input = &mut psbt.inputs[index];
+            input.witness_utxo = Some(funding.output[index].clone());
+            input.tap_internal_key = Some(internal_key(index as u32));
+            input
+                .tap_key_origins
+                .insert(internal_key(index as u32), (vec![], (fingerprint, path)));
+        }
+        // an input with a script tree is not signed on the key path
+        psbt.inputs[2].tap_merkle_root = Some(TapBranchHash::from_inner([1u8; 32]));
+
+        // both parties find the same inputs and sighashes
+        let jobs = party_one_master_key.psbt_sign_jobs(&psbt).unwrap();
+        assert_eq!(party_two_master_key.psbt_sign_jobs(&psbt), Ok(jobs.clone()));
+        assert_eq!(
+            jobs.iter().map(|job| job.input).collect::<Vec<usize>>(),
+            vec![0, 1]
+        );
+        assert!(jobs.iter().all(|job| job.kind == SighashKind::Taproot));
+
+        let mut signatures = Vec::new();
+        for job in &jobs {
+            let eph_keygen_party1 = MasterKey1::sign_first_message();
+            let eph_keygen_party2 = MasterKey2::sign_first_message();
+            let (helper, sign_party1_message2) = party_one_master_key
+                .psbt_sign_second_message(
+                    &psbt,
+                    job,
+                    &eph_keygen_party1,
+                    &eph_keygen_party2.first_message,
+                )
+                .expect("bad psbt job");
+            let (_, sign_party2_message2) = party_two_master_key
+                .psbt_sign_second_message(
+                    &psbt,
+                    job,
+                    &eph_keygen_party2,
+                    &eph_keygen_party1.first_message,
+                )
+                .expect("bad psbt job");
+            let signature = party_one_master_key
+                .psbt_signature(
+                    &mut psbt,
+                    job,
+                    &sign_party1_message2,
+                    &sign_party2_message2,
+                    &helper,
+                )
+                .expect("bad psbt signature");
+            signatures.push(signature);
+        }
+        for index in 0..2 {
+            let tap_key_sig = psbt.inputs[index].tap_key_sig.as_ref().unwrap();
+            assert_eq!(tap_key_sig.to_vec(), signatures[index].to_bytes());
+            assert_eq!(tap_key_sig.hash_ty, SchnorrSighashType::Default);
+        }
+        assert!(psbt.inputs[2].tap_key_sig.is_none());
+
+        // a job not matching the psbt and a signature of another input are refused
+        let mut job = jobs[1].clone();
+        job.message = BigInt::from(1);
+        assert_eq!(
+            add_taproot_signature(&mut psbt, &job, &signatures[1]),
+            Err(PsbtError::SighashMismatch)
+        );
+        assert_eq!(
+            add_taproot_signature(&mut psbt, &jobs[1], &signatures[0]),
+            Err(PsbtError::SignatureInvalid)
+        );
+        let eph_keygen_party1 = MasterKey1::sign_first_message();
+        let eph_keygen_party2 = MasterKey2::sign_first_message();
+        assert_eq!(
+            party_two_master_key
+                .psbt_sign_second_message(
+                    &psbt,
+                    &job,
+                    &eph_keygen_party2,
+                    &eph_keygen_party1.first_message
+                )
+                .err(),
+            Some(PsbtError::SighashMismatch)
+        );
+
+        // the internal key of a derivation path with our fingerprint has to be our child key
+        psbt.inputs[1].tap_internal_key = Some(internal_key(5));
+        psbt.inputs[1].tap_key_origins.insert(
+            internal_key(5),
+            (
+                vec![],
+                (
+                    fingerprint,
+                    DerivationPath::from(vec![ChildNumber::from(6)]),
+                ),
+            ),
+        );
+        assert_eq!(
+            party_one_master_key.psbt_sign_jobs(&psbt),
+            Err(PsbtError::PublicKeyMismatch)
+        );
+    }
+
+    pub fn test_key_gen_with_chain_code() -> (MasterKey1, MasterKey2) {
+        let keygen_party1 = party1::KeyGen::first_message();
+        let keygen_party2 = party2::KeyGen::first_message();
+        let (hash_e1, keygen_party1_second_message) =
+            keygen_party1.second_message(&keygen_party2.first_message);
+        let (hash_e2, keygen_party2_second_message) =
+            keygen_party2.second_message(&keygen_party1.first_message);
+        keygen_party1
+            .third_message(
+                &keygen_party2.first_message,
+                &keygen_party2_second_message,
+                &hash_e1.e,
+            )
+            .expect("bad key proof");
+        keygen_party2
+            .third_message(
+                &keygen_party1.first_message,
+                &keygen_party1_second_message,
+                &hash_e2.e,
+            )
+            .expect("bad key proof");
+
+        let (cc_party_one_first_message, cc_comm_witness, cc_ec_key_pair1) =
+            ChainCode1::chain_code_first_message();
+        let (cc_party_two_first_message, cc_ec_key_pair2) = ChainCode2::chain_code_first_message();
+        let cc_party_one_second_message = ChainCode1::chain_code_second_message(
+            cc_comm_witness,
+            &cc_party_two_first_message.d_log_proof,
+        );
+        ChainCode2::chain_code_second_message(
+            &cc_party_one_first_message,
+            &cc_party_one_second_message,
+        )
+        .expect("bad chain code proof");
+        let party1_cc = ChainCode1::compute_chain_code(
+            &cc_ec_key_pair1,
+            &cc_party_two_first_message.public_share,
+        );
+        let party2_cc = ChainCode2::compute_chain_code(
+            &cc_ec_key_pair2,
+            &cc_party_one_second_message.comm_witness.public_share,
+        );
+        (
+            MasterKey1::set_master_key(&party1_cc, &keygen_party1, &keygen_party2.first_message),
+            MasterKey2::set_master_key(&party2_cc, &keygen_party2, &keygen_party1.first_message),
+        )
+    }
+
+    pub fn test_vrf_eval(
+        party_one_master_key: &MasterKey1,
+        party_two_master_key: &MasterKey2,
+        alpha: &[u8],
+    ) -> (VrfProof, Vec<u8>) {
+        let eph_party1 = party_one_master_key.vrf_first_message(alpha).unwrap();
+        let eph_party2 = party_two_master_key.vrf_first_message(alpha).unwrap();
+        let party1_second_message = party_one_master_key
+            .vrf_second_message(&eph_party1, &eph_party2.first_message)
+            .expect("bad vrf share");
+        let party2_second_message = party_two_master_key
+            .vrf_second_message(
+                &eph_party2,
+                &eph_party1.first_message,
+                &party1_second_message,
+            )
+            .expect("bad vrf share");
+        let (proof, beta) = party_one_master_key
+            .vrf_third_message(
+                &eph_party1,
+                &eph_party2.first_message,
+                &party1_second_message,
+                &party2_second_message,
+            )
+            .expect("bad vrf response");
+        let (proof_party2, beta_party2) = party_two_master_key
+            .vrf_third_message(&eph_party2, &party2_second_message, &party1_second_message)
+            .expect("bad vrf response");
+        assert_eq!(proof, proof_party2);
+        assert_eq!(beta, beta_party2);
+        (proof, beta)
+    }
+
+    pub fn test_sign(party_one_master_key: &MasterKey1, party_two_master_key: &MasterKey2) {
+        test_sign_message(
+            party_one_master_key,
+            party_two_master_key,
+            &BigInt::from(1234),
+        );
+    }
+
+    pub fn test_sign_message(
+        party_one_master_key: &MasterKey1,
+        party_two_master_key: &MasterKey2,
+        message: &BigInt,
+    ) -> Signature {
+        let eph_keygen_party1 = MasterKey1::sign_first_message();
+        let eph_keygen_party2 = MasterKey2::sign_first_message();
+        let (sign_helper_party1, sign_party1_message2) = party_one_master_key.sign_second_message(
+            &eph_keygen_party1,
+            &eph_keygen_party2.first_message,
+            message,
+        );
+        let (sign_helper_party2, sign_party2_message2) = party_two_master_key.sign_second_message(
+            &eph_keygen_party2,
+            &eph_keygen_party1.first_message,
+            message,
+        );
+        party_two_master_key
+            .signature(
+                &sign_party2_message2,
+                &sign_party1_message2,
+                &sign_helper_party2,
+            )
+            .expect("bad signing");
+        party_one_master_key
+            .signature(
+                &sign_party1_message2,
+                &sign_party2_message2,
+                &sign_helper_party1,
+            )
+            .expect("bad signing")
+    }
 }
@@ -0,0 +1,83 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// what the parties are asked to sign: raw bytes with the hash to apply, or a digest hashed by
+// the caller. Both parties compute the message to sign from the same request instead of
+// reducing a digest on their own.
+
+use curv::BigInt;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use SignRequestError::{self, DigestLength, EmptyTag};
+
+const DIGEST_LENGTH: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum HashType {
+    Sha256,
+    // bitcoin transactions and messages
+    DoubleSha256,
+    // ethereum, the original keccak padding and not sha3
+    Keccak256,
+    // bip340: sha256(sha256(tag) || sha256(tag) || data)
+    Tagged(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum SignRequest {
+    Raw { data: Vec<u8>, hash: HashType },
+    Digest(Vec<u8>),
+}
+
+impl SignRequest {
+    pub fn validate(&self) -> Result<(), SignRequestError> {
+        match *self {
+            SignRequest::Raw {
+                hash: HashType::Tagged(ref tag),
+                ..
+            } if tag.is_empty() => Err(EmptyTag),
+            SignRequest::Digest(ref digest) if digest.len() != DIGEST_LENGTH => Err(DigestLength),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn digest(&self) -> Result<Vec<u8>, SignRequestError> {
+        self.validate()?;
+        let digest = match *self {
+            SignRequest::Raw { ref data, ref hash } => match *hash {
+                HashType::Sha256 => Sha256::digest(data).to_vec(),
+                HashType::DoubleSha256 => Sha256::digest(&Sha256::digest(data)).to_vec(),
+                HashType::Keccak256 => Keccak256::digest(data).to_vec(),
+                HashType::Tagged(ref tag) => {
+                    let tag_hash = Sha256::digest(tag.as_bytes());
+                    let mut hasher = Sha256::new();
+                    hasher.input(&tag_hash);
+                    hasher.input(&tag_hash);
+                    hasher.input(data);
+                    hasher.result().to_vec()
+                }
+            },
+            SignRequest::Digest(ref digest) => digest.clone(),
+        };
+        Ok(digest)
+    }
+
+    // the digest as the message of the signing functions, always below 2^256. The ecdsa
+    // signing reduces it modulo the curve order as the standard does.
+    pub fn message(&self) -> Result<BigInt, SignRequestError> {
+        Ok(BigInt::from(&self.digest()?[..]))
+    }
+}
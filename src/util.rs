@@ -0,0 +1,36 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// helpers shared by the protocol modules
+
+use curv::arithmetic::traits::Converter;
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use curv::BigInt;
+use sha2::{Digest, Sha256};
+
+// sec1 compressed encoding of a point
+pub fn compressed(point: &GE) -> Vec<u8> {
+    BigInt::to_bytes(&point.bytes_compressed_to_big_int())
+}
+
+// every value goes in with a 4 byte big endian length, so no two lists of values hash the same.
+// Points go in as their compressed encoding.
+pub fn hash_length_prefixed(hasher: &mut Sha256, value: &BigInt) {
+    let bytes = BigInt::to_bytes(value);
+    hasher.input(&(bytes.len() as u32).to_be_bytes());
+    hasher.input(&bytes);
+}
@@ -0,0 +1,67 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// checks shared by the validate methods of the master keys, all on public data only
+
+use curv::arithmetic::traits::{Converter, Modulo};
+use curv::arithmetic::BasicOps;
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use curv::BigInt;
+use paillier::EncryptionKey;
+use ValidationError::{self, CKeyOutOfRange, ChainCodeMalformed, PaillierKeySize, PointNotOnCurve};
+
+// y^2 = x^3 + 7 over the field of secp256k1
+pub fn check_point(point: &GE) -> Result<(), ValidationError> {
+    let p = BigInt::from(2).pow(256) - BigInt::from(2).pow(32) - BigInt::from(977);
+    let (x, y) = match (point.x_coor(), point.y_coor()) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return Err(PointNotOnCurve),
+    };
+    let lhs = BigInt::mod_mul(&y, &y, &p);
+    let rhs = BigInt::mod_add(
+        &BigInt::mod_pow(&x, &BigInt::from(3), &p),
+        &BigInt::from(7),
+        &p,
+    );
+    if x < p && y < p && lhs == rhs {
+        Ok(())
+    } else {
+        Err(PointNotOnCurve)
+    }
+}
+
+// chain codes are kept as the compressed encoding of a point, see hd_key
+pub fn check_chain_code(chain_code: &BigInt) -> Result<(), ValidationError> {
+    let bytes = BigInt::to_bytes(chain_code);
+    if bytes.len() != 33 {
+        return Err(ChainCodeMalformed);
+    }
+    let point = GE::from_bytes(&bytes[1..33]).or(Err(ChainCodeMalformed))?;
+    check_point(&point).or(Err(ChainCodeMalformed))
+}
+
+// paillier keys of the two party ecdsa are the product of two 1024 bit primes, so n has at
+// least 2047 bits
+pub fn check_paillier(ek: &EncryptionKey, c_key: &BigInt) -> Result<(), ValidationError> {
+    if ek.n < BigInt::from(2).pow(2046) || ek.nn != &ek.n * &ek.n {
+        return Err(PaillierKeySize);
+    }
+    if c_key <= &BigInt::from(0) || c_key >= &ek.nn {
+        return Err(CKeyOutOfRange);
+    }
+    Ok(())
+}
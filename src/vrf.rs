@@ -0,0 +1,179 @@
+/*
+    KMS
+
+    Copyright 2018 by Kzen Networks
+
+    This file is part of KMS library
+    (https://github.com/KZen-networks/kms)
+
+    Cryptography utilities is free software: you can redistribute
+    it and/or modify it under the terms of the GNU General Public
+    License as published by the Free Software Foundation, either
+    version 3 of the License, or (at your option) any later version.
+
+    @license GPL-3.0+ <https://github.com/KZen-networks/kms/blob/master/LICENSE>
+*/
+
+// ecvrf over secp256k1 laid out as rfc 9381 with try and increment hash to curve and sha256
+// (the ECVRF-SECP256K1-SHA256-TAI suite). The two party evaluation runs the dleq proof of
+// gamma = x * H jointly: each party proves its partial gamma and answers the joint challenge
+// with its own nonce, the responses add up to the proof of the joint key. Party one commits to
+// its share before it sees the one of party two, so neither nonce depends on the other, also
+// across concurrent sessions.
+
+use curv::arithmetic::traits::Samplable;
+use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
+use curv::cryptographic_primitives::commitments::traits::Commitment;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::BigInt;
+use ecdh::PartialEcdh;
+use sha2::{Digest, Sha256};
+use util::compressed;
+use VrfError::{self, CommitmentInvalid, HashToCurve, PartialInvalid, ProofInvalid};
+
+const SUITE: u8 = 0xfe;
+const C_LEN: usize = 16;
+const BLIND_BITS: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VrfProof {
+    pub gamma: GE,
+    pub c: FE,
+    pub s: FE,
+}
+
+// x_i * H with its proof and the nonce points k_i * G, k_i * H of the joint proof
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VrfShare {
+    pub partial_gamma: PartialEcdh,
+    pub u: GE,
+    pub v: GE,
+}
+
+pub fn hash_to_curve(y: &GE, alpha: &[u8]) -> Result<GE, VrfError> {
+    let y_bytes = compressed(y);
+    (0..=255u8)
+        .filter_map(|ctr| {
+            let mut hasher = Sha256::new();
+            hasher.input(&[SUITE, 0x01]);
+            hasher.input(&y_bytes[..]);
+            hasher.input(alpha);
+            hasher.input(&[ctr, 0x00]);
+            // x coordinate of the point with even y
+            GE::from_bytes(&hasher.result()).ok()
+        })
+        .next()
+        .ok_or(HashToCurve)
+}
+
+fn challenge(points: &[&GE]) -> FE {
+    let mut hasher = Sha256::new();
+    hasher.input(&[SUITE, 0x02]);
+    for point in points {
+        hasher.input(&compressed(point)[..]);
+    }
+    hasher.input(&[0x00]);
+    ECScalar::from(&BigInt::from(&hasher.result()[..C_LEN]))
+}
+
+// the challenge only depends on the sums, so both parties compute it the same way
+pub fn joint_challenge(y: &GE, h: &GE, local_share: &VrfShare, received_share: &VrfShare) -> FE {
+    challenge(&[
+        y,
+        h,
+        &(&local_share.partial_gamma.point + &received_share.partial_gamma.point),
+        &(&local_share.u + &received_share.u),
+        &(&local_share.v + &received_share.v),
+    ])
+}
+
+pub fn proof_to_hash(proof: &VrfProof) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.input(&[SUITE, 0x03]);
+    hasher.input(&compressed(&proof.gamma)[..]);
+    hasher.input(&[0x00]);
+    hasher.result().to_vec()
+}
+
+// U = s * G - c * Y and V = s * H - c * gamma must hash back to c
+pub fn verify_with_point(y: &GE, h: &GE, proof: &VrfProof) -> Result<Vec<u8>, VrfError> {
+    let g: GE = ECPoint::generator();
+    let u = (&g * &proof.s).sub_point(&(y * &proof.c).get_element());
+    let v = (h * &proof.s).sub_point(&(&proof.gamma * &proof.c).get_element());
+    if challenge(&[y, h, &proof.gamma, &u, &v]) == proof.c {
+        Ok(proof_to_hash(proof))
+    } else {
+        Err(ProofInvalid)
+    }
+}
+
+// the vrf output beta of alpha under the joint key y
+pub fn verify(y: &GE, alpha: &[u8], proof: &VrfProof) -> Result<Vec<u8>, VrfError> {
+    verify_with_point(y, &hash_to_curve(y, alpha)?, proof)
+}
+
+impl VrfShare {
+    pub fn create(secret_share: &FE, h: &GE) -> (VrfShare, FE) {
+        let g: GE = ECPoint::generator();
+        let k: FE = ECScalar::new_random();
+        (
+            VrfShare {
+                partial_gamma: PartialEcdh::compute(secret_share, h),
+                u: &g * &k,
+                v: h * &k,
+            },
+            k,
+        )
+    }
+
+    // commitment to the points of the share and its blinding
+    pub fn commit(&self) -> (BigInt, BigInt) {
+        let blind = BigInt::sample(BLIND_BITS);
+        let com =
+            HashCommitment::create_commitment_with_user_defined_randomness(&self.hash(), &blind);
+        (com, blind)
+    }
+
+    pub fn verify_commitment(&self, com: &BigInt, blind: &BigInt) -> Result<(), VrfError> {
+        if &HashCommitment::create_commitment_with_user_defined_randomness(&self.hash(), blind)
+            == com
+        {
+            Ok(())
+        } else {
+            Err(CommitmentInvalid)
+        }
+    }
+
+    fn hash(&self) -> BigInt {
+        let mut hasher = Sha256::new();
+        for point in &[&self.partial_gamma.point, &self.u, &self.v] {
+            hasher.input(&compressed(point)[..]);
+        }
+        BigInt::from(&hasher.result()[..])
+    }
+
+    pub fn verify(&self, public_share: &GE, h: &GE) -> Result<(), VrfError> {
+        self.partial_gamma
+            .verify(public_share, h)
+            .or(Err(PartialInvalid))
+    }
+
+    // s_i = k_i + c * x_i against the nonce points and the partial gamma
+    pub fn verify_response(
+        &self,
+        public_share: &GE,
+        h: &GE,
+        c: &FE,
+        s: &FE,
+    ) -> Result<(), VrfError> {
+        let g: GE = ECPoint::generator();
+        if &g * s == &self.u + &(public_share * c)
+            && h * s == &self.v + &(&self.partial_gamma.point * c)
+        {
+            Ok(())
+        } else {
+            Err(PartialInvalid)
+        }
+    }
+}